@@ -0,0 +1,43 @@
+// Paywall gateway in front of an upstream server, configured by a JSON file
+// The config path is the first argument, or PIPEGATE_PROXY_CONFIG, proxy.json by default
+
+use std::{env, fs};
+
+use pipegate::proxy::{Proxy, ProxyConfig};
+
+pub const DEFAULT_CONFIG_PATH: &str = "proxy.json";
+
+#[tokio::main]
+pub async fn main() {
+    // Log level is read from RUST_LOG, e.g. `RUST_LOG=pipegate=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    let path = env::args()
+        .nth(1)
+        .or_else(|| env::var("PIPEGATE_PROXY_CONFIG").ok())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+    let config = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read config {}: {}", path, e));
+    let config: ProxyConfig =
+        serde_json::from_str(&config).unwrap_or_else(|e| panic!("Invalid config {}: {}", path, e));
+
+    let proxy = Proxy::from_config(&config)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to build proxy: {}", e));
+    for route in &config.routes {
+        tracing::info!(path = %route.path, paid = !route.accepts.is_empty(), "Serving route");
+    }
+
+    let listener = tokio::net::TcpListener::bind(&config.listen).await.unwrap();
+    tracing::info!(
+        "Proxy listening on: http://{} -> {}",
+        config.listen,
+        config.upstream
+    );
+    axum::serve(listener, proxy.router()).await.unwrap();
+}
@@ -0,0 +1,374 @@
+// Client side of the payment channels, the counterpart of `payment_channel::channel::ChannelState`
+// Opens channels through the factory, signs vouchers against the local balance and nonce, and manages the funds on-chain
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use alloy::{
+    network::EthereumWallet,
+    primitives::{Address, FixedBytes, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionReceipt,
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol,
+    transports::http::{reqwest::Url, Client, Http},
+};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{debug, info};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        payment_channel::{
+            channel::PaymentChannelABI,
+            factory::ChannelFactoryABI,
+            types::{PaymentChannel, SignedRequest},
+            utils::create_channel_message,
+        },
+        rpc::get_rpc_pool,
+        utils::{get_current_time, get_token_decimals},
+    },
+};
+
+sol! {
+    #[sol(rpc)]
+    contract ERC20 {
+        function approve(address spender, uint256 amount) external returns (bool);
+    }
+}
+
+/// Channel as the sender knows it, `balance` is what's left to sign against and `nonce` the nonce of the next voucher
+#[derive(Clone, Debug)]
+pub struct ClientChannel {
+    pub channel: PaymentChannel,
+    pub token: Address,
+    pub decimals: u8,
+}
+
+impl ClientChannel {
+    /// Apply the channel returned by the server after a request, or with a 402 refusing a voucher
+    pub fn apply_returned(&mut self, returned: &PaymentChannel) {
+        self.channel.balance = returned.balance;
+        self.channel.nonce = returned.nonce + U256::from(1);
+    }
+}
+
+struct TrackedChannel {
+    recipient: Address,
+    state: Arc<Mutex<ClientChannel>>, // held while a voucher is in flight
+}
+
+#[derive(Clone)]
+pub struct ChannelManager {
+    signer: PrivateKeySigner,
+    rpc_url: Url,
+    factory_address: Address,
+    channels: Arc<RwLock<HashMap<U256, TrackedChannel>>>, // channels of the signer, by channel id
+}
+
+impl ChannelManager {
+    pub fn new(rpc_url: Url, signer: PrivateKeySigner, factory_address: Address) -> Self {
+        Self {
+            signer,
+            rpc_url,
+            factory_address,
+            channels: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Address of the signer, the sender of every channel managed
+    pub fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// Track a channel opened elsewhere, `channel.nonce` being the nonce of the next voucher
+    pub fn add_channel(&self, channel: PaymentChannel, token: Address, decimals: u8) {
+        self.channels.write().unwrap().insert(
+            channel.channel_id,
+            TrackedChannel {
+                recipient: channel.recipient,
+                state: Arc::new(Mutex::new(ClientChannel {
+                    channel,
+                    token,
+                    decimals,
+                })),
+            },
+        );
+    }
+
+    /// Track a channel of the signer as found on-chain, starting from the last nonce settled there
+    /// Vouchers the server accepted since are off-chain, the client resyncs on the channel returned when it refuses one
+    pub async fn load_channel(&self, channel_id: U256) -> Result<PaymentChannel, AuthError> {
+        let pool = get_rpc_pool(self.rpc_url.as_str())?;
+        let factory_address = self.factory_address;
+
+        let info = pool
+            .call(|provider| async move {
+                ChannelFactoryABI::new(factory_address, provider)
+                    .getChannelInfo(channel_id)
+                    .call()
+                    .await
+            })
+            .await
+            .map_err(|e| {
+                AuthError::ContractError(format!("Failed to fetch channel info: {}", e))
+            })?;
+
+        if !info.exists || info.sender != self.address() {
+            return Err(AuthError::ChannelNotFound);
+        }
+
+        let channel_address = info.channelAddress;
+        let token = pool
+            .call(|provider| async move {
+                PaymentChannelABI::new(channel_address, provider)
+                    .token()
+                    .call()
+                    .await
+            })
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to fetch token: {}", e)))?
+            ._0;
+        let decimals = get_token_decimals(self.rpc_url.as_str(), &token)
+            .await
+            .map_err(AuthError::ContractError)?;
+
+        let channel = PaymentChannel {
+            address: channel_address,
+            sender: info.sender,
+            recipient: info.recipient,
+            balance: info.balance,
+            nonce: info.lastNonce,
+            expiration: info.expiration,
+            channel_id,
+        };
+        self.add_channel(channel.clone(), token, decimals);
+        Ok(channel)
+    }
+
+    pub async fn get_channel(&self, channel_id: U256) -> Option<ClientChannel> {
+        let state = self.state(channel_id)?;
+        let channel = state.lock().await.clone();
+        Some(channel)
+    }
+
+    /// Channel opened to `recipient`, if any
+    pub fn channel_to(&self, recipient: Address) -> Option<U256> {
+        self.channels
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, tracked)| tracked.recipient == recipient)
+            .map(|(channel_id, _)| *channel_id)
+    }
+
+    /// Lock a channel while a voucher is in flight, so the next voucher is signed once the server returned the channel
+    pub async fn lock_channel(&self, channel_id: U256) -> Option<OwnedMutexGuard<ClientChannel>> {
+        let state = self.state(channel_id)?;
+        Some(state.lock_owned().await)
+    }
+
+    /// Voucher paying `amount` from the channel, signed over `body`, empty unless the server binds vouchers to requests
+    pub fn sign_voucher(
+        &self,
+        channel: &ClientChannel,
+        amount: U256,
+        body: &[u8],
+    ) -> Result<SignedRequest, AuthError> {
+        let mut voucher = channel.channel.clone();
+        voucher.balance = voucher
+            .balance
+            .checked_sub(amount)
+            .ok_or(AuthError::InsufficientBalance)?;
+
+        let message =
+            create_channel_message(voucher.channel_id, voucher.balance, voucher.nonce, body);
+        let signature = self
+            .signer
+            .sign_message_sync(&message)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        Ok(SignedRequest {
+            message,
+            signature,
+            payment_channel: voucher,
+            payment_amount: amount,
+            body_bytes: body.to_vec(),
+            timestamp: get_current_time(),
+        })
+    }
+
+    /// Open a channel to `recipient` through the factory, approving the deposit first
+    pub async fn open_channel(
+        &self,
+        recipient: Address,
+        duration_sec: u64,
+        token: Address,
+        amount: U256,
+    ) -> Result<PaymentChannel, AuthError> {
+        self.approve(token, self.factory_address, amount).await?;
+
+        let receipt = ChannelFactoryABI::new(self.factory_address, self.wallet_provider())
+            .createChannel(recipient, U256::from(duration_sec), token, amount)
+            .send()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to create channel: {}", e)))?
+            .get_receipt()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to create channel: {}", e)))?;
+        check_receipt(&receipt)?;
+
+        let created = receipt
+            .inner
+            .logs()
+            .iter()
+            .filter(|log| log.address() == self.factory_address)
+            .find_map(|log| log.log_decode::<ChannelFactoryABI::ChannelCreated>().ok())
+            .ok_or_else(|| {
+                AuthError::ContractError("Channel creation event not found".to_string())
+            })?
+            .inner
+            .data;
+
+        let decimals = get_token_decimals(self.rpc_url.as_str(), &token)
+            .await
+            .map_err(AuthError::ContractError)?;
+
+        let channel = PaymentChannel {
+            address: created.channelAddress,
+            sender: created.sender,
+            recipient: created.recipient,
+            balance: created.amount,
+            nonce: U256::ZERO,
+            expiration: created.timestamp + created.duration,
+            channel_id: created.channelId,
+        };
+        info!(channel_id = %channel.channel_id, %recipient, "Channel opened");
+
+        self.add_channel(channel.clone(), token, decimals);
+        Ok(channel)
+    }
+
+    /// Top up the channel, the server picks the deposit up from the `DepositMade` event
+    pub async fn deposit(
+        &self,
+        channel_id: U256,
+        amount: U256,
+    ) -> Result<FixedBytes<32>, AuthError> {
+        let state = self.state(channel_id).ok_or(AuthError::ChannelNotFound)?;
+        let mut channel = state.lock().await;
+
+        self.approve(channel.token, channel.channel.address, amount)
+            .await?;
+
+        let receipt = PaymentChannelABI::new(channel.channel.address, self.wallet_provider())
+            .deposit(amount)
+            .send()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to deposit: {}", e)))?
+            .get_receipt()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to deposit: {}", e)))?;
+        check_receipt(&receipt)?;
+
+        channel.channel.balance += amount;
+        info!(%channel_id, %amount, "Deposit made on channel");
+        Ok(receipt.transaction_hash)
+    }
+
+    /// Push the expiration of the channel back to `new_expiration`
+    pub async fn extend(
+        &self,
+        channel_id: U256,
+        new_expiration: U256,
+    ) -> Result<FixedBytes<32>, AuthError> {
+        let state = self.state(channel_id).ok_or(AuthError::ChannelNotFound)?;
+        let mut channel = state.lock().await;
+
+        let receipt = PaymentChannelABI::new(channel.channel.address, self.wallet_provider())
+            .extend(new_expiration)
+            .send()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to extend: {}", e)))?
+            .get_receipt()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to extend: {}", e)))?;
+        check_receipt(&receipt)?;
+
+        channel.channel.expiration = new_expiration;
+        info!(%channel_id, expiration = %new_expiration, "Channel extended");
+        Ok(receipt.transaction_hash)
+    }
+
+    /// Reclaim the deposit of an expired channel the recipient didn't close, the channel is no longer tracked
+    pub async fn claim_timeout(&self, channel_id: U256) -> Result<FixedBytes<32>, AuthError> {
+        let state = self.state(channel_id).ok_or(AuthError::ChannelNotFound)?;
+        let channel = state.lock().await;
+
+        if channel.channel.expiration > U256::from(get_current_time()) {
+            return Err(AuthError::InvalidChannel(
+                "Channel has not expired yet".to_string(),
+            ));
+        }
+
+        let receipt = PaymentChannelABI::new(channel.channel.address, self.wallet_provider())
+            .claimTimeout()
+            .send()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to claim timeout: {}", e)))?
+            .get_receipt()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to claim timeout: {}", e)))?;
+        check_receipt(&receipt)?;
+
+        self.channels.write().unwrap().remove(&channel_id);
+        info!(%channel_id, "Channel timeout claimed");
+        Ok(receipt.transaction_hash)
+    }
+
+    fn state(&self, channel_id: U256) -> Option<Arc<Mutex<ClientChannel>>> {
+        let channels = self.channels.read().unwrap();
+        channels
+            .get(&channel_id)
+            .map(|tracked| tracked.state.clone())
+    }
+
+    fn wallet_provider(&self) -> impl Provider<Http<Client>> {
+        ProviderBuilder::new()
+            .with_recommended_fillers()
+            .wallet(EthereumWallet::from(self.signer.clone()))
+            .on_http(self.rpc_url.clone())
+    }
+
+    async fn approve(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: U256,
+    ) -> Result<(), AuthError> {
+        debug!(%token, %spender, %amount, "Approving token");
+
+        let receipt = ERC20::new(token, self.wallet_provider())
+            .approve(spender, amount)
+            .send()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to approve: {}", e)))?
+            .get_receipt()
+            .await
+            .map_err(|e| AuthError::ContractError(format!("Failed to approve: {}", e)))?;
+        check_receipt(&receipt)
+    }
+}
+
+fn check_receipt(receipt: &TransactionReceipt) -> Result<(), AuthError> {
+    if receipt.status() {
+        Ok(())
+    } else {
+        Err(AuthError::ContractError(format!(
+            "Transaction {} reverted",
+            receipt.transaction_hash
+        )))
+    }
+}
@@ -0,0 +1,11 @@
+// Client side of the payment schemes, behind the `client` feature
+// Pays for x402 protected APIs from Rust services instead of hand-crafting `X-Payment` headers
+
+pub mod channel;
+pub mod policy;
+pub mod x402;
+
+pub use channel::{ChannelManager, ClientChannel};
+pub use policy::{SpendLedger, SpendPolicy};
+pub use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+pub use x402::X402Middleware;
@@ -0,0 +1,305 @@
+// Spend limits checked against each accepted requirement of a 402 before the client signs a payment
+// Channel vouchers are counted against per-host daily budgets, kept in memory or in a JSON file across restarts
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use alloy::primitives::{
+    utils::{format_units, parse_units},
+    Address, U256,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::warn;
+
+use crate::{
+    error::AuthError,
+    middleware::{types::PaymentRequiredAccept, utils::get_current_time, Scheme},
+};
+
+pub const SPEND_DECIMALS: u8 = 18; // precision amounts are compared at, in token units
+pub const SPEND_DAY_SEC: u64 = 86400; // budgets reset at 00:00 UTC
+
+// Limit as configured, with its value at `SPEND_DECIMALS`
+#[derive(Clone, Debug)]
+struct Limit {
+    amount: String,
+    value: U256,
+}
+
+impl Limit {
+    fn parse(amount: &str) -> Result<Self, AuthError> {
+        Ok(Self {
+            amount: amount.to_string(),
+            value: parse_amount(amount)?,
+        })
+    }
+}
+
+/// Limits on what the client pays, amounts are in token units like the `amount` of a 402
+/// Price limits apply to every scheme but streams, whose amount is a rate, budgets to channel vouchers only
+/// since other schemes reuse a payment already made
+#[derive(Clone, Debug, Default)]
+pub struct SpendPolicy {
+    max_price: Option<Limit>,             // per request
+    daily_budget: Option<Limit>,          // per host, unless set for the host
+    host_budgets: HashMap<String, Limit>, // per host, by host
+    allowed_tokens: Option<HashSet<Address>>,
+    allowed_networks: Option<HashSet<String>>,
+    preference: Vec<Scheme>, // schemes tried first, in order, before the server order
+    ledger: SpendLedger,
+}
+
+impl SpendPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse requests priced above `amount`
+    pub fn with_max_price(mut self, amount: &str) -> Result<Self, AuthError> {
+        self.max_price = Some(Limit::parse(amount)?);
+        Ok(self)
+    }
+
+    /// Spend at most `amount` per host and day, across tokens
+    pub fn with_daily_budget(mut self, amount: &str) -> Result<Self, AuthError> {
+        self.daily_budget = Some(Limit::parse(amount)?);
+        Ok(self)
+    }
+
+    /// Daily budget of `host`, replacing the default one, `host` includes the port if not the default one
+    pub fn with_host_budget(mut self, host: &str, amount: &str) -> Result<Self, AuthError> {
+        self.host_budgets
+            .insert(host.to_string(), Limit::parse(amount)?);
+        Ok(self)
+    }
+
+    /// Only pay with these tokens
+    pub fn with_allowed_tokens(mut self, tokens: impl IntoIterator<Item = Address>) -> Self {
+        self.allowed_tokens = Some(tokens.into_iter().collect());
+        self
+    }
+
+    /// Only pay on these networks, by x402 name (e.g. `base-sepolia`)
+    pub fn with_allowed_networks(
+        mut self,
+        networks: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.allowed_networks = Some(networks.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Try these schemes first, in order, when the server accepts several
+    pub fn with_preference(mut self, preference: Vec<Scheme>) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    /// Track spend in `ledger`, shared with its clones
+    pub fn with_ledger(mut self, ledger: SpendLedger) -> Self {
+        self.ledger = ledger;
+        self
+    }
+
+    pub fn preference(&self) -> &[Scheme] {
+        &self.preference
+    }
+
+    pub fn ledger(&self) -> &SpendLedger {
+        &self.ledger
+    }
+
+    /// Check a requirement of `host` against the policy, without recording anything
+    pub fn check(&self, host: &str, accept: &PaymentRequiredAccept) -> Result<(), AuthError> {
+        if let Some(networks) = &self.allowed_networks {
+            if !networks.contains(&accept.network) {
+                return Err(AuthError::PolicyViolation(format!(
+                    "network {} is not allowed",
+                    accept.network
+                )));
+            }
+        }
+
+        if let Some(tokens) = &self.allowed_tokens {
+            let allowed =
+                Address::from_str(&accept.asset).is_ok_and(|token| tokens.contains(&token));
+            if !allowed {
+                return Err(AuthError::PolicyViolation(format!(
+                    "token {} is not allowed",
+                    accept.asset
+                )));
+            }
+        }
+
+        if Scheme::from_string(&accept.scheme) == Some(Scheme::SuperfluidStreams) {
+            return Ok(());
+        }
+
+        let price = parse_amount(&accept.amount)?;
+        if let Some(max_price) = &self.max_price {
+            if price > max_price.value {
+                return Err(AuthError::PolicyViolation(format!(
+                    "price {} exceeds the maximum of {} per request",
+                    accept.amount, max_price.amount
+                )));
+            }
+        }
+
+        if Scheme::from_string(&accept.scheme) == Some(Scheme::PaymentChannels) {
+            if let Some(budget) = self.budget(host) {
+                let spent = self.ledger.spent_today(host);
+                if spent + price > budget.value {
+                    return Err(budget_exceeded(host, budget, spent));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count `amount` against the budget of `host`, failing if it would be exceeded
+    pub fn reserve(&self, host: &str, amount: &str) -> Result<(), AuthError> {
+        let amount = parse_amount(amount)?;
+        let budget = self.budget(host);
+        self.ledger.record(host, amount, budget).map_err(|spent| {
+            budget_exceeded(host, budget.expect("Only refused over a budget"), spent)
+        })
+    }
+
+    /// Give back an amount reserved for a payment the server refused
+    pub fn release(&self, host: &str, amount: &str) {
+        if let Ok(amount) = parse_amount(amount) {
+            self.ledger.refund(host, amount);
+        }
+    }
+
+    fn budget(&self, host: &str) -> Option<&Limit> {
+        self.host_budgets.get(host).or(self.daily_budget.as_ref())
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<U256, AuthError> {
+    parse_units(amount, SPEND_DECIMALS)
+        .map(|value| value.get_absolute())
+        .map_err(|_| AuthError::InvalidRequest(format!("Invalid amount {}", amount)))
+}
+
+fn budget_exceeded(host: &str, budget: &Limit, spent: U256) -> AuthError {
+    let spent = format_units(spent, SPEND_DECIMALS).unwrap_or_default();
+    let spent = match spent.trim_end_matches('0').trim_end_matches('.') {
+        "" => "0",
+        spent => spent,
+    };
+    AuthError::PolicyViolation(format!(
+        "daily budget of {} for {} exceeded, {} spent today",
+        budget.amount, host, spent
+    ))
+}
+
+#[serde_as]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct HostSpend {
+    day: u64, // days since the epoch
+    #[serde_as(as = "DisplayFromStr")]
+    spent: U256, // at `SPEND_DECIMALS`
+}
+
+/// Spend per host for the current day, written to `path` on each change when persistent
+#[derive(Clone, Debug, Default)]
+pub struct SpendLedger {
+    spent: Arc<Mutex<HashMap<String, HostSpend>>>,
+    path: Option<PathBuf>,
+}
+
+impl SpendLedger {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Ledger kept in the JSON file at `path`, created on the first payment if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let path = path.as_ref().to_path_buf();
+        let spent = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| AuthError::InvalidRequest(format!("Invalid spend ledger: {}", e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(AuthError::InvalidRequest(format!(
+                    "Failed to read spend ledger: {}",
+                    e
+                )))
+            }
+        };
+
+        Ok(Self {
+            spent: Arc::new(Mutex::new(spent)),
+            path: Some(path),
+        })
+    }
+
+    /// Spent with `host` today, at `SPEND_DECIMALS`
+    pub fn spent_today(&self, host: &str) -> U256 {
+        let today = get_current_time() / SPEND_DAY_SEC;
+        self.spent
+            .lock()
+            .unwrap()
+            .get(host)
+            .filter(|spend| spend.day == today)
+            .map_or(U256::ZERO, |spend| spend.spent)
+    }
+
+    // Add `amount` unless it goes over `budget`, returning what was already spent otherwise
+    fn record(&self, host: &str, amount: U256, budget: Option<&Limit>) -> Result<(), U256> {
+        let today = get_current_time() / SPEND_DAY_SEC;
+        let mut spent = self.spent.lock().unwrap();
+
+        let spend = spent.entry(host.to_string()).or_insert(HostSpend {
+            day: today,
+            spent: U256::ZERO,
+        });
+        if spend.day != today {
+            *spend = HostSpend {
+                day: today,
+                spent: U256::ZERO,
+            };
+        }
+        if budget.is_some_and(|budget| spend.spent + amount > budget.value) {
+            return Err(spend.spent);
+        }
+        spend.spent += amount;
+
+        self.save(&spent);
+        Ok(())
+    }
+
+    fn refund(&self, host: &str, amount: U256) {
+        let today = get_current_time() / SPEND_DAY_SEC;
+        let mut spent = self.spent.lock().unwrap();
+
+        if let Some(spend) = spent.get_mut(host).filter(|spend| spend.day == today) {
+            spend.spent = spend.spent.saturating_sub(amount);
+            self.save(&spent);
+        }
+    }
+
+    // Written to a temporary file then renamed, so a crash never leaves a truncated ledger
+    fn save(&self, spent: &HashMap<String, HostSpend>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_string(spent)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&tmp, json).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!(error = %e, "Failed to save spend ledger");
+        }
+    }
+}
@@ -0,0 +1,383 @@
+// reqwest middleware answering 402 responses with a payment
+// The request is retried once with an `X-Payment` header for the first accepted scheme we hold a payment for
+// A channel voucher refused for a stale nonce is signed again once the channel is resynced from the refusal
+
+use std::{collections::HashMap, str::FromStr};
+
+use alloy::{
+    hex,
+    primitives::{utils::parse_units, Address, FixedBytes, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+};
+use async_trait::async_trait;
+use reqwest::{header::HeaderValue, Request, Response, StatusCode, Url};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+use task_local_extensions::Extensions;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{
+        channel::{ChannelManager, ClientChannel},
+        policy::SpendPolicy,
+    },
+    error::AuthError,
+    middleware::{
+        one_time_payment::utils::create_tx_message,
+        payment_channel::{types::PaymentChannel, utils::create_request_hash},
+        stream_payment::utils::create_stream_message,
+        types::{
+            ChannelPayload, OneTimePayload, PaymentHeader, PaymentPayload, PaymentRequiredAccept,
+            PaymentRequiredResponse, StreamPayload,
+        },
+        Scheme,
+    },
+};
+
+pub const PAYMENT_HEADER: &str = "X-Payment";
+
+#[derive(Clone)]
+pub struct X402Middleware {
+    signer: PrivateKeySigner,
+    payments: HashMap<String, FixedBytes<32>>, // tx paid with, per one-time, credits or subscription scheme
+    stream: bool,                              // whether the signer streams to the APIs called
+    channels: Option<ChannelManager>, // channels of the signer, used for the APIs they pay to
+    policy: SpendPolicy,              // checked before signing any payment
+}
+
+impl X402Middleware {
+    pub fn new(signer: PrivateKeySigner) -> Self {
+        Self {
+            signer,
+            payments: HashMap::new(),
+            stream: false,
+            channels: None,
+            policy: SpendPolicy::new(),
+        }
+    }
+
+    /// Pay for `scheme` (one-time, credits or subscription) with a transfer already made by the signer
+    pub fn with_payment_tx(mut self, scheme: Scheme, tx_hash: FixedBytes<32>) -> Self {
+        self.payments
+            .insert(scheme.to_string().to_string(), tx_hash);
+        self
+    }
+
+    /// Pay with a Superfluid stream opened by the signer to the recipient
+    pub fn with_stream(mut self) -> Self {
+        self.stream = true;
+        self
+    }
+
+    /// Pay with the channels tracked by `channels`, including the ones opened after this call
+    pub fn with_channels(mut self, channels: ChannelManager) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    /// Try these schemes first, in order, when the server accepts several
+    pub fn with_preference(mut self, preference: Vec<Scheme>) -> Self {
+        self.policy = self.policy.with_preference(preference);
+        self
+    }
+
+    /// Only pay within `policy`, replacing any preference set before
+    pub fn with_policy(mut self, policy: SpendPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // First requirement we can pay within the policy, by preference then in the order the server listed them
+    // When every payable requirement breaks the policy, the first violation is returned
+    fn choose<'a>(
+        &self,
+        host: &str,
+        accepts: &'a [PaymentRequiredAccept],
+    ) -> std::result::Result<&'a PaymentRequiredAccept, AuthError> {
+        let payable = |accept: &&PaymentRequiredAccept| match Scheme::from_string(&accept.scheme) {
+            Some(Scheme::SuperfluidStreams) => self.stream,
+            Some(Scheme::PaymentChannels) => self.channel_for(accept).is_some(),
+            Some(_) => self.payments.contains_key(&accept.scheme),
+            None => false,
+        };
+
+        let preferred = self.policy.preference().iter().flat_map(|scheme| {
+            accepts
+                .iter()
+                .filter(move |accept| accept.scheme == scheme.to_string())
+        });
+
+        let mut violation = None;
+        for accept in preferred.chain(accepts).filter(payable) {
+            match self.policy.check(host, accept) {
+                Ok(()) => return Ok(accept),
+                Err(e) => {
+                    debug!(scheme = %accept.scheme, error = %e, "Payment refused by policy");
+                    violation.get_or_insert(e);
+                }
+            }
+        }
+        Err(violation.unwrap_or(AuthError::SchemeNotAccepted))
+    }
+
+    fn channel_for(&self, accept: &PaymentRequiredAccept) -> Option<(&ChannelManager, U256)> {
+        let channels = self.channels.as_ref()?;
+        let recipient = Address::from_str(&accept.pay_to).ok()?;
+        Some((channels, channels.channel_to(recipient)?))
+    }
+
+    fn sign(&self, message: &[u8]) -> std::result::Result<String, AuthError> {
+        let signature = self
+            .signer
+            .sign_message_sync(message)
+            .map_err(|_| AuthError::InvalidSignature)?;
+        Ok(hex::encode_prefixed(signature.as_bytes()))
+    }
+
+    // Voucher for the next request on the channel, charging the advertised price against the remaining balance
+    fn sign_voucher(
+        channels: &ChannelManager,
+        tracked: &mut ClientChannel,
+        accept: &PaymentRequiredAccept,
+        request: &Request,
+    ) -> std::result::Result<PaymentPayload, AuthError> {
+        // The server sends the channel back when it refused a voucher, resync on it
+        if let Some(known) = accept
+            .extra
+            .as_ref()
+            .and_then(|extra| serde_json::from_value::<PaymentChannel>(extra.clone()).ok())
+            .filter(|known| known.channel_id == tracked.channel.channel_id)
+        {
+            tracked.apply_returned(&known);
+        }
+
+        let price = parse_units(&accept.amount, tracked.decimals)
+            .map_err(|_| AuthError::InvalidRequest(format!("Invalid price {}", accept.amount)))?
+            .get_absolute();
+
+        // Bound vouchers are signed over the request instead of an empty body
+        let bound = accept
+            .extra
+            .as_ref()
+            .is_some_and(|extra| extra.get("requestBinding").is_some());
+        let body = if bound {
+            let body = match request.body() {
+                Some(body) => body.as_bytes().ok_or_else(|| {
+                    AuthError::InvalidRequest(
+                        "Streaming bodies can't be bound to a voucher".to_string(),
+                    )
+                })?,
+                None => &[],
+            };
+            let url = request.url();
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            create_request_hash(request.method().as_str(), &path, body)
+        } else {
+            Vec::new()
+        };
+
+        let voucher = channels.sign_voucher(tracked, price, &body)?;
+
+        Ok(PaymentPayload::Channel(ChannelPayload {
+            signature: hex::encode_prefixed(voucher.signature.as_bytes()),
+            message: hex::encode(&voucher.message),
+            payment_channel: voucher.payment_channel,
+            timestamp: voucher.timestamp,
+        }))
+    }
+
+    fn payment_header(
+        x402_version: u8,
+        accept: &PaymentRequiredAccept,
+        payload: PaymentPayload,
+    ) -> std::result::Result<HeaderValue, AuthError> {
+        let header = PaymentHeader {
+            x402_version: x402_version.into(),
+            network: accept.network.clone(),
+            scheme: accept.scheme.clone(),
+            payload,
+        };
+
+        header
+            .to_json_string()
+            .ok()
+            .and_then(|json| HeaderValue::from_str(&json).ok())
+            .ok_or(AuthError::InternalError)
+    }
+}
+
+#[async_trait]
+impl Middleware for X402Middleware {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        // Requests paid by the caller, and streaming bodies that can't be sent twice, go through untouched
+        let retry = match request.headers().contains_key(PAYMENT_HEADER) {
+            true => None,
+            false => request.try_clone(),
+        };
+
+        let response = next.clone().run(request, extensions).await?;
+        let Some(mut retry) = retry.filter(|_| response.status() == StatusCode::PAYMENT_REQUIRED)
+        else {
+            return Ok(response);
+        };
+
+        let required: PaymentRequiredResponse = response.json().await.map_err(|e| {
+            Error::middleware(AuthError::InvalidRequest(format!(
+                "Invalid 402 response: {}",
+                e
+            )))
+        })?;
+
+        let host = host_of(retry.url());
+        let accept = self
+            .choose(&host, &required.accepts)
+            .map_err(Error::middleware)?;
+        debug!(scheme = %accept.scheme, network = %accept.network, "Paying for request");
+
+        let payload = match Scheme::from_string(&accept.scheme) {
+            Some(Scheme::PaymentChannels) => {
+                let (channels, channel_id) = self
+                    .channel_for(accept)
+                    .ok_or_else(|| Error::middleware(AuthError::ChannelNotFound))?;
+
+                // Held until the response updated the channel, requests on a channel are sent one at a time
+                let mut tracked = channels
+                    .lock_channel(channel_id)
+                    .await
+                    .ok_or_else(|| Error::middleware(AuthError::ChannelNotFound))?;
+
+                // A voucher refused for a stale nonce is signed once more, on the channel the server knows
+                let mut resend = retry.try_clone();
+                loop {
+                    // Counted against the budget before signing, given back if the voucher isn't accepted
+                    self.policy
+                        .reserve(&host, &accept.amount)
+                        .map_err(Error::middleware)?;
+                    let signed_nonce = tracked.channel.nonce;
+                    let header = Self::sign_voucher(channels, &mut tracked, accept, &retry)
+                        .and_then(|payload| {
+                            Self::payment_header(required.x402_version, accept, payload)
+                        });
+                    let header = match header {
+                        Ok(header) => header,
+                        Err(e) => {
+                            self.policy.release(&host, &accept.amount);
+                            return Err(Error::middleware(e));
+                        }
+                    };
+                    retry.headers_mut().insert(PAYMENT_HEADER, header);
+
+                    let response = match next.clone().run(retry, extensions).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            self.policy.release(&host, &accept.amount);
+                            return Err(e);
+                        }
+                    };
+                    if response.status() == StatusCode::PAYMENT_REQUIRED {
+                        self.policy.release(&host, &accept.amount);
+
+                        // The refusal carries the channel as the server knows it, resync on it
+                        let (response, known) = refused_channel(response, channel_id).await?;
+                        let Some(known) = known else {
+                            return Ok(response);
+                        };
+                        tracked.apply_returned(&known);
+                        match resend.take() {
+                            Some(next_retry) if known.nonce >= signed_nonce => {
+                                debug!(%channel_id, nonce = %tracked.channel.nonce, "Channel resynced, paying again");
+                                retry = next_retry;
+                                continue;
+                            }
+                            _ => return Ok(response),
+                        }
+                    }
+
+                    // The channel is returned with the balance left after this request
+                    match response
+                        .headers()
+                        .get(PAYMENT_HEADER)
+                        .and_then(|h| h.to_str().ok())
+                        .map(serde_json::from_str::<PaymentChannel>)
+                    {
+                        Some(Ok(channel)) if channel.channel_id == tracked.channel.channel_id => {
+                            tracked.apply_returned(&channel);
+                        }
+                        Some(_) => warn!("Invalid channel returned in the X-Payment header"),
+                        None => {}
+                    }
+
+                    return Ok(response);
+                }
+            }
+            Some(Scheme::SuperfluidStreams) => {
+                let sender = self.signer.address();
+                PaymentPayload::Stream(StreamPayload {
+                    signature: self
+                        .sign(&create_stream_message(sender))
+                        .map_err(Error::middleware)?,
+                    sender: sender.to_string(),
+                })
+            }
+            _ => {
+                let tx_hash = self.payments[&accept.scheme];
+                PaymentPayload::OneTime(OneTimePayload {
+                    signature: self
+                        .sign(&create_tx_message(tx_hash))
+                        .map_err(Error::middleware)?,
+                    tx_hash: hex::encode_prefixed(tx_hash),
+                })
+            }
+        };
+
+        let header = Self::payment_header(required.x402_version, accept, payload)
+            .map_err(Error::middleware)?;
+        retry.headers_mut().insert(PAYMENT_HEADER, header);
+        next.run(retry, extensions).await
+    }
+}
+
+// Channel returned with a 402 refusing a voucher, the response is rebuilt from the body read
+async fn refused_channel(
+    response: Response,
+    channel_id: U256,
+) -> Result<(Response, Option<PaymentChannel>)> {
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?;
+
+    let known = serde_json::from_slice::<PaymentRequiredResponse>(&body)
+        .ok()
+        .and_then(|required| {
+            required
+                .accepts
+                .into_iter()
+                .filter(|accept| accept.scheme == Scheme::PaymentChannels.to_string())
+                .filter_map(|accept| accept.extra)
+                .find_map(|extra| serde_json::from_value::<PaymentChannel>(extra).ok())
+        })
+        .filter(|known| known.channel_id == channel_id);
+
+    let mut rebuilt = http_02::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.version_mut() = version;
+    *rebuilt.headers_mut() = headers;
+    Ok((Response::from(rebuilt), known))
+}
+
+// Host the budgets are kept for, with the port when not the default one
+fn host_of(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
@@ -3,16 +3,29 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use axum::{
+    http::{header::VARY, HeaderValue},
+    response::Html,
+};
 use serde_json::json;
 use thiserror::Error;
 
 use crate::middleware::one_time_payment::types::{
-    ABS_WINDOW_SEC, MAX_REDEMPTIONS, SESSION_TTL_SEC,
+    ABS_WINDOW_SEC, MAX_REDEMPTIONS, SESSION_TTL_SEC, SUBSCRIPTION_PERIOD_SEC,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use alloy::primitives::{
+    utils::{format_units, parse_units},
+    U256,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::middleware::{
+    exact_payment::utils::cached_token_domain,
     payment_channel::types::PaymentChannel,
+    paywall::{prefers_html, PaywallConfig},
     types::{PaymentRequiredAccept, PaymentRequiredResponse},
 };
 
@@ -36,6 +49,8 @@ pub enum AuthError {
     InvalidChannel(String),
     #[error("Channel not found")]
     ChannelNotFound,
+    #[error("Payment channel closed")]
+    ChannelClosed,
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
     #[error("Contract interaction failed: {0}")]
@@ -60,6 +75,12 @@ pub enum AuthError {
     InvalidSender,
     #[error("Payment scheme not accepted")]
     SchemeNotAccepted,
+    #[error("Subscription expired")]
+    SubscriptionExpired,
+    #[error("Spend policy violated: {0}")]
+    PolicyViolation(String),
+    #[error("Payment rejected by the facilitator: {0}")]
+    FacilitatorRejected(String),
 }
 
 impl From<AuthError> for StatusCode {
@@ -75,6 +96,7 @@ impl From<AuthError> for StatusCode {
             AuthError::InvalidChannel(_) => StatusCode::BAD_REQUEST,
             AuthError::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
             AuthError::ChannelNotFound => StatusCode::NOT_FOUND,
+            AuthError::ChannelClosed => StatusCode::UNAUTHORIZED,
             AuthError::ContractError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::NetworkError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::InvalidConfig => StatusCode::BAD_REQUEST,
@@ -86,6 +108,9 @@ impl From<AuthError> for StatusCode {
             AuthError::InvalidStream(_) => StatusCode::BAD_REQUEST,
             AuthError::InvalidSender => StatusCode::BAD_REQUEST,
             AuthError::SchemeNotAccepted => StatusCode::FORBIDDEN,
+            AuthError::SubscriptionExpired => StatusCode::PAYMENT_REQUIRED,
+            AuthError::PolicyViolation(_) => StatusCode::PAYMENT_REQUIRED,
+            AuthError::FacilitatorRejected(_) => StatusCode::PAYMENT_REQUIRED,
         }
     }
 }
@@ -109,7 +134,79 @@ impl AuthError {
         resource: &str,
         payment_channel: Option<PaymentChannel>,
     ) -> Response {
-        let accepts: Vec<PaymentRequiredAccept> = config
+        self.into_x402_response_with_credits(config, resource, payment_channel, None)
+    }
+
+    /// Same as `into_x402_response`, also reporting the remaining credit balance of the payer when known
+    pub fn into_x402_response_with_credits(
+        self,
+        config: &crate::middleware::types::MiddlewareConfig,
+        resource: &str,
+        payment_channel: Option<PaymentChannel>,
+        credit_balance: Option<U256>,
+    ) -> Response {
+        let accepts = Self::x402_accepts(config, resource, payment_channel, credit_balance, None);
+        self.into_payment_required_response(accepts)
+    }
+
+    /// Same as `into_x402_response_with_credits`, rendering the paywall of the config when `accept` prefers HTML
+    /// `channel_price` is the price of this request set by the channel pricer, in token base units
+    pub fn into_negotiated_x402_response(
+        self,
+        config: &crate::middleware::types::MiddlewareConfig,
+        resource: &str,
+        payment_channel: Option<PaymentChannel>,
+        credit_balance: Option<U256>,
+        channel_price: Option<U256>,
+        accept: Option<&str>,
+    ) -> Response {
+        let accepts = Self::x402_accepts(
+            config,
+            resource,
+            payment_channel,
+            credit_balance,
+            channel_price,
+        );
+        let Some(paywall) = &config.paywall else {
+            return self.into_payment_required_response(accepts);
+        };
+
+        let mut response = if accept.is_some_and(prefers_html) {
+            self.into_paywall_response(accepts, paywall)
+        } else {
+            self.into_payment_required_response(accepts)
+        };
+        // Caches must not serve the page to API clients or the JSON to browsers
+        response
+            .headers_mut()
+            .insert(VARY, HeaderValue::from_static("accept"));
+        response
+    }
+
+    /// 402 with the paywall page, the requirements are embedded for a wallet script
+    pub fn into_paywall_response(
+        self,
+        accepts: Vec<PaymentRequiredAccept>,
+        paywall: &PaywallConfig,
+    ) -> Response {
+        let payment_required = PaymentRequiredResponse {
+            x402_version: 1,
+            accepts,
+            error: self.to_string(),
+        };
+
+        let status = StatusCode::PAYMENT_REQUIRED;
+        (status, Html(paywall.render(&payment_required))).into_response()
+    }
+
+    fn x402_accepts(
+        config: &crate::middleware::types::MiddlewareConfig,
+        resource: &str,
+        payment_channel: Option<PaymentChannel>,
+        credit_balance: Option<U256>,
+        channel_price: Option<U256>,
+    ) -> Vec<PaymentRequiredAccept> {
+        config
             .accepts
             .iter()
             .map(|scheme_config| {
@@ -125,25 +222,70 @@ impl AuthError {
                         }))
                     }
                     crate::middleware::types::Scheme::PaymentChannels => {
-                        Some(serde_json::json!(payment_channel))
+                        let mut extra = serde_json::json!(payment_channel);
+                        // Tell the client vouchers must be signed over the request hash
+                        if let Some(binding) = &config.request_binding {
+                            let binding = serde_json::json!({ "maxBodyBytes": binding.max_body_bytes });
+                            match extra.as_object_mut() {
+                                Some(object) => {
+                                    object.insert("requestBinding".to_string(), binding);
+                                }
+                                None => extra = serde_json::json!({ "requestBinding": binding }),
+                            }
+                        }
+                        Some(extra)
+                    }
+                    crate::middleware::types::Scheme::Subscription => Some(serde_json::json!({
+                        "absWindowSeconds": ABS_WINDOW_SEC,
+                        "periodSeconds": SUBSCRIPTION_PERIOD_SEC
+                    })),
+                    crate::middleware::types::Scheme::Credits => Some(serde_json::json!({
+                        "absWindowSeconds": ABS_WINDOW_SEC,
+                        "balance": credit_balance.map(|balance| balance.to_string())
+                    })),
+                    // EIP-712 domain the authorization is signed in
+                    crate::middleware::types::Scheme::Exact => {
+                        cached_token_domain(scheme_config.chain_id, &scheme_config.token_address)
+                            .map(|(name, version)| {
+                                serde_json::json!({ "name": name, "version": version })
+                            })
+                    }
+                };
+
+                let decimals = scheme_config.decimals.unwrap_or(18);
+                // Streams are priced per month, there's no amount to pay upfront
+                // Channel requests are priced individually when a pricer is set
+                let (amount, max_amount_required) = match (&scheme_config.scheme, channel_price) {
+                    (crate::middleware::types::Scheme::SuperfluidStreams, _) => {
+                        (scheme_config.amount.clone(), None)
                     }
+                    (crate::middleware::types::Scheme::PaymentChannels, Some(price)) => (
+                        format_units(price, decimals).unwrap_or_else(|_| scheme_config.amount.clone()),
+                        Some(price.to_string()),
+                    ),
+                    _ => (
+                        scheme_config.amount.clone(),
+                        parse_units(&scheme_config.amount, decimals)
+                            .ok()
+                            .map(|amount| amount.get_absolute().to_string()),
+                    ),
                 };
 
                 PaymentRequiredAccept {
                     scheme: scheme_config.scheme.to_string().to_string(),
                     network: scheme_config.chain_name.clone(),
-                    amount: scheme_config.amount.clone(),
+                    amount,
+                    max_amount_required,
                     pay_to: scheme_config.recipient.to_string(),
                     asset: scheme_config.token_address.to_string(),
                     resource: resource.to_string(),
                     description: Some(format!("Access to {} resource. Go to docs.pipegate.xyz for more info about these payment scheme", resource)),
+                    mime_type: String::new(),
                     max_timeout_seconds: Some(300), // 5 minutes default
                     extra,
                 }
             })
-            .collect();
-
-        self.into_payment_required_response(accepts)
+            .collect()
     }
 }
 
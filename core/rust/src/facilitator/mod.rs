@@ -0,0 +1,572 @@
+// Payment verification as a standalone service, for backends not written in Rust
+// x402 facilitator style: `/verify` checks a payment against a 402 requirement, `/settle` also records it
+
+pub mod types;
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use alloy::{
+    hex,
+    primitives::{
+        utils::{format_units, parse_units},
+        Address, FixedBytes, U256,
+    },
+    signers::local::PrivateKeySigner,
+};
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Json, Router,
+};
+use tokio::sync::OnceCell;
+use tracing::{debug, info, info_span, Instrument};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        exact_payment::{
+            types::ExactPaymentConfig,
+            utils::get_token_domain,
+            verify::{settle_authorization, verify_authorization},
+        },
+        one_time_payment::{
+            types::OneTimePaymentConfig, utils::create_tx_message, verify::verify_tx,
+        },
+        payment_channel::{
+            factory::get_channel_factory_from_chain_id,
+            types::{PaymentChannel, PaymentChannelConfig, SignedRequest},
+            verify::{verify_and_update_channel, verify_channel},
+        },
+        stream_payment::{
+            types::{StreamsConfig, CFA_V1_FORWARDER_ADDRESS},
+            utils::monthly_flow_rate,
+            verify::verify_stream,
+        },
+        types::{PaymentPayload, PaymentRequiredAccept},
+        utils::{
+            get_chain_id, get_chain_name, get_current_time, get_token_decimals,
+            parse_channel_payload, parse_exact_payload, parse_onetime_payload,
+            parse_stream_payload,
+        },
+        MiddlewareState, Scheme,
+    },
+};
+
+use types::{FacilitatorRequest, SettleResponse, SupportedKind, SupportedResponse, VerifyResponse};
+
+pub const FACILITATOR_X402_VERSION: u8 = 1;
+pub const MAX_PAYEES: usize = 1024; // recipients tracked without `with_recipient`, payments of new ones are refused past it
+
+/// Schemes the facilitator verifies, credits and subscriptions keep balances on the resource server
+/// `exact` payments are only served with a settlement signer
+pub const FACILITATOR_SCHEMES: [Scheme; 4] = [
+    Scheme::OneTimePayments,
+    Scheme::SuperfluidStreams,
+    Scheme::PaymentChannels,
+    Scheme::Exact,
+];
+
+#[derive(Clone, Debug)]
+pub struct FacilitatorNetwork {
+    pub name: String, // x402 network name, e.g. `base-sepolia`
+    pub chain_id: u64,
+    pub rpc_url: String,
+}
+
+// Payments and channels of one recipient on one network, a payment verified for one is never accepted for another
+#[derive(Clone)]
+struct PayeeState {
+    state: MiddlewareState,
+    channel_listener: Arc<OnceCell<()>>, // started on the first channel payment
+}
+
+// What a verified payment is reported with
+struct Verified {
+    payer: Address,
+    tx_hash: Option<FixedBytes<32>>,
+    channel: Option<PaymentChannel>,
+}
+
+#[derive(Clone, Default)]
+pub struct Facilitator {
+    networks: HashMap<String, FacilitatorNetwork>, // by name
+    payees: Arc<tokio::sync::RwLock<HashMap<(String, Address), PayeeState>>>, // by network and recipient
+    recipients: HashSet<Address>, // only recipients served when set
+    decimals: Arc<RwLock<HashMap<(String, Address), u8>>>, // by network and token
+    settlement_signer: Option<PrivateKeySigner>, // executes the transfers of `exact` payments
+}
+
+impl Facilitator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_network(mut self, name: &str, chain_id: u64, rpc_url: &str) -> Self {
+        self.networks.insert(
+            name.to_string(),
+            FacilitatorNetwork {
+                name: name.to_string(),
+                chain_id,
+                rpc_url: rpc_url.to_string(),
+            },
+        );
+        self
+    }
+
+    /// Settle `exact` payments with `signer`, it pays the gas of each transfer
+    pub fn with_settlement_signer(mut self, signer: PrivateKeySigner) -> Self {
+        self.settlement_signer = Some(signer);
+        self
+    }
+
+    /// Only serve payments to `recipient` and the other recipients added, any recipient is served by default
+    pub fn with_recipient(mut self, recipient: Address) -> Self {
+        self.recipients.insert(recipient);
+        self
+    }
+
+    /// Add the network served by `rpc_url`, named after its chain id
+    pub async fn add_network(self, rpc_url: &str) -> Result<Self, AuthError> {
+        let chain_id = get_chain_id(rpc_url).await?;
+        let name = get_chain_name(&chain_id).await?;
+        Ok(self.with_network(&name, chain_id, rpc_url))
+    }
+
+    pub fn networks(&self) -> impl Iterator<Item = &FacilitatorNetwork> {
+        self.networks.values()
+    }
+
+    /// Routes `/verify`, `/settle` and `/supported`
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/verify", post(verify_handler))
+            .route("/settle", post(settle_handler))
+            .route("/supported", get(supported_handler))
+            .with_state(self)
+    }
+
+    pub fn supported(&self) -> SupportedResponse {
+        let mut networks: Vec<&String> = self.networks.keys().collect();
+        networks.sort();
+
+        SupportedResponse {
+            kinds: networks
+                .into_iter()
+                .flat_map(|network| {
+                    self.schemes().map(|scheme| SupportedKind {
+                        x402_version: FACILITATOR_X402_VERSION,
+                        scheme: scheme.to_string().to_string(),
+                        network: network.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Check the payment without recording it, a one-time payment can still be settled and a voucher is not stored
+    pub async fn verify(&self, request: &FacilitatorRequest) -> VerifyResponse {
+        let span = info_span!("verify", scheme = %request.payment_requirements.scheme);
+        match self.process(request, false).instrument(span).await {
+            Ok(verified) => VerifyResponse {
+                is_valid: true,
+                invalid_reason: None,
+                payer: Some(verified.payer),
+                payment_channel: verified.channel,
+            },
+            Err(e) => {
+                debug!(error = %e, "Payment invalid");
+                VerifyResponse {
+                    is_valid: false,
+                    invalid_reason: Some(e.to_string()),
+                    payer: None,
+                    payment_channel: None,
+                }
+            }
+        }
+    }
+
+    /// Verify and record the payment, redeeming a one-time payment or storing the voucher of a channel
+    pub async fn settle(&self, request: &FacilitatorRequest) -> SettleResponse {
+        let span = info_span!("settle", scheme = %request.payment_requirements.scheme);
+        let network = request.payment_requirements.network.clone();
+        match self.process(request, true).instrument(span).await {
+            Ok(verified) => {
+                info!(payer = %verified.payer, %network, "Payment settled");
+                SettleResponse {
+                    success: true,
+                    error_reason: None,
+                    payer: Some(verified.payer),
+                    transaction: verified.tx_hash.map(|tx_hash| tx_hash.to_string()),
+                    network,
+                    payment_channel: verified.channel,
+                }
+            }
+            Err(e) => {
+                debug!(error = %e, "Payment not settled");
+                SettleResponse {
+                    success: false,
+                    error_reason: Some(e.to_string()),
+                    payer: None,
+                    transaction: None,
+                    network,
+                    payment_channel: None,
+                }
+            }
+        }
+    }
+
+    async fn process(
+        &self,
+        request: &FacilitatorRequest,
+        settle: bool,
+    ) -> Result<Verified, AuthError> {
+        let payment = &request.payment_header;
+        let requirements = &request.payment_requirements;
+
+        if payment.scheme != requirements.scheme || payment.network != requirements.network {
+            return Err(AuthError::InvalidHeaders(
+                "Payment scheme or network does not match the requirements".to_string(),
+            ));
+        }
+        if !payment.validate_payload_for_scheme() {
+            return Err(AuthError::InvalidHeaders(format!(
+                "Payload type mismatch: scheme '{}' does not match payload type",
+                payment.scheme
+            )));
+        }
+
+        let scheme = payment
+            .get_scheme_enum()
+            .filter(|scheme| self.schemes().any(|served| served == scheme))
+            .ok_or(AuthError::SchemeNotAccepted)?;
+        let network = self.networks.get(&requirements.network).ok_or_else(|| {
+            AuthError::InvalidRequest(format!("Unsupported network {}", requirements.network))
+        })?;
+        let recipient = Address::from_str(&requirements.pay_to)
+            .map_err(|_| AuthError::InvalidRequest("Invalid payTo address".to_string()))?;
+        if !self.recipients.is_empty() && !self.recipients.contains(&recipient) {
+            return Err(AuthError::InvalidRequest(format!(
+                "Recipient {} not served",
+                recipient
+            )));
+        }
+        let token = Address::from_str(&requirements.asset)
+            .map_err(|_| AuthError::InvalidRequest("Invalid asset address".to_string()))?;
+
+        match (scheme, &payment.payload) {
+            (Scheme::OneTimePayments, PaymentPayload::OneTime(payload)) => {
+                let signed_tx = parse_onetime_payload(payload).await?;
+                let config = OneTimePaymentConfig {
+                    rpc_url: network.rpc_url.clone(),
+                    token_address: token,
+                    recipient,
+                    amount: self.amount(network, token, requirements).await?,
+                    period_ttl_sec: None,
+                };
+                let payee = self.payee(network, recipient).await?;
+                let one_time_payment_state = {
+                    let guard = payee.state.one_time_payment_state.read().await;
+                    guard.as_ref().unwrap().clone()
+                };
+
+                // Concurrent settlements of the same tx wait for the first one
+                let key = format!("one-time:{}", signed_tx.tx_hash);
+                let _inflight = payee.state.verification_cache.lock(&key).await;
+                if let Some(e) = payee.state.verification_cache.failure(&key) {
+                    return Err(e);
+                }
+
+                let payer = match one_time_payment_state.get(signed_tx.tx_hash).await {
+                    Some(existing) => {
+                        let message = create_tx_message(signed_tx.tx_hash);
+                        match signed_tx.signature.recover_address_from_msg(message) {
+                            Ok(recovered) if recovered == existing.sender => {}
+                            _ => return Err(AuthError::InvalidSignature),
+                        }
+                        if !one_time_payment_state
+                            .is_valid_for_redemption_with_period(
+                                signed_tx.tx_hash,
+                                get_current_time(),
+                                None,
+                                None,
+                            )
+                            .await
+                        {
+                            return Err(AuthError::InvalidTransaction(
+                                "Payment session expired or max redemptions reached".to_string(),
+                            ));
+                        }
+                        existing.sender
+                    }
+                    None => {
+                        let (payment, verified) = verify_tx(signed_tx.clone(), config)
+                            .await
+                            .inspect_err(|e| {
+                                payee.state.verification_cache.record_failure(&key, e)
+                            })?;
+                        if !verified {
+                            return Err(AuthError::InvalidTransaction(
+                                "Authentication failed".to_string(),
+                            ));
+                        }
+
+                        let payer = payment.sender;
+                        if settle {
+                            one_time_payment_state.set(signed_tx.tx_hash, payment).await;
+                        }
+                        payer
+                    }
+                };
+
+                if settle {
+                    one_time_payment_state
+                        .increment_redemptions(signed_tx.tx_hash)
+                        .await;
+                }
+
+                Ok(Verified {
+                    payer,
+                    tx_hash: Some(signed_tx.tx_hash),
+                    channel: None,
+                })
+            }
+            (Scheme::SuperfluidStreams, PaymentPayload::Stream(payload)) => {
+                let signed_stream = parse_stream_payload(payload).await?;
+                let decimals = self.decimals(network, token).await?;
+                let config = StreamsConfig {
+                    rpc_url: network.rpc_url.clone(),
+                    cfa_forwarder: Address::from_str(CFA_V1_FORWARDER_ADDRESS).unwrap(),
+                    token_address: token,
+                    recipient,
+                    amount: monthly_flow_rate(
+                        &format_units(self.amount(network, token, requirements).await?, decimals)
+                            .map_err(|_| AuthError::InternalError)?,
+                        decimals,
+                    )?,
+                    cache_time: 900,
+                };
+
+                // Streams are checked on-chain each time, nothing is left to record
+                if !verify_stream(signed_stream.clone(), config).await? {
+                    return Err(AuthError::InvalidTransaction(
+                        "Stream verification failed".to_string(),
+                    ));
+                }
+
+                Ok(Verified {
+                    payer: signed_stream.sender,
+                    tx_hash: None,
+                    channel: None,
+                })
+            }
+            (Scheme::PaymentChannels, PaymentPayload::Channel(payload)) => {
+                let (signature, message, payment_channel) = parse_channel_payload(payload).await?;
+                let amount = self.amount(network, token, requirements).await?;
+                let config = PaymentChannelConfig {
+                    rpc_url: network.rpc_url.clone(),
+                    token_address: token,
+                    recipient,
+                    amount,
+                    factory_address: get_channel_factory_from_chain_id(&network.chain_id),
+                };
+
+                let payee = self.payee(network, recipient).await?;
+                payee
+                    .channel_listener
+                    .get_or_init(|| {
+                        payee
+                            .state
+                            .start_channel_listener(network.chain_id, &network.rpc_url)
+                    })
+                    .await;
+                let channel_state = {
+                    let guard = payee.state.channel_state.read().await;
+                    guard.as_ref().unwrap().clone()
+                };
+
+                // Vouchers are checked against the request hash sent by the resource server, without one only unbound ones are accepted
+                let body_bytes = match &request.request_hash {
+                    Some(request_hash) => hex::decode(request_hash).map_err(|_| {
+                        AuthError::InvalidRequest("Invalid request hash".to_string())
+                    })?,
+                    None => Vec::new(),
+                };
+                let signed_request = SignedRequest {
+                    message,
+                    signature,
+                    payment_channel: payment_channel.clone(),
+                    payment_amount: amount,
+                    body_bytes,
+                    timestamp: payload.timestamp,
+                };
+
+                let (channel, verified) = if settle {
+                    verify_and_update_channel(&channel_state, &config, signed_request).await?
+                } else {
+                    if channel_state.is_closed(payment_channel.channel_id).await {
+                        return Err(AuthError::ChannelClosed);
+                    }
+                    let current = channel_state
+                        .get_channel_with_remaining(payment_channel.channel_id)
+                        .await;
+                    verify_channel(config, signed_request, current).await?
+                };
+                if !verified {
+                    return Err(AuthError::InvalidTransaction(
+                        "Channel verification failed".to_string(),
+                    ));
+                }
+
+                Ok(Verified {
+                    payer: channel.sender,
+                    tx_hash: None,
+                    channel: Some(channel),
+                })
+            }
+            (Scheme::Exact, PaymentPayload::Exact(payload)) => {
+                let settlement_signer = self
+                    .settlement_signer
+                    .as_ref()
+                    .ok_or(AuthError::SchemeNotAccepted)?;
+                let signed = parse_exact_payload(payload).await?;
+                let (token_name, token_version) =
+                    get_token_domain(&network.rpc_url, network.chain_id, &token).await?;
+                let config = ExactPaymentConfig {
+                    rpc_url: network.rpc_url.clone(),
+                    chain_id: network.chain_id,
+                    token_address: token,
+                    token_name,
+                    token_version,
+                    recipient,
+                    amount: self.amount(network, token, requirements).await?,
+                };
+
+                // Concurrent settlements of the same authorization wait for the first one
+                let payee = self.payee(network, recipient).await?;
+                let authorization = &signed.authorization;
+                let key = format!("exact:{}:{}", authorization.from, authorization.nonce);
+                let _inflight = payee.state.verification_cache.lock(&key).await;
+                if let Some(e) = payee.state.verification_cache.failure(&key) {
+                    return Err(e);
+                }
+
+                let payer = verify_authorization(&signed, &config).await?;
+                let tx_hash = if settle {
+                    Some(
+                        settle_authorization(&signed, &config, settlement_signer)
+                            .await
+                            .inspect_err(|e| {
+                                payee.state.verification_cache.record_failure(&key, e)
+                            })?,
+                    )
+                } else {
+                    None
+                };
+
+                Ok(Verified {
+                    payer,
+                    tx_hash,
+                    channel: None,
+                })
+            }
+            _ => Err(AuthError::SchemeNotAccepted),
+        }
+    }
+
+    // Schemes served, `exact` needs a settlement signer
+    fn schemes(&self) -> impl Iterator<Item = &Scheme> {
+        FACILITATOR_SCHEMES
+            .iter()
+            .filter(|scheme| **scheme != Scheme::Exact || self.settlement_signer.is_some())
+    }
+
+    // Payments are kept for as long as the facilitator runs, so the recipients tracked are bounded
+    async fn payee(
+        &self,
+        network: &FacilitatorNetwork,
+        recipient: Address,
+    ) -> Result<PayeeState, AuthError> {
+        let key = (network.name.clone(), recipient);
+        if let Some(payee) = self.payees.read().await.get(&key) {
+            return Ok(payee.clone());
+        }
+
+        let mut payees = self.payees.write().await;
+        if let Some(payee) = payees.get(&key) {
+            return Ok(payee.clone());
+        }
+        if self.recipients.is_empty() && payees.len() >= MAX_PAYEES {
+            return Err(AuthError::InvalidRequest(
+                "Too many recipients served".to_string(),
+            ));
+        }
+        let payee = PayeeState {
+            state: MiddlewareState::new()
+                .with_one_time_payment_state()
+                .await
+                .with_channel_state()
+                .await,
+            channel_listener: Arc::new(OnceCell::new()),
+        };
+        payees.insert(key, payee.clone());
+        Ok(payee)
+    }
+
+    // Price of the requirement in token base units, `maxAmountRequired` already is
+    async fn amount(
+        &self,
+        network: &FacilitatorNetwork,
+        token: Address,
+        requirements: &PaymentRequiredAccept,
+    ) -> Result<U256, AuthError> {
+        if let Some(max_amount) = &requirements.max_amount_required {
+            return U256::from_str(max_amount)
+                .map_err(|_| AuthError::InvalidRequest(format!("Invalid amount {}", max_amount)));
+        }
+        let decimals = self.decimals(network, token).await?;
+        parse_units(&requirements.amount, decimals)
+            .map(|amount| amount.get_absolute())
+            .map_err(|_| {
+                AuthError::InvalidRequest(format!("Invalid amount {}", requirements.amount))
+            })
+    }
+
+    async fn decimals(
+        &self,
+        network: &FacilitatorNetwork,
+        token: Address,
+    ) -> Result<u8, AuthError> {
+        let key = (network.name.clone(), token);
+        if let Some(decimals) = self.decimals.read().unwrap().get(&key) {
+            return Ok(*decimals);
+        }
+
+        let decimals = get_token_decimals(&network.rpc_url, &token)
+            .await
+            .map_err(AuthError::ContractError)?;
+        self.decimals.write().unwrap().insert(key, decimals);
+        Ok(decimals)
+    }
+}
+
+async fn verify_handler(
+    State(facilitator): State<Facilitator>,
+    Json(request): Json<FacilitatorRequest>,
+) -> Json<VerifyResponse> {
+    Json(facilitator.verify(&request).await)
+}
+
+async fn settle_handler(
+    State(facilitator): State<Facilitator>,
+    Json(request): Json<FacilitatorRequest>,
+) -> Json<SettleResponse> {
+    Json(facilitator.settle(&request).await)
+}
+
+async fn supported_handler(State(facilitator): State<Facilitator>) -> Json<SupportedResponse> {
+    Json(facilitator.supported())
+}
@@ -0,0 +1,86 @@
+use alloy::primitives::Address;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::middleware::{
+    payment_channel::types::PaymentChannel,
+    types::{PaymentHeader, PaymentRequiredAccept},
+};
+
+// The payment header is accepted as sent in `X-Payment`, a JSON string, or as an object
+fn deserialize_payment_header<'de, D>(deserializer: D) -> Result<PaymentHeader, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PaymentHeaderValue {
+        String(String),
+        Object(Box<PaymentHeader>),
+    }
+
+    match PaymentHeaderValue::deserialize(deserializer)? {
+        PaymentHeaderValue::String(s) => PaymentHeader::decode(&s).map_err(D::Error::custom),
+        PaymentHeaderValue::Object(obj) => Ok(*obj),
+    }
+}
+
+/// Body of `/verify` and `/settle`, the payment sent by the client and the requirement of the 402 it pays for
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FacilitatorRequest {
+    #[serde(rename = "x402Version")]
+    pub x402_version: u8,
+    #[serde(
+        rename = "paymentHeader",
+        deserialize_with = "deserialize_payment_header"
+    )]
+    pub payment_header: PaymentHeader,
+    #[serde(rename = "paymentRequirements")]
+    pub payment_requirements: PaymentRequiredAccept,
+    #[serde(
+        rename = "requestHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub request_hash: Option<String>, // hex `create_request_hash` of the request, channel vouchers are checked to be bound to it
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    #[serde(rename = "isValid")]
+    pub is_valid: bool,
+    #[serde(rename = "invalidReason", skip_serializing_if = "Option::is_none")]
+    pub invalid_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payer: Option<Address>,
+    #[serde(rename = "paymentChannel", skip_serializing_if = "Option::is_none")]
+    pub payment_channel: Option<PaymentChannel>, // balance left after the voucher, to return in `X-Payment`
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SettleResponse {
+    pub success: bool,
+    #[serde(rename = "errorReason", skip_serializing_if = "Option::is_none")]
+    pub error_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payer: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>, // transfer paid with, one-time payments
+    pub network: String,
+    #[serde(rename = "paymentChannel", skip_serializing_if = "Option::is_none")]
+    pub payment_channel: Option<PaymentChannel>, // balance left after the voucher, to return in `X-Payment`
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SupportedKind {
+    #[serde(rename = "x402Version")]
+    pub x402_version: u8,
+    pub scheme: String,
+    pub network: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SupportedResponse {
+    pub kinds: Vec<SupportedKind>,
+}
@@ -1,5 +1,11 @@
+#[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+pub mod client;
 pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod facilitator;
 pub mod middleware;
+#[cfg(all(feature = "proxy", not(target_arch = "wasm32")))]
+pub mod proxy;
 pub mod utils;
 
 #[cfg(target_arch = "wasm32")]
@@ -10,22 +16,2706 @@ pub mod wasm;
 mod tests {
     use std::str::FromStr;
 
-    use alloy::primitives::{aliases::I96, Address, FixedBytes, PrimitiveSignature, U256};
+    use alloy::{
+        primitives::{aliases::I96, Address, FixedBytes, PrimitiveSignature, U256},
+        signers::{local::PrivateKeySigner, SignerSync},
+    };
 
+    use crate::error::AuthError;
     use crate::middleware::{
+        coalesce::VerificationCache,
         one_time_payment::{
-            types::{OneTimePaymentConfig, SignedPaymentTx},
+            credits::CreditState,
+            state::OneTimePaymentState,
+            types::{OneTimePayment, OneTimePaymentConfig, SignedPaymentTx},
             verify::verify_tx,
         },
+        payment_channel::{
+            channel::ChannelState,
+            types::{ChannelAccount, PaymentChannel, PaymentChannelConfig, SignedRequest},
+            utils::{create_channel_message, create_request_hash},
+            verify::{charge_channel, verify_and_update_channel},
+        },
+        rpc::{get_rpc_pool, register_rpc_pool, RpcPool, RpcPoolConfig},
+        session::SessionConfig,
         stream_payment::{
             types::{SignedStream, StreamsConfig},
             verify::{verify_stream, verify_stream_via_indexer},
         },
+        utils::{get_current_time, get_token_decimals, redact},
+        UsageReport,
     };
 
     #[tokio::test]
     async fn test_verify_and_update() {}
 
+    #[tokio::test]
+    async fn test_channel_events() {
+        let state = ChannelState::new();
+        let channel = PaymentChannel {
+            address: Address::from_str("0x4cf93d3b7cd9d50ecfba2082d92534e578fe46f6").unwrap(),
+            sender: Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap(),
+            recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
+            balance: U256::from(999000),
+            nonce: U256::from(0),
+            expiration: U256::from(1734391330),
+            channel_id: U256::from(1),
+        };
+        state
+            .channels
+            .write()
+            .await
+            .insert(channel.channel_id, channel.clone());
+        state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1000000),
+                spent: U256::from(1000),
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            state
+                .apply_deposit(U256::from(1), U256::from(1500000))
+                .await
+        );
+        assert!(
+            state
+                .apply_expiration(U256::from(1), U256::from(1834391330))
+                .await
+        );
+        assert!(!state.apply_deposit(U256::from(2), U256::from(500)).await);
+
+        // The same deposit delivered twice is only counted once
+        assert!(
+            state
+                .apply_deposit(U256::from(1), U256::from(1500000))
+                .await
+        );
+
+        // Events wait for a request in flight on the channel instead of being overwritten by it
+        let guard = state.lock_channel(U256::from(1)).await;
+        let deposit = tokio::spawn({
+            let state = state.clone();
+            async move {
+                state
+                    .apply_deposit(U256::from(1), U256::from(1600000))
+                    .await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(
+            state.get_account(U256::from(1)).await.unwrap().deposited,
+            U256::from(1500000)
+        );
+        drop(guard);
+        assert!(deposit.await.unwrap());
+
+        // Locks are only kept while in use, ids from unverified requests don't pile up
+        drop(state.lock_channel(U256::from(42)).await);
+        assert!(state.channel_locks.lock().unwrap().is_empty());
+
+        let updated = state
+            .get_channel_with_remaining(U256::from(1))
+            .await
+            .unwrap();
+        assert_eq!(updated.balance, U256::from(1599000));
+        assert_eq!(updated.expiration, U256::from(1834391330));
+
+        state.mark_closed(U256::from(1)).await;
+        assert!(state.is_closed(U256::from(1)).await);
+        assert!(state.get_channel(U256::from(1)).await.is_none());
+    }
+
+    #[test]
+    fn test_charge_channel() {
+        let account = ChannelAccount {
+            deposited: U256::from(1000),
+            spent: U256::from(100),
+            ..Default::default()
+        };
+
+        // Signing the remaining balance minus the price pays for the request
+        let charged = charge_channel(&account, U256::from(800), U256::from(100)).unwrap();
+        assert_eq!(charged.spent, U256::from(200));
+        assert_eq!(charged.remaining(), U256::from(800));
+
+        // Overpaying is accepted
+        let charged = charge_channel(&account, U256::from(700), U256::from(100)).unwrap();
+        assert_eq!(charged.spent, U256::from(300));
+
+        // Re-signing the previous balance doesn't cover the request
+        assert!(matches!(
+            charge_channel(&account, U256::from(900), U256::from(100)),
+            Err(AuthError::InvalidChannel(_))
+        ));
+
+        // Signing more than deposited can't underflow
+        assert!(matches!(
+            charge_channel(&account, U256::from(2000), U256::from(100)),
+            Err(AuthError::InvalidChannel(_))
+        ));
+
+        // Nothing left to pay with
+        assert!(matches!(
+            charge_channel(&account, U256::ZERO, U256::from(1000)),
+            Err(AuthError::InsufficientBalance)
+        ));
+
+        // A post-paid charge has to be covered by the next voucher on top of its own price
+        let account = ChannelAccount {
+            owed: U256::from(250),
+            ..account
+        };
+        assert_eq!(account.remaining(), U256::from(650));
+        assert!(matches!(
+            charge_channel(&account, U256::from(800), U256::from(100)),
+            Err(AuthError::InvalidChannel(_))
+        ));
+        let charged = charge_channel(&account, U256::from(550), U256::from(100)).unwrap();
+        assert_eq!(charged.spent, U256::from(450));
+        assert_eq!(charged.owed, U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_usage_report_charge() {
+        let state = ChannelState::new();
+        state.accounts.write().await.insert(
+            U256::from(1),
+            ChannelAccount {
+                deposited: U256::from(1000),
+                spent: U256::from(100),
+                ..Default::default()
+            },
+        );
+
+        // Filled by the handler, possibly in several steps
+        let usage = UsageReport::default();
+        assert!(usage.amount().is_none());
+        usage.charge(U256::from(200));
+        usage.charge(U256::from(50));
+        assert_eq!(usage.amount(), Some(U256::from(250)));
+
+        let (account, charged) = state
+            .record_charge(U256::from(1), usage.amount().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(charged, U256::from(250));
+        assert_eq!(account.owed, U256::from(250));
+        assert_eq!(account.remaining(), U256::from(650));
+
+        // Charged as far as the deposit goes, the request was already served
+        let (account, charged) = state
+            .record_charge(U256::from(1), U256::from(651))
+            .await
+            .unwrap();
+        assert_eq!(charged, U256::from(650));
+        assert_eq!(account.remaining(), U256::ZERO);
+        assert!(matches!(
+            state.record_charge(U256::from(2), U256::from(1)).await,
+            Err(AuthError::ChannelNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_credits() {
+        let state = CreditState::new();
+        let payer = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let tx_hash = FixedBytes::<32>::from_str(
+            "0xe88140d4787b1305c24961dcef2f7f73d583bb862b3cbde4b7eec854f61a0248",
+        )
+        .unwrap();
+
+        assert!(state.top_up(tx_hash, payer, U256::from(1000)).await);
+        // A deposit is only credited once
+        assert!(!state.top_up(tx_hash, payer, U256::from(1000)).await);
+        assert_eq!(state.get_depositor(tx_hash).await, Some(payer));
+        assert_eq!(state.balance(payer).await, U256::from(1000));
+
+        assert_eq!(
+            state.debit(payer, U256::from(400)).await.unwrap(),
+            U256::from(600)
+        );
+        assert!(matches!(
+            state.debit(payer, U256::from(601)).await,
+            Err(AuthError::InsufficientBalance)
+        ));
+        assert_eq!(state.balance(payer).await, U256::from(600));
+
+        // Usage is debited as far as the balance goes
+        assert_eq!(
+            state.debit_usage(payer, U256::from(1000)).await,
+            (U256::from(600), U256::ZERO)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscription() {
+        let state = OneTimePaymentState::new();
+        let sender = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let payment = |tx: u8, payment_timestamp: u64| OneTimePayment {
+            tx_hash: FixedBytes::<32>::with_last_byte(tx),
+            sender,
+            payment_timestamp,
+            first_reedemed: 0,
+            redemptions: 0,
+            amount: U256::from(1000),
+        };
+
+        assert_eq!(
+            state.extend_subscription(payment(1, 1000), 500).await,
+            Some(1500)
+        );
+        // A payment is only applied once
+        assert_eq!(state.extend_subscription(payment(1, 1000), 500).await, None);
+        // Extends the running subscription
+        assert_eq!(
+            state.extend_subscription(payment(2, 1200), 500).await,
+            Some(2000)
+        );
+        // Starts over from the payment once lapsed
+        assert_eq!(
+            state.extend_subscription(payment(3, 5000), 500).await,
+            Some(5500)
+        );
+        assert_eq!(state.get_subscription(sender).await, Some(5500));
+
+        // Subscription payments aren't found as one-time payments, nor one-time payments buy a subscription
+        assert!(state.get(payment(1, 1000).tx_hash).await.is_none());
+        state.set(payment(4, 6000).tx_hash, payment(4, 6000)).await;
+        assert_eq!(state.extend_subscription(payment(4, 6000), 500).await, None);
+        assert_eq!(state.get_subscription(sender).await, Some(5500));
+    }
+
+    #[test]
+    fn test_session_token() {
+        let session = SessionConfig::new("server-secret").with_ttl(60);
+        let now = get_current_time();
+
+        let token = session
+            .issue("0xpayer", "one-time", "/api/data", None)
+            .unwrap();
+        let claims = session.verify(&token).unwrap();
+        assert_eq!(claims.sub, "0xpayer");
+        assert_eq!(claims.scheme, "one-time");
+        assert_eq!(claims.scope, "/api/data");
+        assert!(claims.exp <= now + 61);
+
+        // Bounded by the end of the access paid for
+        let token = session
+            .issue("0xpayer", "subscription", "/api/data", Some(now + 10))
+            .unwrap();
+        assert!(session.verify(&token).unwrap().exp <= now + 10);
+
+        // Signed with another key
+        let other = SessionConfig::new("other-secret");
+        assert!(matches!(
+            other.verify(&token),
+            Err(AuthError::InvalidSignature)
+        ));
+
+        // Tampered claims
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let forged = session
+            .issue("0xpayer", "subscription", "/api/admin", None)
+            .unwrap();
+        parts[1] = forged.split('.').nth(1).unwrap();
+        assert!(session.verify(&parts.join(".")).is_err());
+
+        // Already expired
+        let expired = session
+            .issue("0xpayer", "stream", "/api/data", Some(now - 1))
+            .unwrap();
+        assert!(session.verify(&expired).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verified_payment() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig, VerifiedPayment,
+        };
+
+        let payer = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let session = SessionConfig::new("server-secret");
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::Subscription,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "1".to_string(),
+            decimals: Some(6),
+        }])
+        .with_sessions(session.clone());
+
+        // The handler sees who paid and how, here from a session token
+        let app = Router::new()
+            .route(
+                "/api/data",
+                get(|payment: VerifiedPayment| async move {
+                    assert!(payment.session);
+                    assert_eq!(payment.scheme, Scheme::Subscription);
+                    assert_eq!(payment.network, "base-sepolia");
+                    assert!(payment.expires_at.is_some());
+                    payment.payer.to_string()
+                }),
+            )
+            .layer(PaymentsLayer::new(MiddlewareState::new(), config))
+            .route(
+                "/free",
+                get(|payment: Option<VerifiedPayment>| async move {
+                    assert!(payment.is_none());
+                }),
+            );
+
+        let token = session
+            .issue(&payer.to_string(), "subscription", "/api/data", None)
+            .unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/api/data")
+                    .header("X-Payment-Session", token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, payer.to_string());
+
+        // Routes outside the middleware get no payment
+        let response = app
+            .oneshot(Request::get("/free").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_x402_client() {
+        use std::sync::{Arc, Mutex};
+
+        use axum::{http::HeaderMap, response::IntoResponse, routing::get, Router};
+
+        use crate::client::{ChannelManager, ClientBuilder, X402Middleware};
+        use crate::middleware::{
+            payment_channel::utils::modify_headers_axum,
+            types::{PaymentHeader, PaymentPayload},
+            utils::parse_channel_payload,
+            MiddlewareConfig, Scheme, SchemeConfig,
+        };
+
+        let signer = PrivateKeySigner::random();
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        let scheme = |scheme| SchemeConfig {
+            scheme,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        };
+        let config = MiddlewareConfig::new(vec![
+            scheme(Scheme::OneTimePayments),
+            scheme(Scheme::PaymentChannels),
+        ]);
+
+        // Mock API answering 402 until a channel voucher signed by the client comes along
+        let vouchers = Arc::new(Mutex::new(Vec::new()));
+        let received = vouchers.clone();
+        let payer = signer.address();
+        let app = Router::new().route(
+            "/api",
+            get(move |headers: HeaderMap| async move {
+                let Some(header) = headers.get("X-Payment") else {
+                    return AuthError::MissingHeaders.into_x402_response(&config, "/api", None);
+                };
+                let header: PaymentHeader = serde_json::from_str(header.to_str().unwrap()).unwrap();
+                assert_eq!(header.network, "base-sepolia");
+                let PaymentPayload::Channel(payload) = header.payload else {
+                    panic!("Expected a channel payment");
+                };
+                let (signature, message, channel) = parse_channel_payload(&payload).await.unwrap();
+                assert_eq!(
+                    message,
+                    create_channel_message(channel.channel_id, channel.balance, channel.nonce, &[])
+                );
+                assert_eq!(signature.recover_address_from_msg(&message).unwrap(), payer);
+
+                received.lock().unwrap().push(channel.clone());
+                modify_headers_axum("ok".into_response(), &channel)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let channel = PaymentChannel {
+            address: Address::ZERO,
+            sender: payer,
+            recipient,
+            balance: U256::from(1_000_000),
+            nonce: U256::ZERO,
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(7),
+        };
+        let channels = ChannelManager::new(
+            "http://127.0.0.1:1".parse().unwrap(),
+            signer.clone(),
+            Address::ZERO,
+        );
+        channels.add_channel(channel, Address::ZERO, 6);
+        let x402 = X402Middleware::new(signer)
+            .with_payment_tx(Scheme::OneTimePayments, FixedBytes::<32>::ZERO)
+            .with_channels(channels.clone())
+            .with_preference(vec![Scheme::PaymentChannels]);
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(x402.clone())
+            .build();
+
+        // Each request is paid with the next voucher, 0.01 at 6 decimals off the balance
+        for _ in 0..2 {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), 200);
+        }
+        let vouchers = vouchers.lock().unwrap().clone();
+        assert_eq!(vouchers.len(), 2);
+        assert_eq!(
+            (vouchers[0].nonce, vouchers[0].balance),
+            (U256::ZERO, U256::from(990_000))
+        );
+        assert_eq!(
+            (vouchers[1].nonce, vouchers[1].balance),
+            (U256::from(1), U256::from(980_000))
+        );
+        assert_eq!(
+            channels
+                .get_channel(U256::from(7))
+                .await
+                .unwrap()
+                .channel
+                .nonce,
+            U256::from(2)
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_channel_manager() {
+        use crate::client::ChannelManager;
+
+        let signer = PrivateKeySigner::random();
+        let manager = ChannelManager::new(
+            "http://127.0.0.1:1".parse().unwrap(),
+            signer.clone(),
+            Address::ZERO,
+        );
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        manager.add_channel(
+            PaymentChannel {
+                address: Address::ZERO,
+                sender: signer.address(),
+                recipient,
+                balance: U256::from(1000),
+                nonce: U256::ZERO,
+                expiration: U256::from(get_current_time() + 3600),
+                channel_id: U256::from(3),
+            },
+            Address::ZERO,
+            6,
+        );
+        assert_eq!(manager.channel_to(recipient), Some(U256::from(3)));
+        assert_eq!(manager.channel_to(Address::ZERO), None);
+
+        // Vouchers sign the balance left after paying, without touching the local channel
+        let mut channel = manager.lock_channel(U256::from(3)).await.unwrap();
+        let voucher = manager
+            .sign_voucher(&channel, U256::from(100), &[])
+            .unwrap();
+        assert_eq!(voucher.payment_channel.balance, U256::from(900));
+        assert_eq!(voucher.payment_channel.nonce, U256::ZERO);
+        assert_eq!(
+            voucher.message,
+            create_channel_message(U256::from(3), U256::from(900), U256::ZERO, &[])
+        );
+        assert_eq!(
+            voucher
+                .signature
+                .recover_address_from_msg(&voucher.message)
+                .unwrap(),
+            signer.address()
+        );
+        assert!(matches!(
+            manager.sign_voucher(&channel, U256::from(1001), &[]),
+            Err(AuthError::InsufficientBalance)
+        ));
+
+        // The channel returned by the server moves to the next nonce
+        channel.apply_returned(&voucher.payment_channel);
+        drop(channel);
+        let channel = manager.get_channel(U256::from(3)).await.unwrap();
+        assert_eq!(
+            (channel.channel.balance, channel.channel.nonce),
+            (U256::from(900), U256::from(1))
+        );
+
+        // Timeouts can't be claimed before the channel expired
+        assert!(matches!(
+            manager.claim_timeout(U256::from(3)).await,
+            Err(AuthError::InvalidChannel(_))
+        ));
+        assert!(matches!(
+            manager.deposit(U256::from(4), U256::from(1)).await,
+            Err(AuthError::ChannelNotFound)
+        ));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_spend_policy() {
+        use crate::client::{SpendLedger, SpendPolicy};
+        use crate::middleware::types::PaymentRequiredAccept;
+
+        let token = Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap();
+        let accept = |scheme: &str, amount: &str| PaymentRequiredAccept {
+            scheme: scheme.to_string(),
+            network: "base-sepolia".to_string(),
+            amount: amount.to_string(),
+            max_amount_required: None,
+            pay_to: Address::ZERO.to_string(),
+            asset: token.to_string(),
+            resource: "/api".to_string(),
+            description: None,
+            mime_type: String::new(),
+            max_timeout_seconds: None,
+            extra: None,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("pipegate-ledger-{}.json", get_current_time()));
+        let policy = SpendPolicy::new()
+            .with_max_price("0.5")
+            .unwrap()
+            .with_daily_budget("1")
+            .unwrap()
+            .with_allowed_tokens([token])
+            .with_allowed_networks(["base-sepolia"])
+            .with_ledger(SpendLedger::open(&path).unwrap());
+
+        assert!(policy
+            .check("api.example.com", &accept("channel", "0.5"))
+            .is_ok());
+        assert!(matches!(
+            policy.check("api.example.com", &accept("channel", "0.6")),
+            Err(AuthError::PolicyViolation(e)) if e.contains("maximum of 0.5")
+        ));
+        let mut other_network = accept("channel", "0.1");
+        other_network.network = "base".to_string();
+        assert!(matches!(
+            policy.check("api.example.com", &other_network),
+            Err(AuthError::PolicyViolation(_))
+        ));
+        let mut other_token = accept("channel", "0.1");
+        other_token.asset = Address::ZERO.to_string();
+        assert!(matches!(
+            policy.check("api.example.com", &other_token),
+            Err(AuthError::PolicyViolation(_))
+        ));
+
+        // Vouchers are counted per host until the daily budget is spent, refused ones are given back
+        policy.reserve("api.example.com", "0.5").unwrap();
+        policy.reserve("api.example.com", "0.4").unwrap();
+        assert!(matches!(
+            policy.reserve("api.example.com", "0.2"),
+            Err(AuthError::PolicyViolation(e)) if e.contains("0.9 spent today")
+        ));
+        assert!(policy
+            .check("api.example.com", &accept("channel", "0.2"))
+            .is_err());
+        assert!(policy
+            .check("other.example.com", &accept("channel", "0.2"))
+            .is_ok());
+        policy.release("api.example.com", "0.4");
+        policy.reserve("api.example.com", "0.2").unwrap();
+
+        // Budgets only cover channel vouchers, other schemes reuse a payment already made
+        assert!(policy
+            .check("api.example.com", &accept("one-time", "0.5"))
+            .is_ok());
+
+        // Spend survives a restart
+        let reopened = SpendLedger::open(&path).unwrap();
+        assert_eq!(
+            reopened.spent_today("api.example.com"),
+            U256::from(700_000_000_000_000_000u64)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_facilitator() {
+        use crate::facilitator::{
+            types::{SettleResponse, SupportedResponse, VerifyResponse},
+            Facilitator,
+        };
+
+        let app = Facilitator::new()
+            .with_network("base-sepolia", 84532, "http://127.0.0.1:1")
+            .router();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let supported: SupportedResponse = client
+            .get(format!("{}/supported", url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let schemes: Vec<_> = supported.kinds.iter().map(|k| k.scheme.as_str()).collect();
+        assert_eq!(schemes, ["one-time", "stream", "channel"]);
+        assert!(supported.kinds.iter().all(|k| k.network == "base-sepolia"));
+
+        let payment = |network: &str| {
+            serde_json::json!({
+                "x402Version": 1,
+                "network": network,
+                "scheme": "one-time",
+                "payload": { "signature": "0xnothex", "tx_hash": format!("0x{}", "11".repeat(32)) },
+            })
+        };
+        let requirements = |network: &str| {
+            serde_json::json!({
+                "scheme": "one-time",
+                "network": network,
+                "amount": "0.01",
+                "payTo": "0x62c43323447899acb61c18181e34168903e033bf",
+                "asset": "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+                "resource": "/api",
+            })
+        };
+
+        // The header is accepted as sent in X-Payment, and checked before anything on-chain
+        let verify: VerifyResponse = client
+            .post(format!("{}/verify", url))
+            .json(&serde_json::json!({
+                "x402Version": 1,
+                "paymentHeader": payment("base-sepolia").to_string(),
+                "paymentRequirements": requirements("base-sepolia"),
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(!verify.is_valid);
+        assert!(verify.payer.is_none());
+        assert!(verify.invalid_reason.is_some());
+
+        let settle: SettleResponse = client
+            .post(format!("{}/settle", url))
+            .json(&serde_json::json!({
+                "x402Version": 1,
+                "paymentHeader": payment("base"),
+                "paymentRequirements": requirements("base"),
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(!settle.success);
+        assert_eq!(settle.network, "base");
+        assert_eq!(
+            settle.error_reason.unwrap(),
+            AuthError::InvalidRequest("Unsupported network base".to_string()).to_string()
+        );
+
+        let mismatched: VerifyResponse = client
+            .post(format!("{}/verify", url))
+            .json(&serde_json::json!({
+                "x402Version": 1,
+                "paymentHeader": payment("base-sepolia"),
+                "paymentRequirements": requirements("base"),
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(!mismatched.is_valid);
+
+        // Restricted to its recipients, payments to others are refused before anything on-chain
+        let facilitator = Facilitator::new()
+            .with_network("base-sepolia", 84532, "http://127.0.0.1:1")
+            .with_recipient(Address::ZERO);
+        let request = serde_json::from_value(serde_json::json!({
+            "x402Version": 1,
+            "paymentHeader": payment("base-sepolia"),
+            "paymentRequirements": requirements("base-sepolia"),
+        }))
+        .unwrap();
+        assert_eq!(
+            facilitator.verify(&request).await.invalid_reason.unwrap(),
+            AuthError::InvalidRequest(
+                "Recipient 0x62C43323447899acb61C18181e34168903E033Bf not served".to_string()
+            )
+            .to_string()
+        );
+    }
+
+    #[cfg(feature = "proxy")]
+    #[tokio::test]
+    async fn test_proxy() {
+        use axum::{
+            body::Body,
+            http::{HeaderMap, Request},
+            routing::any,
+            Router,
+        };
+        use tower::ServiceExt;
+
+        use crate::middleware::{MiddlewareConfig, Scheme, SchemeConfig};
+        use crate::proxy::{Proxy, ProxyConfig};
+
+        // Upstream echoing the path, payer and body it received
+        let upstream = Router::new().route(
+            "/*path",
+            any(|request: Request<Body>| async move {
+                let payer = request
+                    .headers()
+                    .get("X-Pipegate-Payer")
+                    .map(|v| v.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                let uri = request.uri().to_string();
+                let body = axum::body::to_bytes(request.into_body(), 1024)
+                    .await
+                    .unwrap();
+                format!("{} {} {}", uri, payer, String::from_utf8_lossy(&body))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/base", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let payer = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let session = SessionConfig::new("server-secret");
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::Subscription,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "1".to_string(),
+            decimals: Some(6),
+        }])
+        .with_sessions(session.clone());
+
+        assert!(Proxy::new("not a url").is_err());
+        let app = Proxy::new(&url)
+            .unwrap()
+            .free_route("/free")
+            .paid_route("/api/*rest", config)
+            .router();
+
+        let call = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let headers: HeaderMap = response.headers().clone();
+                let body = axum::body::to_bytes(response.into_body(), 1024)
+                    .await
+                    .unwrap();
+                (status, headers, String::from_utf8_lossy(&body).to_string())
+            }
+        };
+
+        // Free routes are forwarded with the body, a payer set by the client is dropped
+        let (status, _, body) = call(
+            Request::post("/free?q=1")
+                .header("X-Pipegate-Payer", payer.to_string())
+                .body(Body::from("hello"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, "/base/free?q=1  hello");
+
+        // Paid routes need a payment, unmatched routes are not forwarded
+        let (status, _, _) = call(Request::get("/api/data").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, 402);
+        let (status, _, _) = call(Request::get("/other").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, 404);
+
+        // Once paid, the upstream is told who paid
+        let token = session
+            .issue(&payer.to_string(), "subscription", "/api/data", None)
+            .unwrap();
+        let (status, _, body) = call(
+            Request::get("/api/data")
+                .header("X-Payment-Session", token)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, format!("/base/api/data {} ", payer));
+
+        // An upstream that's down is a bad gateway
+        let app = Proxy::new("http://127.0.0.1:1")
+            .unwrap()
+            .free_route("/free")
+            .router();
+        let response = app
+            .oneshot(Request::get("/free").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 502);
+
+        // Routes accepting exact payments need a settlement key, read from a file and never inline
+        let exact_config = |settlement_key_file: Option<String>| {
+            serde_json::from_value::<ProxyConfig>(serde_json::json!({
+                "listen": "127.0.0.1:0",
+                "upstream": url,
+                "settlementKeyFile": settlement_key_file,
+                "routes": [{
+                    "path": "/api/*rest",
+                    "accepts": [{
+                        "scheme": "exact",
+                        "rpcUrl": "http://127.0.0.1:1",
+                        "token": Address::ZERO,
+                        "recipient": Address::ZERO,
+                        "amount": "0.01",
+                    }],
+                }],
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            Proxy::from_config(&exact_config(None))
+                .await
+                .err()
+                .unwrap()
+                .to_string(),
+            AuthError::InvalidRequest(
+                "Route /api/*rest accepts exact payments without a settlementKeyFile".to_string()
+            )
+            .to_string()
+        );
+        let key_file = std::env::temp_dir().join(format!("pipegate-key-{}", std::process::id()));
+        std::fs::write(&key_file, "not a key").unwrap();
+        assert!(matches!(
+            Proxy::from_config(&exact_config(Some(key_file.to_string_lossy().to_string()))).await,
+            Err(AuthError::InvalidRequest(_))
+        ));
+        std::fs::remove_file(&key_file).unwrap();
+
+        // With the key, exact payments get past the settlement check
+        let exact = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::Exact,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        }]);
+        let payment = serde_json::json!({
+            "x402Version": 1,
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "payload": {
+                "signature": "0xnothex",
+                "authorization": {
+                    "from": payer.to_string(),
+                    "to": Address::ZERO.to_string(),
+                    "value": "10000",
+                    "validAfter": "0",
+                    "validBefore": "0",
+                    "nonce": FixedBytes::<32>::ZERO.to_string(),
+                },
+            },
+        });
+        for signer in [None, Some(PrivateKeySigner::random())] {
+            let mut proxy = Proxy::new(&url)
+                .unwrap()
+                .paid_route("/api/*rest", exact.clone());
+            if let Some(signer) = signer.clone() {
+                proxy = proxy.with_settlement_signer(signer);
+            }
+            let response = proxy
+                .router()
+                .oneshot(
+                    Request::get("/api/data")
+                        .header("X-Payment", payment.to_string())
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 402);
+            let body = axum::body::to_bytes(response.into_body(), 4096)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["error"] == AuthError::InvalidConfig.to_string(),
+                signer.is_none(),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verifier() {
+        use axum::{async_trait, body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::facilitator::{
+            types::{FacilitatorRequest, SettleResponse, VerifyResponse},
+            Facilitator,
+        };
+        use crate::middleware::{
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, RemoteVerifier, Scheme, SchemeConfig,
+            VerifiedPayment, Verifier,
+        };
+
+        let payer = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let tx_hash = format!("0x{}", "11".repeat(32));
+
+        // Settles any payment, checking it's asked for the configured price
+        struct Approve(Address, String);
+
+        #[async_trait]
+        impl Verifier for Approve {
+            async fn verify(
+                &self,
+                _request: &FacilitatorRequest,
+            ) -> Result<VerifyResponse, AuthError> {
+                unreachable!("The middleware only settles")
+            }
+
+            async fn settle(
+                &self,
+                request: &FacilitatorRequest,
+            ) -> Result<SettleResponse, AuthError> {
+                let requirements = &request.payment_requirements;
+                assert_eq!(requirements.scheme, "one-time");
+                assert_eq!(requirements.amount, "0.01");
+                assert_eq!(requirements.resource, "/api/data");
+                Ok(SettleResponse {
+                    success: true,
+                    error_reason: None,
+                    payer: Some(self.0),
+                    transaction: Some(self.1.clone()),
+                    network: requirements.network.clone(),
+                    payment_channel: None,
+                })
+            }
+        }
+
+        let scheme_config = |scheme: Scheme| SchemeConfig {
+            scheme,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        };
+        let config = MiddlewareConfig::new(vec![
+            scheme_config(Scheme::OneTimePayments),
+            scheme_config(Scheme::Credits),
+        ]);
+        let app = |verifier: Box<dyn Fn(PaymentsLayer) -> PaymentsLayer>| {
+            Router::new()
+                .route(
+                    "/api/data",
+                    get(|payment: VerifiedPayment| async move {
+                        format!("{} {}", payment.payer, payment.tx_hash.unwrap())
+                    }),
+                )
+                .layer(verifier(PaymentsLayer::new(
+                    MiddlewareState::new(),
+                    config.clone(),
+                )))
+        };
+        let request = |scheme: &str| {
+            let payment = serde_json::json!({
+                "x402Version": 1,
+                "network": "base-sepolia",
+                "scheme": scheme,
+                "payload": { "signature": format!("0x{}", "22".repeat(65)), "tx_hash": tx_hash },
+            });
+            Request::get("/api/data")
+                .header("X-Payment", payment.to_string())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Nothing is checked on-chain, the handler sees what the verifier settled
+        let settled = tx_hash.clone();
+        let approving = app(Box::new(move |layer| {
+            layer.with_verifier(Approve(payer, settled.clone()))
+        }));
+        let response = approving
+            .clone()
+            .oneshot(request("one-time"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, format!("{} {}", payer, tx_hash));
+
+        // Credits keep balances on the resource server and aren't delegated
+        let response = approving.oneshot(request("credits")).await.unwrap();
+        assert_eq!(response.status(), 402);
+
+        // A remote facilitator refusing the payment, and one that can't be reached
+        let facilitator = Facilitator::new()
+            .with_network("base", 8453, "http://127.0.0.1:1")
+            .router();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, facilitator).await.unwrap() });
+
+        for (url, error) in [
+            (url, "Payment rejected by the facilitator: Invalid request : Unsupported network base-sepolia"),
+            ("http://127.0.0.1:1".to_string(), "Network error"),
+        ] {
+            let remote = RemoteVerifier::new(&url).unwrap();
+            let response = app(Box::new(move |layer| layer.with_verifier(remote.clone())))
+                .oneshot(request("one-time"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 402);
+            let body = axum::body::to_bytes(response.into_body(), 4096)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(body["error"].as_str().unwrap().starts_with(error), "{}", body);
+        }
+        assert!(RemoteVerifier::new("not a url").is_err());
+
+        // A facilitator that never answers fails the payment once the timeout is reached
+        let hung = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", hung.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = hung.accept().await {
+                connections.push(connection);
+            }
+        });
+        let remote = RemoteVerifier::new(&url)
+            .unwrap()
+            .with_timeout(std::time::Duration::from_millis(100));
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            app(Box::new(move |layer| layer.with_verifier(remote.clone())))
+                .oneshot(request("one-time")),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response.status(), 402);
+    }
+
+    #[tokio::test]
+    async fn test_x402_compat() {
+        use axum::{async_trait, body::Body, http::Request, routing::get, Router};
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use tower::ServiceExt;
+
+        use crate::facilitator::types::{
+            FacilitatorRequest, SettleResponse, SupportedKind, VerifyResponse,
+        };
+        use crate::facilitator::Facilitator;
+        use crate::middleware::{
+            exact_payment::{
+                types::{ExactPaymentConfig, SignedAuthorization, TransferWithAuthorization},
+                utils::{authorization_hash, cached_token_domain},
+                verify::verify_authorization,
+            },
+            MiddlewareConfig, MiddlewareState, PaymentHeader, PaymentsLayer, Scheme, SchemeConfig,
+            Verifier, PAYMENT_RESPONSE_HEADER,
+        };
+
+        // USDC on Base Sepolia, its EIP-712 domain is known without an RPC
+        let usdc = Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap();
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        let (name, version) = cached_token_domain(84532, &usdc).unwrap();
+        assert_eq!((name.as_str(), version.as_str()), ("USDC", "2"));
+
+        let payer = PrivateKeySigner::random();
+        let authorization = TransferWithAuthorization {
+            from: payer.address(),
+            to: recipient,
+            value: U256::from(10000),
+            validAfter: U256::ZERO,
+            validBefore: U256::from(get_current_time() + 600),
+            nonce: FixedBytes::repeat_byte(7),
+        };
+        let hash = authorization_hash(&authorization, &name, &version, 84532, usdc);
+        let signed = SignedAuthorization {
+            authorization: authorization.clone(),
+            signature: payer.sign_hash_sync(&hash).unwrap(),
+        };
+        let config = ExactPaymentConfig {
+            rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            token_address: usdc,
+            token_name: name.clone(),
+            token_version: version.clone(),
+            recipient,
+            amount: U256::from(10000),
+        };
+
+        // Signed by the payer, the nonce and balance are then checked on-chain
+        let result = verify_authorization(&signed, &config).await;
+        assert!(
+            !matches!(result, Err(AuthError::InvalidSignature)),
+            "{:?}",
+            result
+        );
+        let forged = SignedAuthorization {
+            authorization: authorization.clone(),
+            signature: PrivateKeySigner::random().sign_hash_sync(&hash).unwrap(),
+        };
+        assert!(matches!(
+            verify_authorization(&forged, &config).await,
+            Err(AuthError::InvalidSignature)
+        ));
+        let underpaid = ExactPaymentConfig {
+            amount: U256::from(20000),
+            ..config.clone()
+        };
+        assert!(matches!(
+            verify_authorization(&signed, &underpaid).await,
+            Err(AuthError::InsufficientBalance)
+        ));
+
+        // The header as x402 SDKs send it, base64 encoded
+        let payment = serde_json::json!({
+            "x402Version": 1,
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "payload": {
+                "signature": signed.signature.to_string(),
+                "authorization": {
+                    "from": authorization.from.to_string(),
+                    "to": authorization.to.to_string(),
+                    "value": authorization.value.to_string(),
+                    "validAfter": authorization.validAfter.to_string(),
+                    "validBefore": authorization.validBefore.to_string(),
+                    "nonce": authorization.nonce.to_string(),
+                },
+            },
+        });
+        let header = STANDARD.encode(payment.to_string());
+        let decoded = PaymentHeader::decode(&header).unwrap();
+        assert!(decoded.validate_payload_for_scheme());
+        assert_eq!(decoded.get_scheme_enum(), Some(Scheme::Exact));
+        assert_eq!(
+            PaymentHeader::decode(&decoded.encode().unwrap())
+                .unwrap()
+                .scheme,
+            "exact"
+        );
+        assert!(PaymentHeader::decode("not a header").is_err());
+
+        // Settles any payment, checking it's asked for the price in base units
+        struct Approve(Address, String);
+
+        #[async_trait]
+        impl Verifier for Approve {
+            async fn verify(
+                &self,
+                _request: &FacilitatorRequest,
+            ) -> Result<VerifyResponse, AuthError> {
+                unreachable!("The middleware only settles")
+            }
+
+            async fn settle(
+                &self,
+                request: &FacilitatorRequest,
+            ) -> Result<SettleResponse, AuthError> {
+                let requirements = &request.payment_requirements;
+                assert_eq!(requirements.scheme, "exact");
+                assert_eq!(requirements.max_amount_required.as_deref(), Some("10000"));
+                assert_eq!(requirements.resource, "http://example.com/api/data");
+                Ok(SettleResponse {
+                    success: true,
+                    error_reason: None,
+                    payer: Some(self.0),
+                    transaction: Some(self.1.clone()),
+                    network: requirements.network.clone(),
+                    payment_channel: None,
+                })
+            }
+        }
+
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::Exact,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: usdc,
+            recipient,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        }]);
+        let app = |layer: PaymentsLayer| {
+            Router::new()
+                .route("/api/data", get(|| async { "data" }))
+                .layer(layer)
+        };
+        let request = |payment: Option<&str>| {
+            let request = Request::get("/api/data").header("Host", "example.com");
+            match payment {
+                Some(payment) => request.header("X-Payment", payment),
+                None => request,
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        // The 402 carries what x402 clients sign with
+        let layer = PaymentsLayer::new(MiddlewareState::new(), config.clone());
+        let response = app(layer.clone()).oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), 402);
+        let body = axum::body::to_bytes(response.into_body(), 4096)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let accept = &body["accepts"][0];
+        assert_eq!(accept["scheme"], "exact");
+        assert_eq!(accept["network"], "base-sepolia");
+        assert_eq!(accept["maxAmountRequired"], "10000");
+        assert_eq!(accept["mimeType"], "");
+        assert_eq!(accept["resource"], "http://example.com/api/data");
+        assert_eq!(accept["extra"]["name"], "USDC");
+        assert_eq!(accept["extra"]["version"], "2");
+
+        // Settling takes an account paying the gas
+        let response = app(layer).oneshot(request(Some(&header))).await.unwrap();
+        assert_eq!(response.status(), 402);
+
+        // The settlement is returned in X-Payment-Response
+        let tx_hash = format!("0x{}", "11".repeat(32));
+        let layer = PaymentsLayer::new(MiddlewareState::new(), config)
+            .with_verifier(Approve(payer.address(), tx_hash.clone()));
+        let response = app(layer).oneshot(request(Some(&header))).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let settled = STANDARD
+            .decode(response.headers()[PAYMENT_RESPONSE_HEADER].as_bytes())
+            .unwrap();
+        let settled: SettleResponse = serde_json::from_slice(&settled).unwrap();
+        assert!(settled.success);
+        assert_eq!(settled.payer, Some(payer.address()));
+        assert_eq!(settled.transaction, Some(tx_hash));
+        assert_eq!(settled.network, "base-sepolia");
+
+        // Facilitators only list exact with a settlement signer
+        let exact = SupportedKind {
+            x402_version: 1,
+            scheme: "exact".to_string(),
+            network: "base-sepolia".to_string(),
+        };
+        let facilitator =
+            Facilitator::new().with_network("base-sepolia", 84532, "http://127.0.0.1:1");
+        assert!(!facilitator.supported().kinds.contains(&exact));
+        let facilitator = facilitator.with_settlement_signer(PrivateKeySigner::random());
+        assert!(facilitator.supported().kinds.contains(&exact));
+    }
+
+    #[tokio::test]
+    async fn test_paywall() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            paywall::prefers_html, MiddlewareConfig, MiddlewareState, PaymentRequiredResponse,
+            PaymentsLayer, PaywallConfig, Scheme, SchemeConfig,
+        };
+
+        // Browsers navigating to a page, API clients and anything else
+        assert!(prefers_html(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        ));
+        assert!(!prefers_html("*/*"));
+        assert!(!prefers_html("application/json"));
+        assert!(!prefers_html("application/json, text/html;q=0.5"));
+        assert!(!prefers_html("text/html;q=0"));
+
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::OneTimePayments,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        }]);
+        let app = |config: MiddlewareConfig| {
+            Router::new()
+                .route("/article", get(|| async { "article" }))
+                .layer(PaymentsLayer::new(MiddlewareState::new(), config))
+        };
+        let request = |accept: &str| {
+            Request::get("/article")
+                .header("Host", "example.com")
+                .header("Accept", accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let browser = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        // Without a paywall everyone gets the JSON 402
+        let response = app(config.clone()).oneshot(request(browser)).await.unwrap();
+        assert_eq!(response.status(), 402);
+        assert_eq!(response.headers()["content-type"], "application/json");
+
+        let paywall = PaywallConfig::new()
+            .with_title("Premium article")
+            .with_script("/wallet.js");
+        let app = app(config.clone().with_paywall(paywall.clone()));
+
+        let response = app.clone().oneshot(request(browser)).await.unwrap();
+        assert_eq!(response.status(), 402);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert_eq!(response.headers()["vary"], "accept");
+        let body = axum::body::to_bytes(response.into_body(), 16384)
+            .await
+            .unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("<title>Premium article</title>"));
+        assert!(page.contains(r#"<script src="/wallet.js" defer></script>"#));
+
+        // The requirements are embedded as the JSON 402 body
+        let start = page.find(r#"type="application/json">"#).unwrap() + 24;
+        let end = start + page[start..].find("</script>").unwrap();
+        let embedded: PaymentRequiredResponse = serde_json::from_str(&page[start..end]).unwrap();
+        assert_eq!(embedded.accepts[0].scheme, "one-time");
+        assert_eq!(embedded.accepts[0].resource, "http://example.com/article");
+        assert_eq!(embedded.error, "Missing required headers");
+
+        // API clients keep the JSON
+        let response = app.oneshot(request("application/json")).await.unwrap();
+        assert_eq!(response.status(), 402);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.headers()["vary"], "accept");
+
+        // Nothing in the requirements can close the script element or inject markup
+        let page = paywall
+            .clone()
+            .with_title("<b>")
+            .render(&PaymentRequiredResponse {
+                x402_version: 1,
+                accepts: embedded.accepts.clone(),
+                error: "</script><script>alert(1)</script>".to_string(),
+            });
+        assert!(!page.contains("alert(1)</script>"));
+        assert!(!page.contains("<b>"));
+
+        // Placeholders in the values aren't substituted, unknown ones are left in the page
+        let page = paywall
+            .with_title("{{script}}")
+            .with_template("<h1>{{title}}</h1>{{other}}<p>{{error}}</p>{{")
+            .render(&PaymentRequiredResponse {
+                x402_version: 1,
+                accepts: embedded.accepts,
+                error: "{{requirements}}".to_string(),
+            });
+        assert_eq!(
+            page,
+            "<h1>{{script}}</h1>{{other}}<p>{{requirements}}</p>{{"
+        );
+
+        // The configured public URL is advertised whatever Host the client sent
+        let response = Router::new()
+            .route("/article", get(|| async { "article" }))
+            .layer(PaymentsLayer::new(
+                MiddlewareState::new(),
+                config.with_public_url("https://api.example.com/"),
+            ))
+            .oneshot(
+                Request::get("/article")
+                    .header("Host", "evil.example")
+                    .header("X-Forwarded-Proto", "http")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 16384)
+            .await
+            .unwrap();
+        let required: PaymentRequiredResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            required.accepts[0].resource,
+            "https://api.example.com/article"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_factory() {
+        use crate::middleware::payment_channel::{
+            factory::{get_channel_factory_from_chain_id, register_provider},
+            types::CHANNEL_FACTORY_ADDRESS,
+        };
+
+        // Only the Base Sepolia deployment is known
+        assert_eq!(
+            get_channel_factory_from_chain_id(&84532),
+            Some(Address::from_str(CHANNEL_FACTORY_ADDRESS).unwrap())
+        );
+        assert_eq!(get_channel_factory_from_chain_id(&1), None);
+
+        // A malformed key is an error, not a panic
+        let result = register_provider(
+            "http://127.0.0.1:1".parse().unwrap(),
+            "not a key",
+            Address::ZERO,
+            U256::from(1000),
+        )
+        .await;
+        assert!(matches!(result, Err(AuthError::InvalidRequest(_))));
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_channel_resync() {
+        use axum::{routing::get, Router};
+
+        use crate::client::{ChannelManager, ClientBuilder, X402Middleware};
+        use crate::middleware::{
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig,
+        };
+
+        let signer = PrivateKeySigner::random();
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::PaymentChannels,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        }]);
+        let channel = PaymentChannel {
+            address: Address::ZERO,
+            sender: signer.address(),
+            recipient,
+            balance: U256::from(1_000_000),
+            nonce: U256::ZERO,
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(5),
+        };
+
+        // The server accepted vouchers up to nonce 4, none of them settled on-chain
+        let state = MiddlewareState::new().with_channel_state().await;
+        let channel_state = state.channel_state.read().await.clone().unwrap();
+        let mut known = channel.clone();
+        known.balance = U256::from(950_000);
+        known.nonce = U256::from(4);
+        channel_state
+            .channels
+            .write()
+            .await
+            .insert(known.channel_id, known);
+        channel_state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1_000_000),
+                spent: U256::from(50_000),
+                ..Default::default()
+            },
+        );
+        let app = Router::new()
+            .route("/api", get(|| async { "ok" }))
+            .layer(PaymentsLayer::new(state, config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // Loaded from chain, the client only knows the deposit
+        let channels = ChannelManager::new(
+            "http://127.0.0.1:1".parse().unwrap(),
+            signer.clone(),
+            Address::ZERO,
+        );
+        channels.add_channel(channel, Address::ZERO, 6);
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(X402Middleware::new(signer).with_channels(channels.clone()))
+            .build();
+
+        // The stale voucher is refused, the next one is signed on the channel the server returned
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        let tracked = channels.get_channel(U256::from(5)).await.unwrap().channel;
+        assert_eq!(
+            (tracked.nonce, tracked.balance),
+            (U256::from(6), U256::from(940_000))
+        );
+
+        // And stays in sync
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let tracked = channels.get_channel(U256::from(5)).await.unwrap().channel;
+        assert_eq!(
+            (tracked.nonce, tracked.balance),
+            (U256::from(7), U256::from(930_000))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_voucher_retry() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            types::{ChannelPayload, PaymentHeader, PaymentPayload},
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig,
+        };
+
+        let signer = PrivateKeySigner::random();
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::PaymentChannels,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient,
+            amount: "0.001".to_string(),
+            decimals: Some(6),
+        }]);
+        let channel = PaymentChannel {
+            address: Address::ZERO,
+            sender: signer.address(),
+            recipient,
+            balance: U256::from(1_000_000),
+            nonce: U256::ZERO,
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(1),
+        };
+
+        // Channel already known, nothing is read on-chain
+        let state = MiddlewareState::new().with_channel_state().await;
+        let channel_state = state.channel_state.read().await.clone().unwrap();
+        channel_state
+            .channels
+            .write()
+            .await
+            .insert(channel.channel_id, channel.clone());
+        channel_state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1_000_000),
+                ..Default::default()
+            },
+        );
+        let layer = PaymentsLayer::new(state, config);
+
+        let request = |balance: u64| {
+            let mut payment_channel = channel.clone();
+            payment_channel.balance = U256::from(balance);
+            payment_channel.nonce = U256::from(1);
+            let message = create_channel_message(
+                payment_channel.channel_id,
+                payment_channel.balance,
+                payment_channel.nonce,
+                &[],
+            );
+            let header = PaymentHeader {
+                x402_version: 1,
+                network: "base-sepolia".to_string(),
+                scheme: "channel".to_string(),
+                payload: PaymentPayload::Channel(ChannelPayload {
+                    signature: signer.sign_message_sync(&message).unwrap().to_string(),
+                    message: alloy::hex::encode(&message),
+                    payment_channel,
+                    timestamp: get_current_time(),
+                }),
+            };
+            Request::get("/api")
+                .header("X-Payment", serde_json::to_string(&header).unwrap())
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = || {
+            Router::new()
+                .route("/api", get(|| async { "ok" }))
+                .layer(layer.clone())
+        };
+
+        // Under-signed, the failure is only remembered for this voucher
+        let response = app().oneshot(request(999_500)).await.unwrap();
+        assert_ne!(response.status(), 200);
+        let response = app().oneshot(request(999_500)).await.unwrap();
+        assert_ne!(response.status(), 200);
+
+        // The corrected voucher for the same nonce is accepted right away
+        let response = app().oneshot(request(999_000)).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_channel_pricing() {
+        use std::sync::Arc;
+
+        use axum::{
+            body::Body,
+            http::{HeaderMap, Request},
+            routing::get,
+            Extension, Router,
+        };
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            types::{ChannelPayload, PaymentHeader, PaymentPayload},
+            ChannelPricer, MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig,
+        };
+
+        let signer = PrivateKeySigner::random();
+        let recipient = Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap();
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::PaymentChannels,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient,
+            amount: "0.001".to_string(),
+            decimals: Some(6),
+        }]);
+        let channel = PaymentChannel {
+            address: Address::ZERO,
+            sender: signer.address(),
+            recipient,
+            balance: U256::from(1_000_000),
+            nonce: U256::ZERO,
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(1),
+        };
+
+        let state = MiddlewareState::new().with_channel_state().await;
+        let channel_state = state.channel_state.read().await.clone().unwrap();
+        channel_state
+            .channels
+            .write()
+            .await
+            .insert(channel.channel_id, channel.clone());
+        channel_state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1_000_000),
+                ..Default::default()
+            },
+        );
+
+        // Large requests cost twice the configured amount, and the handler reports usage on top
+        let pricer: ChannelPricer = Arc::new(|request: &Request<Body>| {
+            match request
+                .headers()
+                .get("x-model")
+                .map(|model| model.as_bytes())
+            {
+                Some(b"large") => U256::from(2000),
+                _ => U256::from(1000),
+            }
+        });
+        let app = Router::new()
+            .route(
+                "/api",
+                get(
+                    |Extension(usage): Extension<UsageReport>, headers: HeaderMap| async move {
+                        let reported = headers
+                            .get("x-usage")
+                            .map(|usage| usage.to_str().unwrap().parse().unwrap())
+                            .unwrap_or(300);
+                        usage.charge(U256::from(reported));
+                        "ok"
+                    },
+                ),
+            )
+            .layer(PaymentsLayer::new(state, config).with_channel_pricer(pricer));
+        let request = |model: &str, voucher: Option<(u64, u64)>| {
+            let request = Request::get("/api").header("x-model", model);
+            match voucher {
+                Some((nonce, balance)) => {
+                    let mut payment_channel = channel.clone();
+                    payment_channel.nonce = U256::from(nonce);
+                    payment_channel.balance = U256::from(balance);
+                    let message = create_channel_message(
+                        payment_channel.channel_id,
+                        payment_channel.balance,
+                        payment_channel.nonce,
+                        &[],
+                    );
+                    let header = PaymentHeader {
+                        x402_version: 1,
+                        network: "base-sepolia".to_string(),
+                        scheme: "channel".to_string(),
+                        payload: PaymentPayload::Channel(ChannelPayload {
+                            signature: signer.sign_message_sync(&message).unwrap().to_string(),
+                            message: alloy::hex::encode(&message),
+                            payment_channel,
+                            timestamp: get_current_time(),
+                        }),
+                    };
+                    request.header("X-Payment", serde_json::to_string(&header).unwrap())
+                }
+                None => request,
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        // The 402 advertises the price of the request, not the configured amount
+        let response = app.clone().oneshot(request("large", None)).await.unwrap();
+        assert_eq!(response.status(), 402);
+        let body = axum::body::to_bytes(response.into_body(), 4096)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["accepts"][0]["maxAmountRequired"], "2000");
+        assert_eq!(body["accepts"][0]["amount"], "0.002000");
+
+        // Signed for the configured amount only
+        let response = app
+            .clone()
+            .oneshot(request("large", Some((1, 999_000))))
+            .await
+            .unwrap();
+        assert_ne!(response.status(), 200);
+
+        let response = app
+            .clone()
+            .oneshot(request("large", Some((1, 998_000))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["X-Payment-Charge"], "2300");
+        let account = channel_state.get_account(U256::from(1)).await.unwrap();
+        assert_eq!(
+            (account.spent, account.owed),
+            (U256::from(2000), U256::from(300))
+        );
+
+        // The next voucher has to cover the usage still owed as well as its own price
+        let response = app
+            .clone()
+            .oneshot(request("small", Some((2, 997_000))))
+            .await
+            .unwrap();
+        assert_ne!(response.status(), 200);
+        let response = app
+            .clone()
+            .oneshot(request("small", Some((2, 996_700))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let account = channel_state.get_account(U256::from(1)).await.unwrap();
+        assert_eq!(
+            (account.spent, account.owed),
+            (U256::from(3300), U256::from(300))
+        );
+        assert_eq!(account.remaining(), U256::from(996_400));
+        assert!(response.headers().get("X-Payment-Shortfall").is_none());
+
+        // Usage past the deposit is charged as far as it goes, the rest is reported
+        let mut usage_request = request("small", Some((3, 995_400)));
+        usage_request
+            .headers_mut()
+            .insert("x-usage", "1000000".parse().unwrap());
+        let response = app.clone().oneshot(usage_request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["X-Payment-Charge"], "996400");
+        assert_eq!(response.headers()["X-Payment-Shortfall"], "4600");
+        let account = channel_state.get_account(U256::from(1)).await.unwrap();
+        assert_eq!(account.remaining(), U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_tx_claims() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            one_time_payment::utils::create_tx_message, MiddlewareConfig, MiddlewareState,
+            PaymentsLayer, Scheme, SchemeConfig,
+        };
+
+        let payer = PrivateKeySigner::random();
+        let tx_hash = FixedBytes::<32>::repeat_byte(9);
+        let scheme_config = |scheme: Scheme| SchemeConfig {
+            scheme,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        };
+        let config = MiddlewareConfig::new(vec![
+            scheme_config(Scheme::OneTimePayments),
+            scheme_config(Scheme::Credits),
+            scheme_config(Scheme::Subscription),
+        ]);
+
+        // The transfer was verified once and credited as a deposit
+        let state = MiddlewareState::new().with_credit_state().await;
+        let credit_state = state.credit_state.read().await.clone().unwrap();
+        assert!(
+            credit_state
+                .top_up(tx_hash, payer.address(), U256::from(50000))
+                .await
+        );
+        state.tx_claims.claim(tx_hash, Scheme::Credits).unwrap();
+
+        let app = Router::new()
+            .route("/api", get(|| async { "ok" }))
+            .layer(PaymentsLayer::new(state.clone(), config));
+        let signature = payer
+            .sign_message_sync(&create_tx_message(tx_hash))
+            .unwrap()
+            .to_string();
+        let request = |scheme: &str| {
+            let payment = serde_json::json!({
+                "x402Version": 1,
+                "network": "base-sepolia",
+                "scheme": scheme,
+                "payload": { "signature": signature, "tx_hash": tx_hash.to_string() },
+            });
+            Request::get("/api")
+                .header("X-Payment", payment.to_string())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("credits")).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // The same transfer can't also pay under another scheme, no RPC is needed to tell
+        for scheme in ["one-time", "subscription"] {
+            let response = app.clone().oneshot(request(scheme)).await.unwrap();
+            assert_eq!(response.status(), 402);
+            let body = axum::body::to_bytes(response.into_body(), 4096)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(
+                body["error"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with("Payment already used for credits"),
+                "{}",
+                body
+            );
+        }
+
+        // Claims are atomic, the first scheme to record the transfer keeps it
+        let other = FixedBytes::<32>::repeat_byte(10);
+        state
+            .tx_claims
+            .claim(other, Scheme::OneTimePayments)
+            .unwrap();
+        assert!(state
+            .tx_claims
+            .claim(other, Scheme::OneTimePayments)
+            .is_ok());
+        assert!(matches!(
+            state.tx_claims.claim(other, Scheme::Subscription),
+            Err(AuthError::InvalidTransaction(_))
+        ));
+        assert_eq!(
+            state.tx_claims.claimed_by(other),
+            Some(Scheme::OneTimePayments)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_redemptions() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::middleware::{
+            one_time_payment::types::{OneTimePayment, MAX_REDEMPTIONS},
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig,
+            VerifiedPayment,
+        };
+
+        let payer = Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        let tx_hash = FixedBytes::<32>::repeat_byte(11);
+        let now = get_current_time();
+        let session = SessionConfig::new("server-secret");
+        let config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::OneTimePayments,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: Address::ZERO,
+            recipient: Address::ZERO,
+            amount: "0.01".to_string(),
+            decimals: Some(6),
+        }])
+        .with_sessions(session.clone());
+
+        // The payment was redeemed once, when the token was issued
+        let state = MiddlewareState::new().with_one_time_payment_state().await;
+        let one_time_payment_state = state.one_time_payment_state.read().await.clone().unwrap();
+        one_time_payment_state
+            .set(
+                tx_hash,
+                OneTimePayment {
+                    tx_hash,
+                    sender: payer,
+                    payment_timestamp: now,
+                    first_reedemed: now,
+                    redemptions: 1,
+                    amount: U256::from(10000),
+                },
+            )
+            .await;
+
+        let app = Router::new()
+            .route(
+                "/api/data",
+                get(|payment: VerifiedPayment| async move {
+                    assert!(payment.session);
+                    payment.remaining_redemptions.unwrap().to_string()
+                }),
+            )
+            .layer(PaymentsLayer::new(state.clone(), config));
+        let request = |token: &str| {
+            Request::get("/api/data")
+                .header("X-Payment-Session", token)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Each request with the token is one more redemption of the payment
+        let token = session
+            .issue_for_payment(
+                &payer.to_string(),
+                "one-time",
+                "/api/data",
+                None,
+                &tx_hash.to_string(),
+            )
+            .unwrap();
+        for remaining in (0..MAX_REDEMPTIONS - 1).rev() {
+            let response = app.clone().oneshot(request(&token)).await.unwrap();
+            assert_eq!(response.status(), 200);
+            let body = axum::body::to_bytes(response.into_body(), 1024)
+                .await
+                .unwrap();
+            assert_eq!(body, remaining.to_string());
+        }
+        assert_eq!(
+            one_time_payment_state
+                .get(tx_hash)
+                .await
+                .unwrap()
+                .redemptions,
+            MAX_REDEMPTIONS
+        );
+
+        // Used up, the token buys nothing more
+        let response = app.clone().oneshot(request(&token)).await.unwrap();
+        assert_eq!(response.status(), 402);
+
+        // A one-time token must name the payment it redeems
+        let token = session
+            .issue(&payer.to_string(), "one-time", "/api/data", None)
+            .unwrap();
+        let response = app.clone().oneshot(request(&token)).await.unwrap();
+        assert_eq!(response.status(), 402);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_pool_failover() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        use alloy::transports::{RpcError, TransportError, TransportErrorKind};
+
+        // Malformed urls are an error, not a panic
+        assert!(matches!(
+            get_rpc_pool("not a url"),
+            Err(AuthError::NetworkError(_))
+        ));
+
+        let config = RpcPoolConfig {
+            timeout: Duration::from_millis(100),
+            max_retries: 1,
+            initial_backoff: Duration::from_millis(1),
+            batch_window: None,
+        };
+        let pool = RpcPool::new(
+            &[
+                "http://127.0.0.1:1".to_string(),
+                "http://127.0.0.1:2".to_string(),
+            ],
+            config,
+        )
+        .unwrap();
+
+        // Every endpoint is tried on each round
+        let attempts = AtomicUsize::new(0);
+        let result: Result<(), String> = pool
+            .call(|_| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err(TransportErrorKind::custom_str("unavailable")) }
+            })
+            .await;
+        assert_eq!(result, Err("unavailable".to_string()));
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+
+        // Rate limits are retried, reverts would fail the same way anywhere
+        let error = |code: i64, message: &'static str| -> TransportError {
+            RpcError::ErrorResp(
+                serde_json::from_value(serde_json::json!({ "code": code, "message": message }))
+                    .unwrap(),
+            )
+        };
+        for (code, message, expected) in [(429, "rate limited", 4), (3, "execution reverted", 1)] {
+            let attempts = AtomicUsize::new(0);
+            let result: Result<(), String> = pool
+                .call(|_| {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    async move { Err(error(code, message)) }
+                })
+                .await;
+            assert!(result.unwrap_err().contains(message));
+            assert_eq!(attempts.load(Ordering::SeqCst), expected);
+        }
+
+        // Hanging calls time out and fail over
+        let attempts = AtomicUsize::new(0);
+        let result = pool
+            .call(|_| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if attempt == 0 {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                    Ok::<_, TransportError>(attempt)
+                }
+            })
+            .await;
+        assert_eq!(result, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_multicall_batching() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use std::time::Duration;
+
+        use alloy::{primitives::Bytes, sol_types::SolCall};
+        use axum::{routing::post, Json, Router};
+
+        use crate::middleware::multicall::{Multicall3, MULTICALL3_ADDRESS};
+
+        // Mock node answering `aggregate3` with 18 decimals for every call
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(body): Json<serde_json::Value>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    let tx = &body["params"][0];
+                    assert_eq!(
+                        Address::from_str(tx["to"].as_str().unwrap()).unwrap(),
+                        Address::from_str(MULTICALL3_ADDRESS).unwrap()
+                    );
+                    let input = tx["input"].as_str().or(tx["data"].as_str()).unwrap();
+                    let call = Multicall3::aggregate3Call::abi_decode(
+                        &alloy::hex::decode(input).unwrap(),
+                        true,
+                    )
+                    .unwrap();
+                    let results: Vec<Multicall3::Call3Result> = call
+                        .calls
+                        .iter()
+                        .map(|_| Multicall3::Call3Result {
+                            success: true,
+                            returnData: Bytes::from(U256::from(18).to_be_bytes::<32>().to_vec()),
+                        })
+                        .collect();
+                    let result = Multicall3::aggregate3Call::abi_encode_returns(&(results,));
+                    Json(serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": body["id"],
+                        "result": format!("0x{}", alloy::hex::encode(result)),
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        register_rpc_pool(
+            vec![rpc_url.clone()],
+            RpcPoolConfig {
+                batch_window: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Concurrent reads go out as one request
+        let token = Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap();
+        let (a, b, c) = tokio::join!(
+            get_token_decimals(&rpc_url, &token),
+            get_token_decimals(&rpc_url, &token),
+            get_token_decimals(&rpc_url, &token),
+        );
+        assert_eq!((a, b, c), (Ok(18), Ok(18), Ok(18)));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verification_cache() {
+        use std::time::Duration;
+
+        let cache = VerificationCache::new(30);
+        let key = "one-time:0xabc";
+
+        // Only failures retrying can't fix are cached
+        cache.record_failure(key, &AuthError::InvalidSignature);
+        cache.record_failure(key, &AuthError::TransactionNotFound);
+        assert!(cache.failure(key).is_none());
+
+        cache.record_failure(
+            key,
+            &AuthError::InvalidTransaction("Invalid recipient".to_string()),
+        );
+        assert!(matches!(
+            cache.failure(key),
+            Some(AuthError::InvalidTransaction(_))
+        ));
+        assert!(cache.failure("one-time:0xdef").is_none());
+
+        let disabled = VerificationCache::new(0);
+        disabled.record_failure(key, &AuthError::ChannelClosed);
+        assert!(disabled.failure(key).is_none());
+
+        // A second verification of the same key waits for the first one
+        let guard = cache.lock(key).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), cache.lock(key))
+                .await
+                .is_err()
+        );
+        let _other = cache.lock("one-time:0xdef").await;
+        drop(guard);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), cache.lock(key))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        let signature = "0x9dbbaab8fb419ad1fc50d2d7d0c037f6621d8fc22701b92c503d80e262081d2a";
+        assert_eq!(redact(signature), "0x9dbb…1d2a");
+        assert_eq!(redact("0x1234"), "<redacted>");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_metrics() {
+        use crate::middleware::{metrics, MiddlewareState};
+
+        let state = MiddlewareState::new().with_channel_state().await;
+        let channel_state = state.channel_state.read().await.clone().unwrap();
+        for (id, spent) in [(1, 1000), (2, 500)] {
+            channel_state.channels.write().await.insert(
+                U256::from(id),
+                PaymentChannel {
+                    address: Address::ZERO,
+                    sender: Address::ZERO,
+                    recipient: Address::ZERO,
+                    balance: U256::ZERO,
+                    nonce: U256::ZERO,
+                    expiration: U256::ZERO,
+                    channel_id: U256::from(id),
+                },
+            );
+            channel_state.accounts.write().await.insert(
+                U256::from(id),
+                ChannelAccount {
+                    deposited: U256::from(10000),
+                    spent: U256::from(spent),
+                    ..Default::default()
+                },
+            );
+        }
+        channel_state.mark_closed(U256::from(2)).await;
+
+        // Labels no other test pays with through the middleware, the registry is shared between tests
+        metrics::record_payment_accepted("subscription");
+        metrics::record_payment_rejected("one-time", &AuthError::InvalidSignature);
+        metrics::record_cache_lookup("stream", true);
+
+        let body = metrics::render(&state).await.unwrap();
+        assert!(body.contains(r#"pipegate_payments_accepted_total{scheme="subscription"} 1"#));
+        assert!(body.contains(
+            r#"pipegate_payments_rejected_total{error="InvalidSignature",scheme="one-time"} 1"#
+        ));
+        assert!(body.contains(r#"pipegate_cache_lookups_total{cache="stream",result="hit"} 1"#));
+        assert!(body.contains("pipegate_open_channels 1"));
+        assert!(body.contains("pipegate_channel_unsettled_value 1000"));
+    }
+
+    #[tokio::test]
+    async fn test_payment_events() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use std::time::Duration;
+
+        use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+
+        use crate::middleware::{
+            events::{
+                sign_webhook, ChannelSink, JsonlFileSink, PaymentEvent, WebhookSink,
+                WEBHOOK_SIGNATURE_HEADER,
+            },
+            stream_payment::types::Stream,
+            MiddlewareState,
+        };
+
+        // Webhook failing the first delivery, then checking the signature of the retry
+        let secret = b"webhook-secret".to_vec();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (delivered_tx, mut delivered) = tokio::sync::mpsc::channel::<String>(8);
+        let (counter, key) = (attempts.clone(), secret.clone());
+        let app = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap, body: String| async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                let header = headers[WEBHOOK_SIGNATURE_HEADER].to_str().unwrap();
+                let (t, v1) = header.split_once(",v1=").unwrap();
+                let timestamp = t.trim_start_matches("t=").parse().unwrap();
+                assert_eq!(sign_webhook(&key, timestamp, &body).unwrap(), v1);
+                delivered_tx.send(body).await.unwrap();
+                StatusCode::OK
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let log_path =
+            std::env::temp_dir().join(format!("pipegate-events-{}.jsonl", get_current_time()));
+        let (sender, mut received) = tokio::sync::mpsc::channel(8);
+        let state = MiddlewareState::new()
+            .with_event_sink(ChannelSink::new(sender))
+            .with_event_sink(JsonlFileSink::new(&log_path).unwrap())
+            .with_event_sink(WebhookSink::with_attempts(url, secret, 2))
+            .with_channel_state()
+            .await
+            .with_stream_state()
+            .await;
+
+        // Settling a tracked channel is reported with what was spent on it
+        let channel_state = state.channel_state.read().await.clone().unwrap();
+        let sender_address =
+            Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap();
+        channel_state.channels.write().await.insert(
+            U256::from(1),
+            PaymentChannel {
+                address: Address::ZERO,
+                sender: sender_address,
+                recipient: Address::ZERO,
+                balance: U256::ZERO,
+                nonce: U256::ZERO,
+                expiration: U256::ZERO,
+                channel_id: U256::from(1),
+            },
+        );
+        channel_state.accounts.write().await.insert(
+            U256::from(1),
+            ChannelAccount {
+                deposited: U256::from(1000),
+                spent: U256::from(400),
+                ..Default::default()
+            },
+        );
+        channel_state.mark_closed(U256::from(1)).await;
+        channel_state.mark_closed(U256::from(1)).await;
+
+        // Only streams still cached are reported as invalidated
+        let stream_state = state.stream_state.read().await.clone().unwrap();
+        stream_state
+            .set(
+                sender_address,
+                Stream {
+                    sender: sender_address,
+                    recipient: Address::ZERO,
+                    token_address: Address::ZERO,
+                    flow_rate: I96::ZERO,
+                    last_verified: 0,
+                },
+            )
+            .await;
+        stream_state.invalidate(sender_address).await;
+        stream_state.invalidate(sender_address).await;
+
+        assert_eq!(
+            received.recv().await.unwrap(),
+            PaymentEvent::ChannelSettled {
+                channel_id: U256::from(1),
+                sender: sender_address,
+                spent: U256::from(400),
+            }
+        );
+        assert_eq!(
+            received.recv().await.unwrap(),
+            PaymentEvent::StreamInvalidated {
+                sender: sender_address
+            }
+        );
+        assert!(received.try_recv().is_err());
+
+        // The first event is delivered on the retry, in order with the second
+        let first = tokio::time::timeout(Duration::from_secs(5), delivered.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let second = delivered.recv().await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let first: serde_json::Value = serde_json::from_str(&first).unwrap();
+        assert_eq!(first["event"], "channel_settled");
+        assert_eq!(first["spent"], "400");
+        assert!(second.contains(r#""event":"stream_invalidated""#));
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        std::fs::remove_file(&log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""event":"channel_settled""#));
+        assert!(lines[1].contains(r#""event":"stream_invalidated""#));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_channel_requests() {
+        let signer = PrivateKeySigner::random();
+        let state = ChannelState::new();
+        let config = PaymentChannelConfig {
+            recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
+            token_address: Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
+            amount: U256::from(1000),
+            rpc_url: "http://localhost:8545".to_string(),
+            factory_address: None,
+        };
+        let channel = PaymentChannel {
+            address: Address::from_str("0x4cf93d3b7cd9d50ecfba2082d92534e578fe46f6").unwrap(),
+            sender: signer.address(),
+            recipient: config.recipient,
+            balance: U256::from(999000),
+            nonce: U256::from(0),
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(1),
+        };
+        state
+            .channels
+            .write()
+            .await
+            .insert(channel.channel_id, channel.clone());
+        state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1000000),
+                spent: U256::from(1000),
+                ..Default::default()
+            },
+        );
+
+        // Same nonce, different amounts, only one of them can be accepted
+        let requests: Vec<SignedRequest> = [998000u64, 997000]
+            .into_iter()
+            .map(|balance| {
+                let mut payment_channel = channel.clone();
+                payment_channel.balance = U256::from(balance);
+                payment_channel.nonce = U256::from(1);
+                let message = create_channel_message(
+                    payment_channel.channel_id,
+                    payment_channel.balance,
+                    payment_channel.nonce,
+                    &[],
+                );
+                SignedRequest {
+                    signature: signer.sign_message_sync(&message).unwrap(),
+                    message,
+                    payment_channel,
+                    payment_amount: config.amount,
+                    body_bytes: Vec::new(),
+                    timestamp: get_current_time(),
+                }
+            })
+            .collect();
+
+        let handles: Vec<_> = requests
+            .into_iter()
+            .map(|request| {
+                let state = state.clone();
+                let config = config.clone();
+                tokio::spawn(
+                    async move { verify_and_update_channel(&state, &config, request).await },
+                )
+            })
+            .collect();
+
+        let mut accepted = 0;
+        for handle in handles {
+            if handle.await.unwrap().is_ok() {
+                accepted += 1;
+            }
+        }
+        assert_eq!(accepted, 1);
+
+        // The stored signature matches the stored channel
+        let (stored, signature, _) = state.get_signed_channel(U256::from(1)).await.unwrap();
+        let message = create_channel_message(stored.channel_id, stored.balance, stored.nonce, &[]);
+        assert_eq!(
+            signature.recover_address_from_msg(message).unwrap(),
+            signer.address()
+        );
+        let account = state.get_account(U256::from(1)).await.unwrap();
+        assert_eq!(account.remaining(), stored.balance);
+    }
+
+    #[tokio::test]
+    async fn test_request_binding() {
+        use axum::{async_trait, body::Body, http::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        use crate::facilitator::types::{FacilitatorRequest, SettleResponse, VerifyResponse};
+        use crate::middleware::{
+            types::{ChannelPayload, PaymentHeader, PaymentPayload},
+            MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig, Verifier,
+        };
+
+        let signer = PrivateKeySigner::random();
+        let state = ChannelState::new();
+        let config = PaymentChannelConfig {
+            recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
+            token_address: Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
+            amount: U256::from(1000),
+            rpc_url: "http://localhost:8545".to_string(),
+            factory_address: None,
+        };
+        let mut channel = PaymentChannel {
+            address: Address::from_str("0x4cf93d3b7cd9d50ecfba2082d92534e578fe46f6").unwrap(),
+            sender: signer.address(),
+            recipient: config.recipient,
+            balance: U256::from(1000000),
+            nonce: U256::from(0),
+            expiration: U256::from(get_current_time() + 3600),
+            channel_id: U256::from(1),
+        };
+        state
+            .channels
+            .write()
+            .await
+            .insert(channel.channel_id, channel.clone());
+        state.accounts.write().await.insert(
+            channel.channel_id,
+            ChannelAccount {
+                deposited: U256::from(1000000),
+                spent: U256::ZERO,
+                ..Default::default()
+            },
+        );
+
+        // Voucher signed for one request
+        let signed_hash = create_request_hash("post", "/api/data?id=1", b"{\"q\":1}");
+        assert_eq!(
+            signed_hash,
+            create_request_hash("POST", "/api/data?id=1", b"{\"q\":1}")
+        );
+        // The path and body are length-prefixed, moving bytes from one to the other changes the hash
+        assert_ne!(
+            create_request_hash("POST", "/a", b"b/c"),
+            create_request_hash("POST", "/ab/c", b"")
+        );
+        assert_ne!(
+            create_request_hash("POST", "/api", b""),
+            create_request_hash("POS", "T/api", b"")
+        );
+        channel.balance = U256::from(999000);
+        channel.nonce = U256::from(1);
+        let message = create_channel_message(
+            channel.channel_id,
+            channel.balance,
+            channel.nonce,
+            &signed_hash,
+        );
+        let signature = signer.sign_message_sync(&message).unwrap();
+
+        // Replayed against another resource, the reconstructed message doesn't match
+        let replayed = SignedRequest {
+            signature,
+            message: message.clone(),
+            payment_channel: channel.clone(),
+            payment_amount: config.amount,
+            body_bytes: create_request_hash("POST", "/api/other", b"{\"q\":1}"),
+            timestamp: get_current_time(),
+        };
+        assert!(matches!(
+            verify_and_update_channel(&state, &config, replayed).await,
+            Err(AuthError::InvalidMessage)
+        ));
+
+        let request = SignedRequest {
+            signature,
+            message,
+            payment_channel: channel,
+            payment_amount: config.amount,
+            body_bytes: signed_hash.clone(),
+            timestamp: get_current_time(),
+        };
+        assert!(verify_and_update_channel(&state, &config, request)
+            .await
+            .is_ok());
+
+        // The signed hash is kept to close the channel with
+        let (_, _, body) = state.get_signed_channel(U256::from(1)).await.unwrap();
+        assert_eq!(body.to_vec(), signed_hash);
+
+        // Delegated to a facilitator, the hash of the request is sent along with the voucher
+        struct Bound(Vec<u8>);
+
+        #[async_trait]
+        impl Verifier for Bound {
+            async fn verify(
+                &self,
+                _request: &FacilitatorRequest,
+            ) -> Result<VerifyResponse, AuthError> {
+                unreachable!("The middleware only settles")
+            }
+
+            async fn settle(
+                &self,
+                request: &FacilitatorRequest,
+            ) -> Result<SettleResponse, AuthError> {
+                assert_eq!(
+                    request.request_hash,
+                    Some(alloy::hex::encode_prefixed(&self.0))
+                );
+                let PaymentPayload::Channel(payload) = &request.payment_header.payload else {
+                    panic!("Expected a channel payment");
+                };
+                Ok(SettleResponse {
+                    success: true,
+                    error_reason: None,
+                    payer: Some(payload.payment_channel.sender),
+                    transaction: None,
+                    network: request.payment_requirements.network.clone(),
+                    payment_channel: Some(payload.payment_channel.clone()),
+                })
+            }
+        }
+
+        let mut voucher = state.get_channel(U256::from(1)).await.unwrap();
+        voucher.balance = U256::from(998000);
+        voucher.nonce = U256::from(2);
+        let message = create_channel_message(
+            voucher.channel_id,
+            voucher.balance,
+            voucher.nonce,
+            &signed_hash,
+        );
+        let header = PaymentHeader {
+            x402_version: 1,
+            network: "base-sepolia".to_string(),
+            scheme: "channel".to_string(),
+            payload: PaymentPayload::Channel(ChannelPayload {
+                signature: signer.sign_message_sync(&message).unwrap().to_string(),
+                message: alloy::hex::encode(&message),
+                payment_channel: voucher,
+                timestamp: get_current_time(),
+            }),
+        };
+        let middleware_config = MiddlewareConfig::new(vec![SchemeConfig {
+            scheme: Scheme::PaymentChannels,
+            network_rpc_url: "http://127.0.0.1:1".to_string(),
+            chain_id: 84532,
+            chain_name: "base-sepolia".to_string(),
+            token_address: config.token_address,
+            recipient: config.recipient,
+            amount: "0.001".to_string(),
+            decimals: Some(6),
+        }])
+        .with_request_binding(1024);
+        let app = Router::new()
+            .route("/api/data", post(|body: String| async move { body }))
+            .layer(
+                PaymentsLayer::new(MiddlewareState::new(), middleware_config)
+                    .with_verifier(Bound(signed_hash.clone())),
+            );
+        let response = app
+            .oneshot(
+                Request::post("/api/data?id=1")
+                    .header("X-Payment", serde_json::to_string(&header).unwrap())
+                    .body(Body::from("{\"q\":1}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, "{\"q\":1}");
+    }
+
     #[tokio::test]
     async fn test_verify_tx() {
         let rpc_url = "https://base-sepolia-rpc.publicnode.com";
@@ -46,10 +2736,10 @@ mod tests {
         let result = verify_tx(signed_payment_tx, onetime_payment_config).await;
         println!("Result: {:?}", result);
 
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
 
         let (_, is_valid) = result.unwrap();
-        assert_eq!(is_valid, true);
+        assert!(is_valid);
     }
 
     #[tokio::test]
@@ -73,8 +2763,8 @@ mod tests {
         let result = verify_stream(signed_stream, stream_payment_config).await;
         println!("Result: {:?}", result);
 
-        assert_eq!(result.is_ok(), true);
-        assert_eq!(result.unwrap(), true);
+        assert!(result.is_ok());
+        assert!(result.unwrap());
     }
 
     #[tokio::test]
@@ -99,8 +2789,8 @@ mod tests {
             verify_stream_via_indexer(signed_stream, stream_payment_config).await;
         println!("Result: {:?}", result);
 
-        assert_eq!(result.is_ok(), true);
-        assert_eq!(result.unwrap(), true);
+        assert!(result.is_ok());
+        assert!(result.unwrap());
     }
 }
 
@@ -1,231 +1,68 @@
-use std::{env, str::FromStr};
+// Facilitator service, verifying and settling payments for resource servers not written in Rust
+// FACILITATOR_RPC_URLS: comma separated RPC urls of the networks served, named after their chain id
+// FACILITATOR_ADDR: address to listen on, 0.0.0.0:8000 by default
+// FACILITATOR_PRIVATE_KEY: account settling `exact` payments, the scheme is only served when set
+// FACILITATOR_RECIPIENTS: comma separated recipients served, any recipient up to `MAX_PAYEES` when unset
 
-use alloy::primitives::{Address, Bytes, U256};
-use axum::{routing::get, Router};
+use std::env;
+
+pub const DEFAULT_RPC_URLS: &str = "https://sepolia.base.org"; // Base Sepolia
+pub const DEFAULT_ADDR: &str = "0.0.0.0:8000";
 
-#[allow(deprecated)]
 #[cfg(not(target_arch = "wasm32"))]
 #[tokio::main]
 pub async fn main() {
-    use alloy::primitives::aliases::I96;
-
-    use pipegate::middleware::{
-        one_time_payment::{
-            state::OneTimePaymentState, types::OneTimePaymentConfig, OnetimePaymentMiddlewareLayer,
-        },
-        payment_channel::{
-            channel::ChannelState, types::PaymentChannelConfig, PaymentChannelMiddlewareLayer,
-        },
-        stream_payment::{
-            state::StreamState,
-            types::{StreamListenerConfig, StreamsConfig},
-            StreamListner, StreamMiddlewareLayer,
-        },
-        MiddlewareConfig, MiddlewareState, PipegateMiddlewareLayer, Scheme, SchemeConfig,
-    };
-
-    // a mock server implementation using axum
-    // build our application with a route
-    // add middleware we created for protecting routes
-    println!("Starting server...");
-
-    let rpc_url: alloy::transports::http::reqwest::Url =
-        "https://sepolia.base.org".parse().unwrap();
-
-    // **** PAYMENT CHANNEL CONFIG ****
-    // Amount is not supposed to be in the decimal format, so parsed with the decimals of that token
-    // E.g. if USDC is being used 1USDC = 1000000 after 6 decimal places in case of the USDC token
-    let payment_channel_state = ChannelState::new();
-    let payment_channel_config = PaymentChannelConfig {
-        recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        token_address: Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
-        amount: U256::from(1000), // 0.001 USDC in this case
-        rpc_url: rpc_url.to_string(),
-    };
-
-    // **** ONE TIME PAYMENT CONFIG ****
-    let onetime_payment_state = OneTimePaymentState::new();
-    let onetime_payment_config = OneTimePaymentConfig {
-        recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        token_address: Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
-        amount: U256::from(1000000), // 1 USDC
-        period_ttl_sec: None,
-        rpc_url: rpc_url.to_string(),
-    };
-
-    // **** STREAM PAYMENT CONFIG ****
-    let stream_state = StreamState::new();
-    let stream_payment_config = StreamsConfig {
-        recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        token_address: Address::from_str("0x1650581f573ead727b92073b5ef8b4f5b94d1648").unwrap(),
-        amount: "761035007610".parse::<I96>().unwrap(), // 2 USDC per month
-        cfa_forwarder: Address::from_str("0xcfA132E353cB4E398080B9700609bb008eceB125").unwrap(),
-        rpc_url: rpc_url.to_string(),
-        cache_time: 900,
-    };
-    let stream_state_clone = stream_state.clone();
-    let stream_payment_config_clone = stream_payment_config.clone();
-
-    // *** PAYMENT MIDDLEWARE LAYER ***
-    let middleware_state = MiddlewareState::new();
-    let onetime_payment = SchemeConfig::new(
-        Scheme::OneTimePayments,
-        rpc_url.to_string(),
-        Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
-        Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        "0.01".to_string(),
-    )
-    .await;
-    let stream_payment = SchemeConfig::new(
-        Scheme::SuperfluidStreams,
-        rpc_url.to_string(),
-        Address::from_str("0x1650581f573ead727b92073b5ef8b4f5b94d1648").unwrap(),
-        Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        "2".to_string(), // 2 USDC per month
-    )
-    .await;
-    let payment_channel_payment = SchemeConfig::new(
-        Scheme::PaymentChannels,
-        rpc_url.to_string(),
-        Address::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap(),
-        Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        "0.01".to_string(), // 0.001 USDC
-    )
-    .await;
-    let middleware_config = MiddlewareConfig::new(vec![
-        stream_payment,
-        onetime_payment,
-        payment_channel_payment,
-    ]);
-
-    let app = Router::new()
-        .route(
-            "/",
-            get(root).route_layer(PaymentChannelMiddlewareLayer::new(
-                payment_channel_state.clone(),
-                payment_channel_config,
-            )),
-        )
-        .route(
-            "/one-time",
-            get(one_time).route_layer(OnetimePaymentMiddlewareLayer::new(
-                onetime_payment_config,
-                onetime_payment_state,
-            )),
+    use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+    use pipegate::facilitator::Facilitator;
+
+    // Log level is read from RUST_LOG, e.g. `RUST_LOG=pipegate=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
         )
-        .route(
-            "/stream",
-            get(stream).route_layer(StreamMiddlewareLayer::new(
-                stream_payment_config,
-                stream_state,
-            )),
-        )
-        .route(
-            "/test",
-            get(root).route_layer(PipegateMiddlewareLayer::new(
-                middleware_state,
-                middleware_config,
-            )),
-        );
-
-    // Run our server on localhost:8000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-
-    // Start the stream listener
-    let stream_listener_config = StreamListenerConfig {
-        wss_url: "wss://base-sepolia-rpc.publicnode.com".to_string(),
-        cfa: Address::from_str("0x6836F23d6171D74Ef62FcF776655aBcD2bcd62Ef").unwrap(),
-    };
-    let _stream_listener = StreamListner::new(
-        stream_state_clone,
-        stream_payment_config_clone,
-        stream_listener_config,
-    )
-    .await;
-
-    println!("Listening on: http://localhost:8000");
-    axum::serve(listener, app).await.unwrap();
-}
-
-async fn root() -> &'static str {
-    "Hello, World!"
-}
-
-async fn one_time() -> &'static str {
-    "One Time Payment Authenticated"
-}
-
-async fn stream() -> &'static str {
-    "Stream Payment Authenticated"
-}
-
-use pipegate::middleware::payment_channel::{
-    channel::{close_channel, ChannelState},
-    types::PaymentChannel,
-};
-
-#[cfg(not(target_arch = "wasm32"))]
-pub async fn close_and_withdraw(_state: &ChannelState) {
-    use alloy::primitives::PrimitiveSignature;
-
-    let payment_channel = PaymentChannel {
-        address: Address::from_str("0x4cf93d3b7cd9d50ecfba2082d92534e578fe46f6").unwrap(),
-        sender: Address::from_str("0x898d0dbd5850e086e6c09d2c83a26bb5f1ff8c33").unwrap(),
-        recipient: Address::from_str("0x62c43323447899acb61c18181e34168903e033bf").unwrap(),
-        balance: U256::from(1000000),
-        nonce: U256::from(0),
-        expiration: U256::from(1734391330),
-        channel_id: U256::from(1),
-    };
-
-    let signature  = PrimitiveSignature::from_str("0x9dbbaab8fb419ad1fc50d2d7d0c037f6621d8fc22701b92c503d80e262081d2a11343599127d064b9ca054cd0ae29c7025394f658b47b4c5c102bfd631d7bcb91b").unwrap();
-
-    let rpc_url: alloy::transports::http::reqwest::Url =
-        "https://base-sepolia-rpc.publicnode.com".parse().unwrap();
-
-    let private_key = env::var("PRIVATE_KEY").expect("PRIVATE_KEY must be set");
-
-    let raw_body = Bytes::from("0x");
-
-    let tx_hash = close_channel(
-        rpc_url,
-        private_key.as_str(),
-        &payment_channel,
-        &signature,
-        raw_body,
-    );
-
-    println!("Transaction Hash: {:?}", tx_hash.await);
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-pub async fn close_and_withdraw_from_state(_state: &ChannelState) {
-    use pipegate::middleware::{payment_channel::channel::close_channel_from_state, PaymentsState};
-
-    let payment_state = PaymentsState::new();
-    let channel_state = payment_state
-        .channel_state
-        .clone()
-        .read()
-        .await
-        .as_ref()
-        .unwrap()
-        .clone();
-
-    let rpc_url: alloy::transports::http::reqwest::Url =
-        "https://base-sepolia-rpc.publicnode.com".parse().unwrap();
-
-    let private_key = env::var("PRIVATE_KEY").expect("PRIVATE_KEY must be set");
-
-    let raw_body = Bytes::from("0x");
-
-    let tx_hash = close_channel_from_state(
-        &channel_state,
-        rpc_url,
-        private_key.as_str(),
-        U256::from(1),
-        raw_body,
-    );
-
-    println!("Transaction Hash: {:?}", tx_hash.await);
+        .init();
+
+    let rpc_urls =
+        env::var("FACILITATOR_RPC_URLS").unwrap_or_else(|_| DEFAULT_RPC_URLS.to_string());
+    let addr = env::var("FACILITATOR_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+
+    let mut facilitator = Facilitator::new();
+    for rpc_url in rpc_urls
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+    {
+        facilitator = facilitator
+            .add_network(rpc_url)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to add network {}: {}", rpc_url, e));
+    }
+    if let Ok(private_key) = env::var("FACILITATOR_PRIVATE_KEY") {
+        let signer: PrivateKeySigner = private_key
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid FACILITATOR_PRIVATE_KEY: {}", e));
+        tracing::info!(address = %signer.address(), "Settling exact payments");
+        facilitator = facilitator.with_settlement_signer(signer);
+    }
+    if let Ok(recipients) = env::var("FACILITATOR_RECIPIENTS") {
+        for recipient in recipients
+            .split(',')
+            .map(str::trim)
+            .filter(|recipient| !recipient.is_empty())
+        {
+            let recipient: Address = recipient
+                .parse()
+                .unwrap_or_else(|e| panic!("Invalid recipient {}: {}", recipient, e));
+            tracing::info!(%recipient, "Serving recipient");
+            facilitator = facilitator.with_recipient(recipient);
+        }
+    }
+    for network in facilitator.networks() {
+        tracing::info!(network = %network.name, chain_id = network.chain_id, "Serving network");
+    }
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    tracing::info!("Facilitator listening on: http://{}", addr);
+    axum::serve(listener, facilitator.router()).await.unwrap();
 }
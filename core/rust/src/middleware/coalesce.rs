@@ -0,0 +1,109 @@
+// Single-flight verification and negative cache
+// Concurrent requests paying with the same tx hash, stream sender or channel wait for the first verification
+// instead of each hitting the RPC, and definitive failures are answered from memory for a short time
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::{error::AuthError, middleware::utils::get_current_time};
+
+pub const NEGATIVE_CACHE_TTL_SEC: u64 = 30; // default time a definitive failure is remembered
+
+#[derive(Clone, Debug)]
+pub struct VerificationCache {
+    inflight: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>, // one lock per payment being verified
+    failures: Arc<Mutex<HashMap<String, (AuthError, u64)>>>,    // failure and when it expires
+    pub ttl_sec: u64,                                           // 0 disables the negative cache
+}
+
+impl Default for VerificationCache {
+    fn default() -> Self {
+        Self::new(NEGATIVE_CACHE_TTL_SEC)
+    }
+}
+
+impl VerificationCache {
+    pub fn new(ttl_sec: u64) -> Self {
+        Self {
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            ttl_sec,
+        }
+    }
+
+    /// Wait for any verification of `key` in flight, held until the payment is verified and stored
+    pub async fn lock(&self, key: &str) -> VerificationGuard {
+        let lock = {
+            let mut inflight = self.inflight.lock().unwrap();
+            inflight.entry(key.to_string()).or_default().clone()
+        };
+
+        VerificationGuard {
+            guard: Some(lock.lock_owned().await),
+            key: key.to_string(),
+            inflight: self.inflight.clone(),
+        }
+    }
+
+    /// Failure recorded for `key`, if it hasn't expired yet
+    pub fn failure(&self, key: &str) -> Option<AuthError> {
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get(key) {
+            Some((error, expires_at)) if *expires_at > get_current_time() => Some(error.clone()),
+            Some(_) => {
+                failures.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Remember a failure for `key`, only if retrying can't change the outcome
+    pub fn record_failure(&self, key: &str, error: &AuthError) {
+        if self.ttl_sec == 0 || !is_definitive(error) {
+            return;
+        }
+
+        let now = get_current_time();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, expires_at)| *expires_at > now);
+        failures.insert(key.to_string(), (error.clone(), now + self.ttl_sec));
+    }
+}
+
+// Failures coming from the payment itself, not from the request (signature, timestamp, nonce)
+// or a transient state (pending transaction, RPC errors, balance yet to be topped up)
+fn is_definitive(error: &AuthError) -> bool {
+    matches!(
+        error,
+        AuthError::InvalidTransaction(_)
+            | AuthError::InvalidStream(_)
+            | AuthError::InvalidChannel(_)
+            | AuthError::ChannelClosed
+            | AuthError::Expired
+    )
+}
+
+/// Held while a payment is verified, the lock is dropped from the cache once nobody waits on it
+pub struct VerificationGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    key: String,
+    inflight: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl Drop for VerificationGuard {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+
+        let mut inflight = self.inflight.lock().unwrap();
+        if let Some(lock) = inflight.get(&self.key) {
+            if Arc::strong_count(lock) == 1 {
+                inflight.remove(&self.key);
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// Payment events, reported to the sinks registered on the middleware state
+// Sinks are called inline on the request path or from the chain listeners, so they hand events off instead of blocking
+
+use std::sync::{Arc, RwLock};
+
+use alloy::primitives::{Address, U256};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use hmac::{Hmac, Mac};
+#[cfg(not(target_arch = "wasm32"))]
+use sha2::Sha256;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::{debug, warn};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{error::AuthError, middleware::utils::get_current_time};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Pipegate-Signature";
+#[cfg(not(target_arch = "wasm32"))]
+pub const WEBHOOK_QUEUE_CAPACITY: usize = 1024; // events waiting for delivery before new ones are dropped
+#[cfg(not(target_arch = "wasm32"))]
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 5; // deliveries tried per event
+#[cfg(not(target_arch = "wasm32"))]
+pub const WEBHOOK_RETRY_BASE_MS: u64 = 500; // first retry delay, doubled on each attempt
+#[cfg(not(target_arch = "wasm32"))]
+pub const WEBHOOK_TIMEOUT_SEC: u64 = 10; // per delivery attempt
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PaymentEvent {
+    /// A payment was verified and the request served
+    PaymentAccepted {
+        scheme: String,
+        payer: Address,
+        resource: String,
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        amount: Option<U256>, // charged for this request, only known for channels and credits
+    },
+    /// A payment was sent but refused with a 402, requests without any payment aren't reported
+    PaymentRejected {
+        scheme: Option<String>,
+        resource: String,
+        error: String,
+    },
+    /// A channel voucher was accepted, `balance` is what's left after this request
+    ChannelUpdated {
+        #[serde_as(as = "DisplayFromStr")]
+        channel_id: U256,
+        sender: Address,
+        #[serde_as(as = "DisplayFromStr")]
+        balance: U256,
+        #[serde_as(as = "DisplayFromStr")]
+        nonce: U256,
+        #[serde_as(as = "DisplayFromStr")]
+        charged: U256,
+    },
+    /// A stream was modified or cancelled on-chain and has to be verified again
+    StreamInvalidated { sender: Address },
+    /// A channel was closed on-chain, claimed by the recipient or by the sender after the timeout
+    ChannelSettled {
+        #[serde_as(as = "DisplayFromStr")]
+        channel_id: U256,
+        sender: Address,
+        #[serde_as(as = "DisplayFromStr")]
+        spent: U256,
+    },
+}
+
+pub trait PaymentEventSink: Send + Sync {
+    /// Receive an event, must return quickly and leave any I/O to a task of its own
+    fn emit(&self, event: &PaymentEvent);
+}
+
+impl<F> PaymentEventSink for F
+where
+    F: Fn(&PaymentEvent) + Send + Sync,
+{
+    fn emit(&self, event: &PaymentEvent) {
+        self(event)
+    }
+}
+
+/// Sinks shared by the middleware state and the listeners, sinks added later are seen by every clone
+#[derive(Clone, Default)]
+pub struct PaymentEvents {
+    sinks: Arc<RwLock<Vec<Arc<dyn PaymentEventSink>>>>,
+}
+
+impl PaymentEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, sink: impl PaymentEventSink + 'static) {
+        self.sinks.write().unwrap().push(Arc::new(sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.read().unwrap().is_empty()
+    }
+
+    pub fn emit(&self, event: PaymentEvent) {
+        for sink in self.sinks.read().unwrap().iter() {
+            sink.emit(&event);
+        }
+    }
+}
+
+// Event as written to files and webhooks
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize)]
+struct EventRecord<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a PaymentEvent,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn to_record(event: &PaymentEvent) -> Option<String> {
+    let record = EventRecord {
+        timestamp: get_current_time(),
+        event,
+    };
+    match serde_json::to_string(&record) {
+        Ok(json) => Some(json),
+        Err(e) => {
+            warn!(error = %e, "Failed to serialize payment event");
+            None
+        }
+    }
+}
+
+/// Forwards events to a tokio channel, dropped when the receiver falls behind
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct ChannelSink {
+    sender: mpsc::Sender<PaymentEvent>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ChannelSink {
+    pub fn new(sender: mpsc::Sender<PaymentEvent>) -> Self {
+        Self { sender }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PaymentEventSink for ChannelSink {
+    fn emit(&self, event: &PaymentEvent) {
+        if let Err(e) = self.sender.try_send(event.clone()) {
+            warn!(error = %e, "Dropping payment event");
+        }
+    }
+}
+
+/// Appends events to a file, one JSON object per line, written from a thread of its own
+#[cfg(not(target_arch = "wasm32"))]
+pub struct JsonlFileSink {
+    sender: std::sync::mpsc::Sender<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl JsonlFileSink {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| AuthError::InvalidRequest(format!("Failed to open event log: {}", e)))?;
+
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        std::thread::spawn(move || Self::write_lines(file, receiver));
+
+        Ok(Self { sender })
+    }
+
+    fn write_lines(mut file: File, receiver: std::sync::mpsc::Receiver<String>) {
+        while let Ok(line) = receiver.recv() {
+            if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                warn!(error = %e, "Failed to write payment event");
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PaymentEventSink for JsonlFileSink {
+    fn emit(&self, event: &PaymentEvent) {
+        if let Some(line) = to_record(event) {
+            let _ = self.sender.send(line);
+        }
+    }
+}
+
+/// POSTs events to a URL in order, signed with HMAC-SHA256 and retried with exponential backoff
+/// The signature header is `t=<timestamp>,v1=<hex hmac of "<timestamp>.<body>">`
+/// Must be created inside a tokio runtime
+#[cfg(not(target_arch = "wasm32"))]
+pub struct WebhookSink {
+    sender: mpsc::Sender<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WebhookSink {
+    pub fn new(url: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        Self::with_attempts(url, secret, WEBHOOK_MAX_ATTEMPTS)
+    }
+
+    /// Try each delivery up to `max_attempts` times before dropping the event
+    pub fn with_attempts(
+        url: impl Into<String>,
+        secret: impl Into<Vec<u8>>,
+        max_attempts: u32,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(WEBHOOK_QUEUE_CAPACITY);
+        tokio::spawn(Self::deliver_all(
+            url.into(),
+            secret.into(),
+            max_attempts.max(1),
+            receiver,
+        ));
+        Self { sender }
+    }
+
+    async fn deliver_all(
+        url: String,
+        secret: Vec<u8>,
+        max_attempts: u32,
+        mut receiver: mpsc::Receiver<String>,
+    ) {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SEC))
+            .build()
+            .unwrap_or_default();
+
+        while let Some(body) = receiver.recv().await {
+            let mut delay = Duration::from_millis(WEBHOOK_RETRY_BASE_MS);
+            for attempt in 1..=max_attempts {
+                match Self::deliver(&client, &url, &secret, &body).await {
+                    Ok(()) => {
+                        debug!(attempt, "Payment event delivered");
+                        break;
+                    }
+                    Err(e) if attempt < max_attempts => {
+                        debug!(attempt, error = %e, "Payment event delivery failed, retrying");
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    Err(e) => warn!(error = %e, "Payment event delivery failed, dropping it"),
+                }
+            }
+        }
+    }
+
+    async fn deliver(
+        client: &reqwest::Client,
+        url: &str,
+        secret: &[u8],
+        body: &str,
+    ) -> Result<(), String> {
+        let timestamp = get_current_time();
+        let signature = sign_webhook(secret, timestamp, body).map_err(|e| e.to_string())?;
+
+        let response = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(
+                WEBHOOK_SIGNATURE_HEADER,
+                format!("t={},v1={}", timestamp, signature),
+            )
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook responded with {}", response.status()))
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PaymentEventSink for WebhookSink {
+    fn emit(&self, event: &PaymentEvent) {
+        if let Some(body) = to_record(event) {
+            if let Err(e) = self.sender.try_send(body) {
+                warn!(error = %e, "Dropping payment event");
+            }
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>`, for receivers to check the webhook signature
+#[cfg(not(target_arch = "wasm32"))]
+pub fn sign_webhook(secret: &[u8], timestamp: u64, body: &str) -> Result<String, AuthError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| AuthError::InternalError)?;
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    Ok(alloy::hex::encode(mac.finalize().into_bytes()))
+}
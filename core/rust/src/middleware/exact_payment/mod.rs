@@ -0,0 +1,6 @@
+// x402 `exact` scheme on EVM chains, the payer signs an EIP-3009 `transferWithAuthorization` for the price
+// The authorization is checked off-chain then executed by a settlement signer paying the gas
+
+pub mod types;
+pub mod utils;
+pub mod verify;
@@ -0,0 +1,47 @@
+use alloy::{
+    primitives::{Address, PrimitiveSignature, U256},
+    sol,
+};
+
+pub const EXACT_VALIDITY_MARGIN_SEC: u64 = 6; // an authorization must stay valid this long to be settled
+
+sol! {
+    #[sol(rpc)]
+    #[allow(clippy::too_many_arguments)] // transferWithAuthorization binding
+    contract EIP3009 {
+        function transferWithAuthorization(address from, address to, uint256 value, uint256 validAfter, uint256 validBefore, bytes32 nonce, bytes signature) external;
+        function authorizationState(address authorizer, bytes32 nonce) external view returns (bool);
+        function balanceOf(address account) external view returns (uint256);
+        function name() external view returns (string);
+        function version() external view returns (string);
+    }
+
+    /// EIP-712 message signed by the payer, authorizing the transfer
+    #[derive(Debug, PartialEq, Eq)]
+    struct TransferWithAuthorization {
+        address from;
+        address to;
+        uint256 value;
+        uint256 validAfter;
+        uint256 validBefore;
+        bytes32 nonce;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SignedAuthorization {
+    pub authorization: TransferWithAuthorization,
+    pub signature: PrimitiveSignature,
+}
+
+/// Token and price of an `exact` payment, `token_name` and `token_version` form the EIP-712 domain of the token
+#[derive(Clone, Debug)]
+pub struct ExactPaymentConfig {
+    pub rpc_url: String,
+    pub chain_id: u64,
+    pub token_address: Address,
+    pub token_name: String,
+    pub token_version: String,
+    pub recipient: Address,
+    pub amount: U256, // minimum value authorized, in token base units
+}
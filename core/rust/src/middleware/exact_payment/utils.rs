@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{OnceLock, RwLock},
+};
+
+use alloy::{
+    primitives::{Address, B256},
+    sol_types::{eip712_domain, SolStruct},
+};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        exact_payment::types::{TransferWithAuthorization, EIP3009},
+        rpc::get_rpc_pool,
+    },
+};
+
+// EIP-712 domains of the USDC deployments known to x402 clients, by chain id and token
+const KNOWN_DOMAINS: [(u64, &str, &str, &str); 6] = [
+    // base
+    (
+        8453,
+        "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+        "USD Coin",
+        "2",
+    ),
+    // base-sepolia
+    (
+        84532,
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        "USDC",
+        "2",
+    ),
+    // avalanche
+    (
+        43114,
+        "0xB97EF9Ef8734C71904D8002F8b6Bc66Dd9c48a6E",
+        "USD Coin",
+        "2",
+    ),
+    // avalanche-fuji
+    (
+        43113,
+        "0x5425890298aed601595a70AB815c96711a31Bc65",
+        "USD Coin",
+        "2",
+    ),
+    // polygon
+    (
+        137,
+        "0x3c499c542cEF5E3811e1192ce70d8cC03d5c3359",
+        "USD Coin",
+        "2",
+    ),
+    // polygon-amoy
+    (
+        80002,
+        "0x41E94Eb019C0762f9Bfcf9Fb1E58725BfB0e7582",
+        "USDC",
+        "2",
+    ),
+];
+
+type DomainCache = RwLock<HashMap<(u64, Address), (String, String)>>; // name and version by chain id and token
+
+// Domains known or fetched so far, read when answering 402s
+fn domains() -> &'static DomainCache {
+    static DOMAINS: OnceLock<DomainCache> = OnceLock::new();
+    DOMAINS.get_or_init(|| {
+        let known = KNOWN_DOMAINS
+            .iter()
+            .map(|(chain_id, token, name, version)| {
+                (
+                    (*chain_id, Address::from_str(token).unwrap()),
+                    (name.to_string(), version.to_string()),
+                )
+            })
+            .collect();
+        RwLock::new(known)
+    })
+}
+
+/// Name and version of the token's EIP-712 domain, if known or fetched before
+pub fn cached_token_domain(chain_id: u64, token: &Address) -> Option<(String, String)> {
+    domains().read().unwrap().get(&(chain_id, *token)).cloned()
+}
+
+/// Name and version of the token's EIP-712 domain, fetched from `name()` and `version()` if not known
+pub async fn get_token_domain(
+    rpc_url: &str,
+    chain_id: u64,
+    token: &Address,
+) -> Result<(String, String), AuthError> {
+    if let Some(domain) = cached_token_domain(chain_id, token) {
+        return Ok(domain);
+    }
+
+    let pool = get_rpc_pool(rpc_url)?;
+    let name = pool
+        .read(*token, EIP3009::nameCall {})
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to fetch token name: {}", e)))?
+        ._0;
+    let version = pool
+        .read(*token, EIP3009::versionCall {})
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to fetch token version: {}", e)))?
+        ._0;
+
+    domains()
+        .write()
+        .unwrap()
+        .insert((chain_id, *token), (name.clone(), version.clone()));
+    Ok((name, version))
+}
+
+/// Hash the payer signs, the EIP-712 hash of the authorization in the token's domain
+pub fn authorization_hash(
+    authorization: &TransferWithAuthorization,
+    token_name: &str,
+    token_version: &str,
+    chain_id: u64,
+    token: Address,
+) -> B256 {
+    let domain = eip712_domain! {
+        name: token_name.to_string(),
+        version: token_version.to_string(),
+        chain_id: chain_id,
+        verifying_contract: token,
+    };
+    authorization.eip712_signing_hash(&domain)
+}
@@ -0,0 +1,138 @@
+use alloy::{
+    network::EthereumWallet,
+    primitives::{Address, FixedBytes, U256},
+    providers::ProviderBuilder,
+    signers::local::PrivateKeySigner,
+    transports::http::reqwest::Url,
+};
+use tracing::{debug, info};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        exact_payment::{
+            types::{ExactPaymentConfig, SignedAuthorization, EIP3009, EXACT_VALIDITY_MARGIN_SEC},
+            utils::authorization_hash,
+        },
+        rpc::get_rpc_pool,
+        utils::get_current_time,
+    },
+};
+
+/// Check an `exact` payment can be settled: signed by the payer, paying the recipient enough, within its validity
+/// window, and not used yet by a payer holding the value. Returns the payer
+pub async fn verify_authorization(
+    signed: &SignedAuthorization,
+    config: &ExactPaymentConfig,
+) -> Result<Address, AuthError> {
+    let authorization = &signed.authorization;
+
+    if authorization.to != config.recipient {
+        return Err(AuthError::InvalidTransaction(
+            "Authorization is not to the recipient".to_string(),
+        ));
+    }
+    if authorization.value < config.amount {
+        return Err(AuthError::InsufficientBalance);
+    }
+
+    let now = U256::from(get_current_time());
+    if authorization.validAfter > now {
+        return Err(AuthError::TimestampError);
+    }
+    if authorization.validBefore < now + U256::from(EXACT_VALIDITY_MARGIN_SEC) {
+        return Err(AuthError::Expired);
+    }
+
+    let hash = authorization_hash(
+        authorization,
+        &config.token_name,
+        &config.token_version,
+        config.chain_id,
+        config.token_address,
+    );
+    let signer = signed
+        .signature
+        .recover_address_from_prehash(&hash)
+        .map_err(|_| AuthError::InvalidSignature)?;
+    if signer != authorization.from {
+        return Err(AuthError::InvalidSignature);
+    }
+    debug!(payer = %authorization.from, "Authorization signature verified");
+
+    let pool = get_rpc_pool(&config.rpc_url)?;
+    let used = pool
+        .read(
+            config.token_address,
+            EIP3009::authorizationStateCall {
+                authorizer: authorization.from,
+                nonce: authorization.nonce,
+            },
+        )
+        .await
+        .map_err(|e| {
+            AuthError::ContractError(format!("Failed to fetch authorization state: {}", e))
+        })?
+        ._0;
+    if used {
+        return Err(AuthError::InvalidNonce);
+    }
+
+    let balance = pool
+        .read(
+            config.token_address,
+            EIP3009::balanceOfCall {
+                account: authorization.from,
+            },
+        )
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to fetch balance: {}", e)))?
+        ._0;
+    if balance < authorization.value {
+        return Err(AuthError::InsufficientBalance);
+    }
+
+    Ok(authorization.from)
+}
+
+/// Execute the transfer with `transferWithAuthorization`, the settlement signer pays the gas
+pub async fn settle_authorization(
+    signed: &SignedAuthorization,
+    config: &ExactPaymentConfig,
+    settlement_signer: &PrivateKeySigner,
+) -> Result<FixedBytes<32>, AuthError> {
+    let authorization = &signed.authorization;
+    let rpc_url = Url::parse(&config.rpc_url)
+        .map_err(|e| AuthError::NetworkError(format!("Invalid RPC url: {}", e)))?;
+    let provider = ProviderBuilder::new()
+        .with_recommended_fillers()
+        .wallet(EthereumWallet::from(settlement_signer.clone()))
+        .on_http(rpc_url);
+
+    let receipt = EIP3009::new(config.token_address, provider)
+        .transferWithAuthorization(
+            authorization.from,
+            authorization.to,
+            authorization.value,
+            authorization.validAfter,
+            authorization.validBefore,
+            authorization.nonce,
+            signed.signature.as_bytes().into(),
+        )
+        .send()
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to settle authorization: {}", e)))?
+        .get_receipt()
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to settle authorization: {}", e)))?;
+
+    if !receipt.status() {
+        return Err(AuthError::ContractError(format!(
+            "Transaction {} reverted",
+            receipt.transaction_hash
+        )));
+    }
+
+    info!(tx_hash = %receipt.transaction_hash, payer = %authorization.from, "Authorization settled");
+    Ok(receipt.transaction_hash)
+}
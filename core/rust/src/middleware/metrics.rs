@@ -0,0 +1,187 @@
+// Prometheus metrics for payment verification, behind the `metrics` feature
+// Counters are updated as payments are verified, channel gauges are computed from the state on each scrape
+
+use std::{sync::OnceLock, time::Duration};
+
+use alloy::primitives::U256;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{error::AuthError, middleware::MiddlewareState};
+
+struct Metrics {
+    registry: Registry,
+    payments_accepted: IntCounterVec, // scheme
+    payments_rejected: IntCounterVec, // scheme, error
+    rpc_duration: HistogramVec,       // chain, outcome
+    cache_lookups: IntCounterVec,     // cache, result
+    open_channels: IntGauge,
+    unsettled_channel_value: Gauge,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("pipegate".to_string()), None)?;
+
+        let payments_accepted = IntCounterVec::new(
+            Opts::new("payments_accepted_total", "Payments verified, per scheme"),
+            &["scheme"],
+        )?;
+        let payments_rejected = IntCounterVec::new(
+            Opts::new(
+                "payments_rejected_total",
+                "Requests answered with a 402, per scheme and error",
+            ),
+            &["scheme", "error"],
+        )?;
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rpc_duration_seconds",
+                "Latency of each RPC attempt, per chain",
+            ),
+            &["chain", "outcome"],
+        )?;
+        let cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "cache_lookups_total",
+                "Payment state lookups answered from memory (hit) or on-chain (miss)",
+            ),
+            &["cache", "result"],
+        )?;
+        let open_channels = IntGauge::new("open_channels", "Payment channels tracked and open")?;
+        let unsettled_channel_value = Gauge::new(
+            "channel_unsettled_value",
+            "Amount signed over by channel senders and not claimed yet, in token base units",
+        )?;
+
+        registry.register(Box::new(payments_accepted.clone()))?;
+        registry.register(Box::new(payments_rejected.clone()))?;
+        registry.register(Box::new(rpc_duration.clone()))?;
+        registry.register(Box::new(cache_lookups.clone()))?;
+        registry.register(Box::new(open_channels.clone()))?;
+        registry.register(Box::new(unsettled_channel_value.clone()))?;
+
+        Ok(Self {
+            registry,
+            payments_accepted,
+            payments_rejected,
+            rpc_duration,
+            cache_lookups,
+            open_channels,
+            unsettled_channel_value,
+        })
+    }
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("Metrics are registered once"))
+}
+
+/// Registry holding the crate metrics, all prefixed with `pipegate_`
+pub fn registry() -> &'static Registry {
+    &metrics().registry
+}
+
+pub(crate) fn record_payment_accepted(scheme: &str) {
+    metrics()
+        .payments_accepted
+        .with_label_values(&[scheme])
+        .inc();
+}
+
+pub(crate) fn record_payment_rejected(scheme: &str, error: &AuthError) {
+    metrics()
+        .payments_rejected
+        .with_label_values(&[scheme, error_label(error)])
+        .inc();
+}
+
+pub(crate) fn observe_rpc(chain_id: Option<u64>, elapsed: Duration, success: bool) {
+    let chain = chain_id.map_or_else(|| "unknown".to_string(), |id| id.to_string());
+    let outcome = if success { "success" } else { "error" };
+    metrics()
+        .rpc_duration
+        .with_label_values(&[&chain, outcome])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub(crate) fn record_cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics()
+        .cache_lookups
+        .with_label_values(&[cache, result])
+        .inc();
+}
+
+/// Refresh the channel gauges from `state` and encode every metric in the Prometheus text format
+pub async fn render(state: &MiddlewareState) -> Result<String, AuthError> {
+    let channel_state = state.channel_state.read().await.clone();
+    if let Some(channel_state) = channel_state {
+        let channels = channel_state.channels.read().await;
+        let accounts = channel_state.accounts.read().await;
+        let closed = channel_state.closed_channels.read().await;
+
+        let open = channels.keys().filter(|id| !closed.contains(*id)).count();
+        let unsettled = accounts
+            .iter()
+            .filter(|(id, _)| !closed.contains(*id))
+            .fold(U256::ZERO, |total, (_, account)| total + account.spent);
+
+        metrics().open_channels.set(open as i64);
+        metrics()
+            .unsettled_channel_value
+            .set(unsettled.to_string().parse().unwrap_or(f64::MAX));
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&registry().gather(), &mut buffer)
+        .map_err(|_| AuthError::InternalError)?;
+    String::from_utf8(buffer).map_err(|_| AuthError::InternalError)
+}
+
+/// Axum handler serving the metrics, e.g. `.route("/metrics", get(metrics_handler)).with_state(state)`
+pub async fn metrics_handler(State(state): State<MiddlewareState>) -> Response {
+    match render(&state).await {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+fn error_label(error: &AuthError) -> &'static str {
+    match error {
+        AuthError::MissingHeaders => "MissingHeaders",
+        AuthError::InvalidHeaders(_) => "InvalidHeaders",
+        AuthError::TimestampError => "TimestampError",
+        AuthError::InvalidSignature => "InvalidSignature",
+        AuthError::InsufficientBalance => "InsufficientBalance",
+        AuthError::Expired => "Expired",
+        AuthError::InvalidNonce => "InvalidNonce",
+        AuthError::InvalidChannel(_) => "InvalidChannel",
+        AuthError::ChannelNotFound => "ChannelNotFound",
+        AuthError::ChannelClosed => "ChannelClosed",
+        AuthError::RateLimitExceeded => "RateLimitExceeded",
+        AuthError::ContractError(_) => "ContractError",
+        AuthError::NetworkError(_) => "NetworkError",
+        AuthError::InvalidConfig => "InvalidConfig",
+        AuthError::InvalidMessage => "InvalidMessage",
+        AuthError::InvalidRequest(_) => "InvalidRequest",
+        AuthError::InternalError => "InternalError",
+        AuthError::TransactionNotFound => "TransactionNotFound",
+        AuthError::InvalidTransaction(_) => "InvalidTransaction",
+        AuthError::InvalidStream(_) => "InvalidStream",
+        AuthError::InvalidSender => "InvalidSender",
+        AuthError::SchemeNotAccepted => "SchemeNotAccepted",
+        AuthError::SubscriptionExpired => "SubscriptionExpired",
+        AuthError::PolicyViolation(_) => "PolicyViolation",
+        AuthError::FacilitatorRejected(_) => "FacilitatorRejected",
+    }
+}
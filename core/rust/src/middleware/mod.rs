@@ -1,41 +1,95 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod coalesce;
+pub mod events;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod exact_payment;
+#[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod multicall;
 pub mod one_time_payment;
 pub mod payment_channel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod paywall;
+pub mod rpc;
 pub mod stream_payment;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod session;
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) mod state;
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) mod types;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod verifier;
 
-mod utils;
+pub(crate) mod utils;
 
-use alloy::primitives::{aliases::I96, utils::parse_units, Address};
+use alloy::primitives::{
+    utils::{format_units, parse_units},
+    Address, FixedBytes, U256,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use alloy::signers::local::PrivateKeySigner;
+#[cfg(not(target_arch = "wasm32"))]
+use axum::http::header::ACCEPT;
 use axum::{body::Body, http::Request, response::Response};
-use std::{future::Future, pin::Pin, str::FromStr};
+#[cfg(not(target_arch = "wasm32"))]
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{future::Future, pin::Pin, str::FromStr, sync::Arc};
 use tower::{Layer, Service};
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use paywall::PaywallConfig;
 #[cfg(not(target_arch = "wasm32"))]
 pub use state::MiddlewareState;
 #[cfg(not(target_arch = "wasm32"))]
-pub use types::{MiddlewareConfig, Scheme, SchemeConfig};
+pub use types::{
+    ChannelPricer, MiddlewareConfig, PaymentHeader, PaymentPayload, PaymentRequiredAccept,
+    PaymentRequiredResponse, Scheme, SchemeConfig, UsageReport, VerifiedPayment,
+    PAYMENT_RESPONSE_HEADER,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use verifier::{RemoteVerifier, Verifier};
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::{
     error::AuthError,
+    facilitator::{
+        types::{FacilitatorRequest, SettleResponse},
+        FACILITATOR_SCHEMES, FACILITATOR_X402_VERSION,
+    },
     middleware::{
-        one_time_payment::{types::OneTimePaymentConfig, verify::verify_tx},
+        events::PaymentEvent,
+        exact_payment::{
+            types::ExactPaymentConfig,
+            utils::get_token_domain,
+            verify::{settle_authorization, verify_authorization},
+        },
+        one_time_payment::{
+            types::{
+                OneTimePaymentConfig, MAX_REDEMPTIONS, SESSION_TTL_SEC, SUBSCRIPTION_PERIOD_SEC,
+            },
+            utils::create_tx_message,
+            verify::{verify_deposit, verify_tx},
+        },
         payment_channel::{
+            factory::get_channel_factory_from_chain_id,
             types::{PaymentChannel, PaymentChannelConfig},
-            utils::modify_headers_axum,
+            utils::{create_request_hash, modify_headers_axum},
             verify::verify_and_update_channel,
         },
+        session::{SessionClaims, SESSION_HEADER},
         stream_payment::{
             types::{StreamsConfig, CFA_V1_FORWARDER_ADDRESS},
+            utils::monthly_flow_rate,
             verify::verify_stream,
         },
-        types::{PaymentHeader, PaymentPayload},
         utils::{
-            get_current_time, parse_channel_payload, parse_onetime_payload, parse_stream_payload,
+            get_current_time, parse_channel_payload, parse_exact_payload, parse_onetime_payload,
+            parse_stream_payload,
         },
     },
 };
@@ -54,6 +108,9 @@ pub type PaymentsConfig = MiddlewareConfig;
 pub struct PipegateMiddlewareLayer {
     pub state: MiddlewareState,
     pub config: MiddlewareConfig,
+    pub channel_pricer: Option<ChannelPricer>,
+    pub verifier: Option<Arc<dyn Verifier>>,
+    pub settlement_signer: Option<PrivateKeySigner>,
 }
 
 /// Preferred alias: use `PaymentsLayer` in new code (added in 0.6.0)
@@ -64,7 +121,33 @@ pub type PaymentsLayer = PipegateMiddlewareLayer;
 #[cfg(not(target_arch = "wasm32"))]
 impl PipegateMiddlewareLayer {
     pub fn new(state: MiddlewareState, config: MiddlewareConfig) -> Self {
-        Self { state, config }
+        Self {
+            state,
+            config,
+            channel_pricer: None,
+            verifier: None,
+            settlement_signer: None,
+        }
+    }
+
+    /// Price payment channel requests individually instead of charging the configured amount
+    pub fn with_channel_pricer(mut self, pricer: ChannelPricer) -> Self {
+        self.channel_pricer = Some(pricer);
+        self
+    }
+
+    /// Delegate verification to a facilitator, e.g. a `RemoteVerifier`, instead of calling the RPCs of the config
+    /// Only one-time, stream, channel and exact payments can be delegated, the request hash of bound vouchers is sent along
+    /// Channels are kept by the facilitator, so `UsageReport` charges can't be added and come back in `X-Payment-Shortfall`
+    pub fn with_verifier(mut self, verifier: impl Verifier + 'static) -> Self {
+        self.verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Account executing the transfers authorized by `exact` payments, it pays the gas of each settlement
+    pub fn with_settlement_signer(mut self, signer: PrivateKeySigner) -> Self {
+        self.settlement_signer = Some(signer);
+        self
     }
 }
 
@@ -77,16 +160,32 @@ impl<S> Layer<S> for PipegateMiddlewareLayer {
             inner: service,
             state: self.state.clone(),
             config: self.config.clone(),
+            channel_pricer: self.channel_pricer.clone(),
+            verifier: self.verifier.clone(),
+            settlement_signer: self.settlement_signer.clone(),
         }
     }
 }
 
+// What a verified payment leaves to settle once the response is known
+#[cfg(not(target_arch = "wasm32"))]
+enum Settlement {
+    Access(Scheme, Address, Option<u64>), // scheme, payer and end of access if bounded
+    Channel(PaymentChannel, U256),        // updated channel and amount charged
+    Credits(Address, U256, U256),         // payer, remaining balance and amount debited
+    Subscription(Address, u64),           // subscriber and subscription expiry
+    Exact(Address, U256),                 // payer and amount transferred
+}
+
 #[derive(Clone)]
 #[cfg(not(target_arch = "wasm32"))]
 pub struct PipegateMiddleware<S> {
     inner: S,
     state: MiddlewareState,
     config: MiddlewareConfig,
+    channel_pricer: Option<ChannelPricer>,
+    verifier: Option<Arc<dyn Verifier>>,
+    settlement_signer: Option<PrivateKeySigner>,
 }
 
 /// Preferred alias: use `Payments` in new code (added in 0.6.0)
@@ -111,20 +210,125 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, request: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
         let mut state = self.state.clone();
         let config = self.config.clone();
+        let channel_pricer = self.channel_pricer.clone();
+        let verifier = self.verifier.clone();
+        let settlement_signer = self.settlement_signer.clone();
         let mut inner = self.inner.clone();
 
-        Box::pin(async move {
-            let resource = request.uri().path();
+        // One span per request, payer and outcome are filled in once known
+        let span = info_span!(
+            "payment",
+            resource = %request.uri().path(),
+            scheme = field::Empty,
+            payer = field::Empty,
+            outcome = field::Empty,
+        );
+
+        let handled = async move {
+            let resource = request.uri().path().to_string();
+            let resource_url = resource_url(&request, config.public_url.as_deref());
+            let accept = request
+                .headers()
+                .get(ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .map(str::to_string);
+            let events = state.events.clone();
+            // Price of this request on a channel, advertised in the 402 and charged to the voucher
+            let channel_price = channel_pricer.as_ref().map(|pricer| pricer(&request));
+
+            // Scheme rejections are reported against, known once the payment header is parsed
+            let scheme_label = std::sync::OnceLock::<&'static str>::new();
 
             // Helper function to create x402 responses
             let create_x402_response =
                 |error: AuthError, payment_channel: Option<PaymentChannel>| {
-                    error.into_x402_response(&config, resource, payment_channel)
+                    Span::current().record("outcome", "rejected");
+                    debug!(error = %error, "Payment rejected");
+                    #[cfg(feature = "metrics")]
+                    metrics::record_payment_rejected(
+                        scheme_label.get().copied().unwrap_or("unknown"),
+                        &error,
+                    );
+                    if !matches!(error, AuthError::MissingHeaders) {
+                        events.emit(PaymentEvent::PaymentRejected {
+                            scheme: scheme_label.get().map(|s| s.to_string()),
+                            resource: resource.clone(),
+                            error: error.to_string(),
+                        });
+                    }
+                    error.into_negotiated_x402_response(
+                        &config,
+                        &resource_url,
+                        payment_channel,
+                        None,
+                        channel_price,
+                        accept.as_deref(),
+                    )
+                };
+
+            // A valid session token issued for this resource stands in for the payment
+            if let (Some(session), Some(token)) =
+                (&config.session, request.headers().get(SESSION_HEADER))
+            {
+                let session_result = token
+                    .to_str()
+                    .map_err(|_| AuthError::InvalidHeaders("Invalid session token".to_string()))
+                    .and_then(|token| session.verify(token))
+                    .and_then(|claims| {
+                        let accepted = Scheme::from_string(&claims.scheme)
+                            .and_then(|scheme| config.get_scheme_config(scheme))
+                            .is_some();
+                        if accepted && claims.scope == resource {
+                            Ok(claims)
+                        } else {
+                            Err(AuthError::InvalidHeaders(
+                                "Session token not valid for this resource".to_string(),
+                            ))
+                        }
+                    });
+                let session_result = match session_result {
+                    Ok(claims) => redeem_session(&state, &claims)
+                        .await
+                        .map(|remaining| (claims, remaining)),
+                    Err(e) => Err(e),
                 };
 
+                match session_result {
+                    Ok((claims, remaining_redemptions)) => {
+                        Span::current()
+                            .record("scheme", claims.scheme.as_str())
+                            .record("payer", claims.sub.as_str())
+                            .record("outcome", "session");
+
+                        let scheme_config = Scheme::from_string(&claims.scheme)
+                            .and_then(|scheme| config.get_scheme_config(scheme));
+                        if let (Some(scheme_config), Ok(payer)) =
+                            (scheme_config, Address::from_str(&claims.sub))
+                        {
+                            let mut verified = VerifiedPayment::new(scheme_config, payer);
+                            verified.expires_at = Some(claims.exp);
+                            verified.tx_hash = claims
+                                .payment
+                                .as_deref()
+                                .and_then(|payment| FixedBytes::from_str(payment).ok());
+                            verified.remaining_redemptions = remaining_redemptions;
+                            verified.session = true;
+                            request.extensions_mut().insert(verified);
+                        }
+                        return inner.call(request).await;
+                    }
+                    Err(e) => {
+                        // Fall back to the payment if one was sent along
+                        if !request.headers().contains_key("X-Payment") {
+                            return Ok(create_x402_response(e, None));
+                        }
+                    }
+                }
+            }
+
             // 1. Check for X-Payment headers -> PaymentRequiredHeader
             let headers = request.headers();
 
@@ -135,7 +339,7 @@ where
                 }
             };
 
-            let payment_json = match payment_header.to_str().map_err(|_| {
+            let payment_value = match payment_header.to_str().map_err(|_| {
                 AuthError::InvalidHeaders(
                     "X-Payment header contains invalid UTF-8 characters".to_string(),
                 )
@@ -144,12 +348,17 @@ where
                 Err(e) => return Ok(create_x402_response(e, None)),
             };
 
-            let payment: PaymentHeader = match serde_json::from_str(payment_json).map_err(|e| {
-                AuthError::InvalidHeaders(format!("Failed to parse X-Payment JSON: {}", e))
-            }) {
+            // Raw JSON from pipegate clients, base64 encoded from x402 SDKs
+            let payment = match PaymentHeader::decode(payment_value) {
                 Ok(p) => p,
                 Err(e) => return Ok(create_x402_response(e, None)),
             };
+            Span::current().record("scheme", payment.scheme.as_str());
+            let _ = scheme_label.set(
+                payment
+                    .get_scheme_enum()
+                    .map_or("unknown", |s| s.to_string()),
+            );
 
             if !payment.validate_payload_for_scheme() {
                 return Ok(create_x402_response(
@@ -161,188 +370,336 @@ where
                 ));
             }
 
+            // Channel vouchers are bound to the request when enabled, the body is buffered to be hashed and handed back to the service
+            let request_hash = match (&config.request_binding, payment.get_scheme_enum()) {
+                (Some(binding), Some(Scheme::PaymentChannels)) => {
+                    let (parts, body) = request.into_parts();
+                    let body_bytes = match axum::body::to_bytes(body, binding.max_body_bytes).await
+                    {
+                        Ok(b) => b,
+                        Err(_) => {
+                            return Ok(create_x402_response(
+                                AuthError::InvalidRequest(
+                                    "Request body too large or unreadable".to_string(),
+                                ),
+                                None,
+                            ))
+                        }
+                    };
+
+                    let path = parts
+                        .uri
+                        .path_and_query()
+                        .map(|p| p.as_str())
+                        .unwrap_or(&resource);
+                    let hash = create_request_hash(parts.method.as_str(), path, &body_bytes);
+
+                    request = Request::from_parts(parts, Body::from(body_bytes));
+                    Some(hash)
+                }
+                _ => None,
+            };
+
             // 2. Route to the correct child middleware logic based on scheme
-            let verification_result: Result<Option<PaymentChannel>, AuthError> = match payment
-                .get_scheme_enum()
-            {
-                Some(Scheme::OneTimePayments) => {
-                    if let PaymentPayload::OneTime(payload) = payment.payload {
-                        // 3. Verify if the user accepts this scheme even in the config
+            let verification_result: Result<(Settlement, VerifiedPayment), AuthError> =
+                match payment.get_scheme_enum() {
+                    Some(scheme) if verifier.is_some() => {
+                        let channel_amount = match scheme {
+                            Scheme::PaymentChannels => channel_price,
+                            _ => None,
+                        };
+                        delegate_verification(
+                            verifier.as_deref().unwrap(),
+                            &config,
+                            scheme,
+                            payment,
+                            &resource_url,
+                            channel_amount,
+                            request_hash.as_deref(),
+                        )
+                        .await
+                    }
+                    Some(Scheme::OneTimePayments) => {
+                        if let PaymentPayload::OneTime(payload) = payment.payload {
+                            // 3. Verify if the user accepts this scheme even in the config
+
+                            let scheme_config =
+                                match config.get_scheme_config(Scheme::OneTimePayments) {
+                                    Some(c) => c,
+                                    None => {
+                                        return Ok(create_x402_response(
+                                            AuthError::SchemeNotAccepted,
+                                            None,
+                                        ))
+                                    }
+                                };
 
-                        let scheme_config = match config.get_scheme_config(Scheme::OneTimePayments)
-                        {
-                            Some(c) => c,
-                            None => {
-                                return Ok(create_x402_response(AuthError::SchemeNotAccepted, None))
+                            let amount = match parse_units(
+                                &scheme_config.amount,
+                                scheme_config.decimals.unwrap_or(18),
+                            ) {
+                                Ok(a) => a.get_absolute(),
+                                Err(_) => {
+                                    return Ok(create_x402_response(
+                                        AuthError::InternalError,
+                                        None,
+                                    ));
+                                }
+                            };
+
+                            let onetime_config = OneTimePaymentConfig {
+                                rpc_url: scheme_config.network_rpc_url.clone(),
+                                token_address: scheme_config.token_address,
+                                recipient: scheme_config.recipient,
+                                amount,
+                                period_ttl_sec: None,
+                            };
+
+                            let payment = match parse_onetime_payload(&payload).await {
+                                Ok(p) => p,
+                                Err(e) => return Ok(create_x402_response(e, None)),
+                            };
+
+                            if state.one_time_payment_state.read().await.is_none() {
+                                debug!("Initialising one-time payment state");
+                                state = state.with_one_time_payment_state().await;
                             }
-                        };
 
-                        let amount = match parse_units(
-                            &scheme_config.amount,
-                            scheme_config.decimals.unwrap_or(18),
-                        ) {
-                            Ok(a) => a.get_absolute(),
-                            Err(_) => {
-                                return Ok(create_x402_response(AuthError::InternalError, None));
+                            // We need to drop the read guard and use the state directly since OneTimePaymentState methods handle their own locking
+                            let one_time_payment_state = {
+                                let guard = state.one_time_payment_state.read().await;
+                                guard.as_ref().unwrap().clone()
+                            };
+                            let current_time = get_current_time();
+
+                            // Concurrent requests with the same tx wait for its first verification
+                            let key = format!("one-time:{}", payment.tx_hash);
+                            let _inflight = state.verification_cache.lock(&key).await;
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, None));
                             }
-                        };
 
-                        let onetime_config = OneTimePaymentConfig {
-                            rpc_url: scheme_config.network_rpc_url.clone(),
-                            token_address: scheme_config.token_address,
-                            recipient: scheme_config.recipient,
-                            amount: amount,
-                            period_ttl_sec: None,
-                        };
+                            // Payer and end of the redemption session, bounding any session token issued
+                            let (payer, session_end) = if let Some(existing) =
+                                one_time_payment_state.get(payment.tx_hash).await
+                            {
+                                debug!("Found existing payment in state");
+                                #[cfg(feature = "metrics")]
+                                metrics::record_cache_lookup("one_time", true);
+
+                                // Check if the payment is still valid for redemption
+                                if one_time_payment_state
+                                    .is_valid_for_redemption_with_period(
+                                        payment.tx_hash,
+                                        current_time,
+                                        None,
+                                        None,
+                                    )
+                                    .await
+                                {
+                                    debug!("Payment is valid for redemption");
 
-                        let payment = match parse_onetime_payload(&payload).await {
-                            Ok(p) => p,
-                            Err(e) => return Ok(create_x402_response(e, None)),
-                        };
+                                    one_time_payment_state
+                                        .increment_redemptions(payment.tx_hash)
+                                        .await;
 
-                        if state.one_time_payment_state.read().await.is_none() {
-                            println!("Initialising one-time payment state");
-                            state = state.with_one_time_payment_state().await;
-                        }
+                                    (existing.sender, existing.first_reedemed + SESSION_TTL_SEC)
+                                } else {
+                                    debug!("Payment is no longer valid for redemption");
+                                    return Ok(create_x402_response(
+                                        AuthError::InvalidTransaction(
+                                            "Payment session expired or max redemptions reached"
+                                                .to_string(),
+                                        ),
+                                        None,
+                                    ));
+                                }
+                            } else {
+                                debug!("New payment - verifying transaction");
+                                #[cfg(feature = "metrics")]
+                                metrics::record_cache_lookup("one_time", false);
 
-                        // We need to drop the read guard and use the state directly since OneTimePaymentState methods handle their own locking
-                        let one_time_payment_state = {
-                            let guard = state.one_time_payment_state.read().await;
-                            guard.as_ref().unwrap().clone()
-                        };
-                        let current_time = get_current_time();
+                                if let Err(e) = state
+                                    .tx_claims
+                                    .check(payment.tx_hash, &Scheme::OneTimePayments)
+                                {
+                                    return Ok(create_x402_response(e, None));
+                                }
 
-                        if let Some(_) = one_time_payment_state.get(payment.tx_hash).await {
-                            println!("Found existing payment in state");
+                                // First time seeing this payment, verify the transaction
+                                let (new_payment, verify) =
+                                    match verify_tx(payment.clone(), onetime_config).await {
+                                        Ok(v) => v,
+                                        Err(e) => {
+                                            state.verification_cache.record_failure(&key, &e);
+                                            return Ok(create_x402_response(e, None));
+                                        }
+                                    };
 
-                            // Check if the payment is still valid for redemption
-                            if one_time_payment_state
-                                .is_valid_for_redemption_with_period(
-                                    payment.tx_hash,
-                                    current_time,
-                                    None,
-                                    None,
-                                )
-                                .await
-                            {
-                                println!("Payment is valid for redemption");
+                                if !verify {
+                                    debug!("Transaction verification failed");
+                                    return Ok(create_x402_response(
+                                        AuthError::InvalidTransaction(
+                                            "Authentication failed".to_string(),
+                                        ),
+                                        None,
+                                    ));
+                                }
+
+                                debug!("Transaction verified successfully");
+
+                                let payer = new_payment.sender;
+                                let session_end = new_payment.first_reedemed + SESSION_TTL_SEC;
 
+                                // The transfer may already have bought credits or a subscription
+                                if let Err(e) = state
+                                    .tx_claims
+                                    .claim(payment.tx_hash, Scheme::OneTimePayments)
+                                {
+                                    state.verification_cache.record_failure(&key, &e);
+                                    return Ok(create_x402_response(e, None));
+                                }
                                 one_time_payment_state
-                                    .increment_redemptions(payment.tx_hash)
+                                    .set(payment.tx_hash, new_payment)
                                     .await;
-                                println!("=== end middleware check ===");
-                            } else {
-                                println!("Payment is no longer valid for redemption");
-                                return Ok(create_x402_response(
-                                    AuthError::InvalidTransaction(
-                                        "Payment session expired or max redemptions reached"
-                                            .to_string(),
-                                    ),
-                                    None,
-                                ));
-                            }
-                        } else {
-                            println!("New payment - verifying transaction");
-
-                            // First time seeing this payment, verify the transaction
-                            let (new_payment, verify) =
-                                match verify_tx(payment.clone(), onetime_config).await {
-                                    Ok(v) => v,
-                                    Err(e) => return Ok(create_x402_response(e, None)),
-                                };
+                                debug!("Added new payment to state");
 
-                            if !verify {
-                                println!("Transaction verification failed");
-                                return Ok(create_x402_response(
-                                    AuthError::InvalidTransaction(
-                                        "Authentication failed".to_string(),
-                                    ),
-                                    None,
-                                ));
-                            }
+                                one_time_payment_state
+                                    .increment_redemptions(payment.clone().tx_hash)
+                                    .await;
 
-                            println!("Transaction verified successfully");
+                                (payer, session_end)
+                            };
 
-                            one_time_payment_state
-                                .set(payment.clone().tx_hash, new_payment)
-                                .await;
-                            println!("Added new payment to state");
+                            let mut verified = VerifiedPayment::new(scheme_config, payer);
+                            verified.tx_hash = Some(payment.tx_hash);
+                            verified.expires_at = Some(session_end);
+                            if let Some(redeemed) =
+                                one_time_payment_state.get(payment.tx_hash).await
+                            {
+                                verified.amount = Some(redeemed.amount);
+                                verified.remaining_redemptions =
+                                    Some(MAX_REDEMPTIONS.saturating_sub(redeemed.redemptions));
+                            }
 
-                            one_time_payment_state
-                                .increment_redemptions(payment.clone().tx_hash)
-                                .await;
+                            Ok((
+                                Settlement::Access(
+                                    Scheme::OneTimePayments,
+                                    payer,
+                                    Some(session_end),
+                                ),
+                                verified,
+                            ))
+                        } else {
+                            Err(AuthError::InvalidHeaders(
+                                "Expected OneTime payload for one-time payment scheme".to_string(),
+                            ))
                         }
-                        Ok(None)
-                    } else {
-                        Err(AuthError::InvalidHeaders(
-                            "Expected OneTime payload for one-time payment scheme".to_string(),
-                        ))
                     }
-                }
-                Some(Scheme::SuperfluidStreams) => {
-                    if let PaymentPayload::Stream(payload) = payment.payload {
-                        let scheme_config = match config
-                            .get_scheme_config(Scheme::SuperfluidStreams)
-                        {
-                            Some(c) => c,
-                            None => {
-                                return Ok(create_x402_response(AuthError::SchemeNotAccepted, None))
-                            }
-                        };
-
-                        let signed_stream = match parse_stream_payload(&payload).await {
-                            Ok(s) => s,
-                            Err(e) => return Ok(create_x402_response(e, None)),
-                        };
+                    Some(Scheme::SuperfluidStreams) => {
+                        if let PaymentPayload::Stream(payload) = payment.payload {
+                            let scheme_config =
+                                match config.get_scheme_config(Scheme::SuperfluidStreams) {
+                                    Some(c) => c,
+                                    None => {
+                                        return Ok(create_x402_response(
+                                            AuthError::SchemeNotAccepted,
+                                            None,
+                                        ))
+                                    }
+                                };
 
-                        let flow_rate = {
-                            let monthly_amount = match scheme_config.amount.parse::<f64>() {
-                                Ok(amount) => amount,
-                                Err(_) => {
-                                    return Ok(create_x402_response(AuthError::InternalError, None))
-                                }
+                            let signed_stream = match parse_stream_payload(&payload).await {
+                                Ok(s) => s,
+                                Err(e) => return Ok(create_x402_response(e, None)),
                             };
 
-                            let decimals = scheme_config.decimals.unwrap_or(18);
-                            let amount_with_decimals =
-                                monthly_amount * (10_f64.powi(decimals as i32));
+                            let flow_rate = match monthly_flow_rate(
+                                &scheme_config.amount,
+                                scheme_config.decimals.unwrap_or(18),
+                            ) {
+                                Ok(flow_rate) => flow_rate,
+                                Err(e) => return Ok(create_x402_response(e, None)),
+                            };
 
-                            let flow_rate_per_second =
-                                amount_with_decimals / ((365.0 / 12.0) * 24.0 * 60.0 * 60.0);
+                            let streams_config = StreamsConfig {
+                                rpc_url: scheme_config.network_rpc_url.clone(),
+                                cfa_forwarder: Address::from_str(CFA_V1_FORWARDER_ADDRESS).unwrap(),
+                                token_address: scheme_config.token_address,
+                                recipient: scheme_config.recipient,
+                                amount: flow_rate,
+                                cache_time: 900,
+                            };
 
-                            let flow_rate_i128 = flow_rate_per_second as i128;
-                            I96::try_from(flow_rate_i128).unwrap_or(I96::ZERO)
-                        };
+                            if state.stream_state.read().await.is_none() {
+                                debug!("Initialising stream state");
+                                state = state.with_stream_state().await;
+                                #[allow(unused_must_use)]
+                                state
+                                    .start_stream_listener(scheme_config.chain_id, &streams_config);
+                            }
 
-                        let streams_config = StreamsConfig {
-                            rpc_url: scheme_config.network_rpc_url.clone(),
-                            cfa_forwarder: Address::from_str(CFA_V1_FORWARDER_ADDRESS).unwrap(),
-                            token_address: scheme_config.token_address,
-                            recipient: scheme_config.recipient,
-                            amount: flow_rate,
-                            cache_time: 900,
-                        };
+                            // We need to drop the read guard and use the state directly since StreamState methods handle their own locking
+                            let stream_state = {
+                                let guard = state.stream_state.read().await;
+                                guard.as_ref().unwrap().clone()
+                            };
+                            let current_time = get_current_time();
 
-                        if state.stream_state.read().await.is_none() {
-                            println!("Initialising stream state");
-                            state = state.with_stream_state().await;
-                            #[allow(unused_must_use)]
-                            state.start_stream_listener(scheme_config.chain_id, &streams_config);
-                        }
+                            let key = format!("stream:{}", signed_stream.sender);
+                            let _inflight = state.verification_cache.lock(&key).await;
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, None));
+                            }
 
-                        // We need to drop the read guard and use the state directly since StreamState methods handle their own locking
-                        let stream_state = {
-                            let guard = state.stream_state.read().await;
-                            guard.as_ref().unwrap().clone()
-                        };
-                        let current_time = get_current_time();
+                            if let Some(stream) = stream_state.get(signed_stream.sender).await {
+                                if stream.last_verified > 0
+                                    && current_time - stream.last_verified
+                                        < streams_config.cache_time
+                                {
+                                    debug!("Stream already verified, in Cache!");
+                                    #[cfg(feature = "metrics")]
+                                    metrics::record_cache_lookup("stream", true);
+                                } else {
+                                    #[cfg(feature = "metrics")]
+                                    metrics::record_cache_lookup("stream", false);
+                                    let verify = match verify_stream(
+                                        signed_stream.clone(),
+                                        streams_config.clone(),
+                                    )
+                                    .await
+                                    {
+                                        Ok(v) => v,
+                                        Err(e) => {
+                                            state.verification_cache.record_failure(&key, &e);
+                                            return Ok(create_x402_response(e, None));
+                                        }
+                                    };
 
-                        if let Some(stream) = stream_state.get(signed_stream.sender).await {
-                            if stream.last_verified > 0
-                                && current_time - stream.last_verified < streams_config.cache_time
-                            {
-                                println!("Stream already verified, in Cache!");
+                                    if !verify {
+                                        return Ok(create_x402_response(
+                                            AuthError::InvalidTransaction(
+                                                "Stream verification failed".to_string(),
+                                            ),
+                                            None,
+                                        ));
+                                    }
+
+                                    let updated_stream =
+                                        crate::middleware::stream_payment::types::Stream {
+                                            sender: signed_stream.sender,
+                                            recipient: streams_config.recipient,
+                                            token_address: streams_config.token_address,
+                                            flow_rate: streams_config.amount,
+                                            last_verified: current_time,
+                                        };
+
+                                    stream_state.set(signed_stream.sender, updated_stream).await;
+                                    debug!("Stream verified and updated");
+                                }
                             } else {
+                                #[cfg(feature = "metrics")]
+                                metrics::record_cache_lookup("stream", false);
                                 let verify = match verify_stream(
                                     signed_stream.clone(),
                                     streams_config.clone(),
@@ -350,7 +707,10 @@ where
                                 .await
                                 {
                                     Ok(v) => v,
-                                    Err(e) => return Ok(create_x402_response(e, None)),
+                                    Err(e) => {
+                                        state.verification_cache.record_failure(&key, &e);
+                                        return Ok(create_x402_response(e, None));
+                                    }
                                 };
 
                                 if !verify {
@@ -362,167 +722,711 @@ where
                                     ));
                                 }
 
-                                let updated_stream =
-                                    crate::middleware::stream_payment::types::Stream {
-                                        sender: signed_stream.sender,
-                                        recipient: streams_config.recipient,
-                                        token_address: streams_config.token_address,
-                                        flow_rate: streams_config.amount,
-                                        last_verified: current_time,
-                                    };
+                                let new_stream = crate::middleware::stream_payment::types::Stream {
+                                    sender: signed_stream.sender,
+                                    recipient: streams_config.recipient,
+                                    token_address: streams_config.token_address,
+                                    flow_rate: streams_config.amount,
+                                    last_verified: current_time,
+                                };
 
-                                stream_state.set(signed_stream.sender, updated_stream).await;
-                                println!("Stream verified and updated");
+                                stream_state.set(signed_stream.sender, new_stream).await;
+                                debug!("New stream verified and added");
                             }
+
+                            let mut verified =
+                                VerifiedPayment::new(scheme_config, signed_stream.sender);
+                            verified.flow_rate = Some(streams_config.amount);
+
+                            Ok((
+                                Settlement::Access(
+                                    Scheme::SuperfluidStreams,
+                                    signed_stream.sender,
+                                    None,
+                                ),
+                                verified,
+                            ))
                         } else {
-                            let verify =
-                                match verify_stream(signed_stream.clone(), streams_config.clone())
-                                    .await
-                                {
-                                    Ok(v) => v,
+                            Err(AuthError::InvalidHeaders(
+                                "Expected Stream payload for stream payment scheme".to_string(),
+                            ))
+                        }
+                    }
+                    Some(Scheme::PaymentChannels) => {
+                        if let PaymentPayload::Channel(payload) = payment.payload {
+                            let scheme_config =
+                                match config.get_scheme_config(Scheme::PaymentChannels) {
+                                    Some(c) => c,
+                                    None => {
+                                        return Ok(create_x402_response(
+                                            AuthError::SchemeNotAccepted,
+                                            None,
+                                        ))
+                                    }
+                                };
+
+                            let amount = match channel_price {
+                                Some(price) => price,
+                                None => match parse_units(
+                                    &scheme_config.amount,
+                                    scheme_config.decimals.unwrap_or(18),
+                                ) {
+                                    Ok(a) => a.get_absolute(),
+                                    Err(_) => {
+                                        return Ok(create_x402_response(
+                                            AuthError::InternalError,
+                                            None,
+                                        ))
+                                    }
+                                },
+                            };
+
+                            let (signature, message, payment_channel) =
+                                match parse_channel_payload(&payload).await {
+                                    Ok(data) => data,
                                     Err(e) => return Ok(create_x402_response(e, None)),
                                 };
 
-                            if !verify {
+                            let channel_config = PaymentChannelConfig {
+                                rpc_url: scheme_config.network_rpc_url.clone(),
+                                token_address: scheme_config.token_address,
+                                recipient: scheme_config.recipient,
+                                amount,
+                                factory_address: get_channel_factory_from_chain_id(
+                                    &scheme_config.chain_id,
+                                ),
+                            };
+
+                            if state.channel_state.read().await.is_none() {
+                                debug!("Initialising channel state");
+                                state = state.with_channel_state().await;
+                                state
+                                    .start_channel_listener(
+                                        scheme_config.chain_id,
+                                        &scheme_config.network_rpc_url,
+                                    )
+                                    .await;
+                            }
+
+                            // We need to drop the read guard and use the state directly since ChannelState methods handle their own locking
+                            let channel_state = {
+                                let guard = state.channel_state.read().await;
+                                guard.as_ref().unwrap().clone()
+                            };
+
+                            let existing_channel = channel_state
+                                .get_channel_with_remaining(payment_channel.channel_id)
+                                .await;
+
+                            let signed_request =
+                                crate::middleware::payment_channel::types::SignedRequest {
+                                    message,
+                                    signature,
+                                    payment_channel: payment_channel.clone(),
+                                    payment_amount: amount,
+                                    body_bytes: request_hash.unwrap_or_default(), // empty unless request binding is enabled
+                                    timestamp: payload.timestamp,
+                                };
+
+                            // Requests on a channel are already serialized by the channel lock, only failures are cached
+                            // Keyed by voucher, a failure depends on the signed nonce and balance and a corrected one must go through
+                            let key = format!(
+                                "channel:{}:{}:{}:{}",
+                                payment_channel.channel_id,
+                                payment_channel.sender,
+                                payment_channel.nonce,
+                                payment_channel.balance
+                            );
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, existing_channel));
+                            }
+
+                            let (updated_channel, verify) = match verify_and_update_channel(
+                                &channel_state,
+                                &channel_config,
+                                signed_request,
+                            )
+                            .await
+                            {
+                                Ok((channel, verified)) => (channel, verified),
+                                Err(e) => {
+                                    state.verification_cache.record_failure(&key, &e);
+                                    return Ok(create_x402_response(e, existing_channel));
+                                }
+                            };
+
+                            if verify {
+                                debug!("Channel verified and updated");
+                            } else {
                                 return Ok(create_x402_response(
                                     AuthError::InvalidTransaction(
-                                        "Stream verification failed".to_string(),
+                                        "Channel verification failed".to_string(),
                                     ),
-                                    None,
+                                    existing_channel,
                                 ));
                             }
+                            let mut verified =
+                                VerifiedPayment::new(scheme_config, updated_channel.sender);
+                            verified.amount = Some(amount);
+                            verified.channel_id = Some(updated_channel.channel_id);
+                            verified.remaining_balance = Some(updated_channel.balance);
 
-                            let new_stream = crate::middleware::stream_payment::types::Stream {
-                                sender: signed_stream.sender,
-                                recipient: streams_config.recipient,
-                                token_address: streams_config.token_address,
-                                flow_rate: streams_config.amount,
-                                last_verified: current_time,
+                            Ok((Settlement::Channel(updated_channel, amount), verified))
+                        } else {
+                            Err(AuthError::InvalidHeaders(
+                                "Expected Channel payload for payment channel scheme".to_string(),
+                            ))
+                        }
+                    }
+                    Some(Scheme::Subscription) => {
+                        if let PaymentPayload::OneTime(payload) = payment.payload {
+                            let scheme_config = match config.get_scheme_config(Scheme::Subscription)
+                            {
+                                Some(c) => c,
+                                None => {
+                                    return Ok(create_x402_response(
+                                        AuthError::SchemeNotAccepted,
+                                        None,
+                                    ))
+                                }
                             };
 
-                            stream_state.set(signed_stream.sender, new_stream).await;
-                            println!("New stream verified and added");
-                        }
+                            // Price of one subscription period
+                            let price = match parse_units(
+                                &scheme_config.amount,
+                                scheme_config.decimals.unwrap_or(18),
+                            ) {
+                                Ok(a) => a.get_absolute(),
+                                Err(_) => {
+                                    return Ok(create_x402_response(
+                                        AuthError::InternalError,
+                                        None,
+                                    ));
+                                }
+                            };
 
-                        Ok(None)
-                    } else {
-                        Err(AuthError::InvalidHeaders(
-                            "Expected Stream payload for stream payment scheme".to_string(),
-                        ))
-                    }
-                }
-                Some(Scheme::PaymentChannels) => {
-                    if let PaymentPayload::Channel(payload) = payment.payload {
-                        let scheme_config = match config.get_scheme_config(Scheme::PaymentChannels)
-                        {
-                            Some(c) => c,
-                            None => {
-                                return Ok(create_x402_response(AuthError::SchemeNotAccepted, None))
+                            let payment = match parse_onetime_payload(&payload).await {
+                                Ok(p) => p,
+                                Err(e) => return Ok(create_x402_response(e, None)),
+                            };
+
+                            if state.one_time_payment_state.read().await.is_none() {
+                                debug!("Initialising one-time payment state");
+                                state = state.with_one_time_payment_state().await;
                             }
-                        };
 
-                        let amount = match parse_units(
-                            &scheme_config.amount,
-                            scheme_config.decimals.unwrap_or(18),
-                        ) {
-                            Ok(a) => a.get_absolute(),
-                            Err(_) => {
-                                return Ok(create_x402_response(AuthError::InternalError, None))
+                            let one_time_payment_state = {
+                                let guard = state.one_time_payment_state.read().await;
+                                guard.as_ref().unwrap().clone()
+                            };
+
+                            let key = format!("subscription:{}", payment.tx_hash);
+                            let _inflight = state.verification_cache.lock(&key).await;
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, None));
                             }
-                        };
 
-                        let (signature, message, payment_channel) =
-                            match parse_channel_payload(&payload).await {
-                                Ok(data) => data,
+                            let sender = match one_time_payment_state
+                                .get_subscription_payment(payment.tx_hash)
+                                .await
+                            {
+                                Some(existing) => {
+                                    // Payment already applied, the signature only has to come from its sender
+                                    let message = create_tx_message(payment.tx_hash);
+                                    match payment.signature.recover_address_from_msg(message) {
+                                        Ok(recovered) if recovered == existing.sender => {
+                                            existing.sender
+                                        }
+                                        _ => {
+                                            return Ok(create_x402_response(
+                                                AuthError::InvalidSignature,
+                                                None,
+                                            ))
+                                        }
+                                    }
+                                }
+                                None => {
+                                    debug!("New subscription payment - verifying transaction");
+                                    if let Err(e) = state
+                                        .tx_claims
+                                        .check(payment.tx_hash, &Scheme::Subscription)
+                                    {
+                                        return Ok(create_x402_response(e, None));
+                                    }
+
+                                    let subscription_config = OneTimePaymentConfig {
+                                        rpc_url: scheme_config.network_rpc_url.clone(),
+                                        token_address: scheme_config.token_address,
+                                        recipient: scheme_config.recipient,
+                                        amount: price,
+                                        period_ttl_sec: Some(SUBSCRIPTION_PERIOD_SEC),
+                                    };
+
+                                    let (new_payment, _) =
+                                        match verify_deposit(payment.clone(), subscription_config)
+                                            .await
+                                        {
+                                            Ok(v) => v,
+                                            Err(e) => {
+                                                state.verification_cache.record_failure(&key, &e);
+                                                return Ok(create_x402_response(e, None));
+                                            }
+                                        };
+
+                                    // Access bought is proportional to the amount paid
+                                    let duration = (U256::from(SUBSCRIPTION_PERIOD_SEC)
+                                        * new_payment.amount
+                                        / price.max(U256::from(1)))
+                                    .saturating_to::<u64>();
+
+                                    if let Err(e) =
+                                        state.tx_claims.claim(payment.tx_hash, Scheme::Subscription)
+                                    {
+                                        state.verification_cache.record_failure(&key, &e);
+                                        return Ok(create_x402_response(e, None));
+                                    }
+
+                                    let sender = new_payment.sender;
+                                    if let Some(expiry) = one_time_payment_state
+                                        .extend_subscription(new_payment, duration)
+                                        .await
+                                    {
+                                        info!(%expiry, "Subscription extended");
+                                    }
+                                    sender
+                                }
+                            };
+
+                            match one_time_payment_state.get_subscription(sender).await {
+                                Some(expiry) if expiry > get_current_time() => {
+                                    let mut verified = VerifiedPayment::new(scheme_config, sender);
+                                    verified.tx_hash = Some(payment.tx_hash);
+                                    verified.expires_at = Some(expiry);
+                                    verified.amount = one_time_payment_state
+                                        .get_subscription_payment(payment.tx_hash)
+                                        .await
+                                        .map(|p| p.amount);
+
+                                    Ok((Settlement::Subscription(sender, expiry), verified))
+                                }
+                                _ => {
+                                    return Ok(create_x402_response(
+                                        AuthError::SubscriptionExpired,
+                                        None,
+                                    ))
+                                }
+                            }
+                        } else {
+                            Err(AuthError::InvalidHeaders(
+                                "Expected OneTime payload for subscription scheme".to_string(),
+                            ))
+                        }
+                    }
+                    Some(Scheme::Credits) => {
+                        if let PaymentPayload::OneTime(payload) = payment.payload {
+                            let scheme_config = match config.get_scheme_config(Scheme::Credits) {
+                                Some(c) => c,
+                                None => {
+                                    return Ok(create_x402_response(
+                                        AuthError::SchemeNotAccepted,
+                                        None,
+                                    ))
+                                }
+                            };
+
+                            let price = match parse_units(
+                                &scheme_config.amount,
+                                scheme_config.decimals.unwrap_or(18),
+                            ) {
+                                Ok(a) => a.get_absolute(),
+                                Err(_) => {
+                                    return Ok(create_x402_response(
+                                        AuthError::InternalError,
+                                        None,
+                                    ));
+                                }
+                            };
+
+                            let deposit = match parse_onetime_payload(&payload).await {
+                                Ok(p) => p,
                                 Err(e) => return Ok(create_x402_response(e, None)),
                             };
 
-                        let channel_config = PaymentChannelConfig {
-                            rpc_url: scheme_config.network_rpc_url.clone(),
-                            token_address: scheme_config.token_address,
-                            recipient: scheme_config.recipient,
-                            amount,
-                        };
+                            if state.credit_state.read().await.is_none() {
+                                debug!("Initialising credit state");
+                                state = state.with_credit_state().await;
+                            }
+
+                            let credit_state = {
+                                let guard = state.credit_state.read().await;
+                                guard.as_ref().unwrap().clone()
+                            };
+
+                            let key = format!("credits:{}", deposit.tx_hash);
+                            let _inflight = state.verification_cache.lock(&key).await;
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, None));
+                            }
+
+                            let payer = match credit_state.get_depositor(deposit.tx_hash).await {
+                                Some(depositor) => {
+                                    // Deposit already credited, the signature only has to come from the depositor
+                                    let message = create_tx_message(deposit.tx_hash);
+                                    match deposit.signature.recover_address_from_msg(message) {
+                                        Ok(recovered) if recovered == depositor => depositor,
+                                        _ => {
+                                            return Ok(create_x402_response(
+                                                AuthError::InvalidSignature,
+                                                None,
+                                            ))
+                                        }
+                                    }
+                                }
+                                None => {
+                                    debug!("New deposit - verifying transaction");
+                                    if let Err(e) =
+                                        state.tx_claims.check(deposit.tx_hash, &Scheme::Credits)
+                                    {
+                                        return Ok(create_x402_response(e, None));
+                                    }
+
+                                    // The deposit must at least pay for one request
+                                    let deposit_config = OneTimePaymentConfig {
+                                        rpc_url: scheme_config.network_rpc_url.clone(),
+                                        token_address: scheme_config.token_address,
+                                        recipient: scheme_config.recipient,
+                                        amount: price,
+                                        period_ttl_sec: None,
+                                    };
+
+                                    let (new_payment, _) =
+                                        match verify_deposit(deposit.clone(), deposit_config).await
+                                        {
+                                            Ok(v) => v,
+                                            Err(e) => {
+                                                state.verification_cache.record_failure(&key, &e);
+                                                return Ok(create_x402_response(e, None));
+                                            }
+                                        };
+
+                                    if let Err(e) =
+                                        state.tx_claims.claim(deposit.tx_hash, Scheme::Credits)
+                                    {
+                                        state.verification_cache.record_failure(&key, &e);
+                                        return Ok(create_x402_response(e, None));
+                                    }
+
+                                    if credit_state
+                                        .top_up(
+                                            deposit.tx_hash,
+                                            new_payment.sender,
+                                            new_payment.amount,
+                                        )
+                                        .await
+                                    {
+                                        info!(amount = %new_payment.amount, "Credited deposit");
+                                    }
+                                    new_payment.sender
+                                }
+                            };
 
-                        if state.channel_state.read().await.is_none() {
-                            println!("Initialising channel state");
-                            state = state.with_channel_state().await;
+                            match credit_state.debit(payer, price).await {
+                                Ok(remaining) => {
+                                    let mut verified = VerifiedPayment::new(scheme_config, payer);
+                                    verified.amount = Some(price);
+                                    verified.tx_hash = Some(deposit.tx_hash);
+                                    verified.remaining_balance = Some(remaining);
+
+                                    Ok((Settlement::Credits(payer, remaining, price), verified))
+                                }
+                                Err(e) => {
+                                    let balance = credit_state.balance(payer).await;
+                                    Span::current()
+                                        .record("payer", field::display(payer))
+                                        .record("outcome", "rejected");
+                                    #[cfg(feature = "metrics")]
+                                    metrics::record_payment_rejected(
+                                        Scheme::Credits.to_string(),
+                                        &e,
+                                    );
+                                    events.emit(PaymentEvent::PaymentRejected {
+                                        scheme: Some(Scheme::Credits.to_string().into()),
+                                        resource: resource.clone(),
+                                        error: e.to_string(),
+                                    });
+                                    return Ok(e.into_negotiated_x402_response(
+                                        &config,
+                                        &resource_url,
+                                        None,
+                                        Some(balance),
+                                        None,
+                                        accept.as_deref(),
+                                    ));
+                                }
+                            }
+                        } else {
+                            Err(AuthError::InvalidHeaders(
+                                "Expected OneTime payload for credits scheme".to_string(),
+                            ))
                         }
+                    }
+                    Some(Scheme::Exact) => {
+                        if let PaymentPayload::Exact(payload) = payment.payload {
+                            let scheme_config = match config.get_scheme_config(Scheme::Exact) {
+                                Some(c) => c,
+                                None => {
+                                    return Ok(create_x402_response(
+                                        AuthError::SchemeNotAccepted,
+                                        None,
+                                    ))
+                                }
+                            };
 
-                        // We need to drop the read guard and use the state directly since ChannelState methods handle their own locking
-                        let channel_state = {
-                            let guard = state.channel_state.read().await;
-                            guard.as_ref().unwrap().clone()
-                        };
+                            let Some(settlement_signer) = &settlement_signer else {
+                                warn!("Exact payments need a settlement signer, see PaymentsLayer::with_settlement_signer");
+                                return Ok(create_x402_response(AuthError::InvalidConfig, None));
+                            };
 
-                        let existing_channel =
-                            channel_state.get_channel(payment_channel.channel_id).await;
-
-                        let signed_request =
-                            crate::middleware::payment_channel::types::SignedRequest {
-                                message,
-                                signature,
-                                payment_channel: payment_channel.clone(),
-                                payment_amount: amount,
-                                body_bytes: Vec::new(), // NOTE: We don't use body_bytes anymore
-                                timestamp: payload.timestamp,
+                            let amount = match parse_units(
+                                &scheme_config.amount,
+                                scheme_config.decimals.unwrap_or(18),
+                            ) {
+                                Ok(a) => a.get_absolute(),
+                                Err(_) => {
+                                    return Ok(create_x402_response(AuthError::InternalError, None))
+                                }
                             };
 
-                        let (updated_channel, verify) = match verify_and_update_channel(
-                            &channel_state,
-                            &channel_config,
-                            signed_request,
-                        )
-                        .await
-                        {
-                            Ok((channel, verified)) => (channel, verified),
-                            Err(e) => return Ok(create_x402_response(e, existing_channel)),
-                        };
+                            let signed = match parse_exact_payload(&payload).await {
+                                Ok(s) => s,
+                                Err(e) => return Ok(create_x402_response(e, None)),
+                            };
 
-                        if verify {
-                            channel_state
-                                .channels
-                                .write()
-                                .await
-                                .insert(updated_channel.channel_id, updated_channel.clone());
+                            let (token_name, token_version) = match get_token_domain(
+                                &scheme_config.network_rpc_url,
+                                scheme_config.chain_id,
+                                &scheme_config.token_address,
+                            )
+                            .await
+                            {
+                                Ok(domain) => domain,
+                                Err(e) => return Ok(create_x402_response(e, None)),
+                            };
 
-                            channel_state
-                                .update_latest_signature(updated_channel.channel_id, signature)
-                                .await;
+                            let exact_config = ExactPaymentConfig {
+                                rpc_url: scheme_config.network_rpc_url.clone(),
+                                chain_id: scheme_config.chain_id,
+                                token_address: scheme_config.token_address,
+                                token_name,
+                                token_version,
+                                recipient: scheme_config.recipient,
+                                amount,
+                            };
+
+                            // Concurrent requests with the same authorization wait for its settlement, the nonce is then used
+                            let authorization = &signed.authorization;
+                            let key =
+                                format!("exact:{}:{}", authorization.from, authorization.nonce);
+                            let _inflight = state.verification_cache.lock(&key).await;
+                            if let Some(e) = state.verification_cache.failure(&key) {
+                                return Ok(create_x402_response(e, None));
+                            }
+
+                            let settled = async {
+                                let payer = verify_authorization(&signed, &exact_config).await?;
+                                let tx_hash =
+                                    settle_authorization(&signed, &exact_config, settlement_signer)
+                                        .await?;
+                                Ok((payer, tx_hash))
+                            }
+                            .await;
 
-                            println!("Channel verified and updated");
+                            match settled {
+                                Ok((payer, tx_hash)) => {
+                                    let mut verified = VerifiedPayment::new(scheme_config, payer);
+                                    verified.amount = Some(authorization.value);
+                                    verified.tx_hash = Some(tx_hash);
+
+                                    Ok((Settlement::Exact(payer, authorization.value), verified))
+                                }
+                                Err(e) => {
+                                    state.verification_cache.record_failure(&key, &e);
+                                    Err(e)
+                                }
+                            }
                         } else {
-                            return Ok(create_x402_response(
-                                AuthError::InvalidTransaction(
-                                    "Channel verification failed".to_string(),
-                                ),
-                                existing_channel,
-                            ));
+                            Err(AuthError::InvalidHeaders(
+                                "Expected Exact payload for exact scheme".to_string(),
+                            ))
                         }
-                        Ok(Some(updated_channel.clone()))
-                    } else {
-                        Err(AuthError::InvalidHeaders(
-                            "Expected Channel payload for payment channel scheme".to_string(),
-                        ))
                     }
-                }
-                None => Err(AuthError::InvalidHeaders(
-                    "Unknown or unsupported payment scheme".to_string(),
-                )),
-            };
-
-            println!("=== end middleware check ===");
+                    None => Err(AuthError::InvalidHeaders(
+                        "Unknown or unsupported payment scheme".to_string(),
+                    )),
+                };
 
             // Handle verification result
             match verification_result {
-                Ok(payment_channel) => {
+                Ok((settlement, verified)) => {
+                    let payer = match &settlement {
+                        Settlement::Access(_, payer, _)
+                        | Settlement::Credits(payer, _, _)
+                        | Settlement::Subscription(payer, _)
+                        | Settlement::Exact(payer, _) => *payer,
+                        Settlement::Channel(channel, _) => channel.sender,
+                    };
+                    Span::current()
+                        .record("payer", field::display(payer))
+                        .record("outcome", "paid");
+                    #[cfg(feature = "metrics")]
+                    metrics::record_payment_accepted(
+                        scheme_label.get().copied().unwrap_or("unknown"),
+                    );
+
+                    let accepted = |amount: Option<U256>| {
+                        events.emit(PaymentEvent::PaymentAccepted {
+                            scheme: scheme_label.get().copied().unwrap_or("unknown").into(),
+                            payer,
+                            resource: resource.clone(),
+                            amount,
+                        })
+                    };
+
                     // Payment verified, proceed with the request
+                    let usage = UsageReport::default();
+                    request.extensions_mut().insert(usage.clone());
+                    let payment_response = payment_response_header(&verified);
+                    let paid_tx = verified.tx_hash;
+                    request.extensions_mut().insert(verified);
+
                     let response = inner.call(request).await?;
-                    if let Some(updated_channel) = payment_channel {
-                        let response = modify_headers_axum(response, &updated_channel);
-                        return Ok(response);
-                    }
+                    let mut response = match settlement {
+                        Settlement::Channel(mut updated_channel, mut charged) => {
+                            // Post-paid usage reported by the handler is owed until the next voucher covers it
+                            // What the channel can't cover, or a channel settled by a facilitator, is reported as a shortfall
+                            let mut shortfall = U256::ZERO;
+                            if let Some(usage_amount) = usage.amount() {
+                                let channel_state = state.channel_state.read().await.clone();
+                                let recorded = match channel_state {
+                                    Some(channel_state) => {
+                                        channel_state
+                                            .record_charge(updated_channel.channel_id, usage_amount)
+                                            .await
+                                    }
+                                    None => Err(AuthError::ChannelNotFound),
+                                };
+                                match recorded {
+                                    Ok((account, usage_charged)) => {
+                                        updated_channel.balance = account.remaining();
+                                        charged += usage_charged;
+                                        shortfall = usage_amount - usage_charged;
+                                    }
+                                    Err(e) => {
+                                        warn!(error = %e, "Failed to charge usage");
+                                        shortfall = usage_amount;
+                                    }
+                                }
+                            }
 
+                            events.emit(PaymentEvent::ChannelUpdated {
+                                channel_id: updated_channel.channel_id,
+                                sender: updated_channel.sender,
+                                balance: updated_channel.balance,
+                                nonce: updated_channel.nonce,
+                                charged,
+                            });
+                            accepted(Some(charged));
+
+                            let mut response = modify_headers_axum(response, &updated_channel);
+                            // The amount charged for this request including usage, the balance in X-Payment already accounts for it
+                            response
+                                .headers_mut()
+                                .insert("X-Payment-Charge", charged.to_string().parse().unwrap());
+                            insert_shortfall(&mut response, shortfall);
+                            response
+                        }
+                        Settlement::Credits(payer, mut remaining, mut charged) => {
+                            // Usage is debited from what's left, the service was already rendered
+                            let mut shortfall = U256::ZERO;
+                            if let Some(usage_amount) = usage.amount() {
+                                let credit_state = state.credit_state.read().await.clone();
+                                if let Some(credit_state) = credit_state {
+                                    let (debited, balance) =
+                                        credit_state.debit_usage(payer, usage_amount).await;
+                                    charged += debited;
+                                    remaining = balance;
+                                    shortfall = usage_amount - debited;
+                                }
+                            }
+                            accepted(Some(charged));
+
+                            let mut response = response;
+                            insert_shortfall(&mut response, shortfall);
+                            let headers = response.headers_mut();
+                            headers
+                                .insert("X-Payment-Charge", charged.to_string().parse().unwrap());
+                            headers.insert(
+                                "X-Credits-Remaining",
+                                remaining.to_string().parse().unwrap(),
+                            );
+                            response
+                        }
+                        Settlement::Access(scheme, payer, access_end) => {
+                            accepted(None);
+                            // One-time tokens draw on the redemptions of the payment, only counted where it's stored
+                            let payment = match (
+                                &scheme,
+                                state.one_time_payment_state.read().await.clone(),
+                                paid_tx,
+                            ) {
+                                (
+                                    Scheme::OneTimePayments,
+                                    Some(one_time_payment_state),
+                                    Some(tx_hash),
+                                ) if one_time_payment_state.get(tx_hash).await.is_some() => {
+                                    Some(tx_hash)
+                                }
+                                _ => None,
+                            };
+                            if scheme == Scheme::OneTimePayments && payment.is_none() {
+                                response
+                            } else {
+                                issue_session(
+                                    &config, response, &scheme, payer, &resource, access_end,
+                                    payment,
+                                )
+                            }
+                        }
+                        Settlement::Subscription(subscriber, expiry) => {
+                            accepted(None);
+                            let mut response = response;
+                            response.headers_mut().insert(
+                                "X-Subscription-Expires",
+                                expiry.to_string().parse().unwrap(),
+                            );
+                            issue_session(
+                                &config,
+                                response,
+                                &Scheme::Subscription,
+                                subscriber,
+                                &resource,
+                                Some(expiry),
+                                None,
+                            )
+                        }
+                        Settlement::Exact(_, amount) => {
+                            // Paid once for this request, there's nothing left to redeem a session with
+                            accepted(Some(amount));
+                            response
+                        }
+                    };
+
+                    // Settlement details for x402 clients
+                    if let Some(payment_response) = payment_response {
+                        response
+                            .headers_mut()
+                            .insert(PAYMENT_RESPONSE_HEADER, payment_response);
+                    }
                     Ok(response)
                 }
                 Err(auth_error) => {
@@ -530,6 +1434,250 @@ where
                     Ok(create_x402_response(auth_error, None))
                 }
             }
-        })
+        };
+
+        Box::pin(
+            async move {
+                let result = handled.await;
+                info!("Payment request handled");
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+// Verification by the facilitator, what it settled is handled like a payment verified here
+#[cfg(not(target_arch = "wasm32"))]
+async fn delegate_verification(
+    verifier: &dyn Verifier,
+    config: &MiddlewareConfig,
+    scheme: Scheme,
+    payment: PaymentHeader,
+    resource: &str,
+    channel_amount: Option<U256>, // priced by the channel pricer, in token base units
+    request_hash: Option<&[u8]>,  // channel vouchers bound to the request
+) -> Result<(Settlement, VerifiedPayment), AuthError> {
+    // Credits and subscriptions keep balances on the resource server
+    if !FACILITATOR_SCHEMES.contains(&scheme) {
+        return Err(AuthError::SchemeNotAccepted);
+    }
+    let scheme_config = config
+        .get_scheme_config(scheme.clone())
+        .ok_or(AuthError::SchemeNotAccepted)?;
+    let decimals = scheme_config.decimals.unwrap_or(18);
+
+    let (amount, price) = match channel_amount {
+        Some(amount) => (
+            amount,
+            format_units(amount, decimals).map_err(|_| AuthError::InternalError)?,
+        ),
+        None => (
+            parse_units(&scheme_config.amount, decimals)
+                .map_err(|_| AuthError::InternalError)?
+                .get_absolute(),
+            scheme_config.amount.clone(),
+        ),
+    };
+
+    let request = FacilitatorRequest {
+        x402_version: FACILITATOR_X402_VERSION,
+        payment_header: payment,
+        payment_requirements: PaymentRequiredAccept {
+            scheme: scheme.to_string().to_string(),
+            network: scheme_config.chain_name.clone(),
+            amount: price,
+            max_amount_required: Some(amount.to_string()),
+            pay_to: scheme_config.recipient.to_string(),
+            asset: scheme_config.token_address.to_string(),
+            resource: resource.to_string(),
+            description: None,
+            mime_type: String::new(),
+            max_timeout_seconds: None,
+            extra: None,
+        },
+        request_hash: request_hash.map(alloy::hex::encode_prefixed),
+    };
+    let settled = verifier.settle(&request).await?;
+    if !settled.success {
+        return Err(AuthError::FacilitatorRejected(
+            settled.error_reason.unwrap_or_default(),
+        ));
+    }
+    let payer = settled.payer.ok_or_else(|| {
+        AuthError::NetworkError("Facilitator settled without a payer".to_string())
+    })?;
+    debug!(%payer, "Payment settled by the facilitator");
+
+    let mut verified = VerifiedPayment::new(scheme_config, payer);
+    match scheme {
+        Scheme::PaymentChannels => {
+            let channel = settled.payment_channel.ok_or_else(|| {
+                AuthError::NetworkError("Facilitator settled without the channel".to_string())
+            })?;
+            verified.amount = Some(amount);
+            verified.channel_id = Some(channel.channel_id);
+            verified.remaining_balance = Some(channel.balance);
+            Ok((Settlement::Channel(channel, amount), verified))
+        }
+        Scheme::SuperfluidStreams => {
+            verified.flow_rate = monthly_flow_rate(&scheme_config.amount, decimals).ok();
+            Ok((Settlement::Access(scheme, payer, None), verified))
+        }
+        Scheme::Exact => {
+            verified.amount = Some(amount);
+            verified.tx_hash = settled
+                .transaction
+                .and_then(|tx_hash| FixedBytes::from_str(&tx_hash).ok());
+            Ok((Settlement::Exact(payer, amount), verified))
+        }
+        _ => {
+            // Redemptions are tracked by the facilitator, sessions last as long as one it grants
+            let session_end = get_current_time() + SESSION_TTL_SEC;
+            verified.amount = Some(amount);
+            verified.tx_hash = settled
+                .transaction
+                .and_then(|tx_hash| FixedBytes::from_str(&tx_hash).ok());
+            verified.expires_at = Some(session_end);
+            Ok((
+                Settlement::Access(scheme, payer, Some(session_end)),
+                verified,
+            ))
+        }
+    }
+}
+
+// Absolute URL of the requested resource for the 402, as x402 clients expect, the path if the host is unknown
+// The configured public URL is trusted over the headers, which are set by the client
+#[cfg(not(target_arch = "wasm32"))]
+fn resource_url(request: &Request<Body>, public_url: Option<&str>) -> String {
+    let path = request.uri().path();
+    if let Some(public_url) = public_url {
+        return format!("{}{}", public_url.trim_end_matches('/'), path);
+    }
+    let Some(host) = request
+        .headers()
+        .get("host")
+        .and_then(|host| host.to_str().ok())
+    else {
+        return path.to_string();
+    };
+    let scheme = request
+        .headers()
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .or(request.uri().scheme_str())
+        .unwrap_or("http");
+    format!("{}://{}{}", scheme, host, path)
+}
+
+// Base64 encoded settlement for the `X-Payment-Response` header
+#[cfg(not(target_arch = "wasm32"))]
+fn payment_response_header(verified: &VerifiedPayment) -> Option<axum::http::HeaderValue> {
+    let settled = SettleResponse {
+        success: true,
+        error_reason: None,
+        payer: Some(verified.payer),
+        transaction: verified.tx_hash.map(|tx_hash| tx_hash.to_string()),
+        network: verified.network.clone(),
+        payment_channel: None,
+    };
+    let json = serde_json::to_vec(&settled).ok()?;
+    STANDARD.encode(json).parse().ok()
+}
+
+// A request with a one-time session token is one more redemption of its payment, refused once they're used up
+// Returns the redemptions left, tokens of other schemes are only bounded by their expiry
+#[cfg(not(target_arch = "wasm32"))]
+async fn redeem_session(
+    state: &MiddlewareState,
+    claims: &SessionClaims,
+) -> Result<Option<u32>, AuthError> {
+    if Scheme::from_string(&claims.scheme) != Some(Scheme::OneTimePayments) {
+        return Ok(None);
+    }
+
+    let invalid =
+        || AuthError::InvalidHeaders("Session token not valid for this resource".to_string());
+    let tx_hash = claims
+        .payment
+        .as_deref()
+        .and_then(|payment| FixedBytes::<32>::from_str(payment).ok())
+        .ok_or_else(invalid)?;
+    let one_time_payment_state = state
+        .one_time_payment_state
+        .read()
+        .await
+        .clone()
+        .ok_or_else(invalid)?;
+
+    // Serialized with the payment itself, redemptions can't be counted twice
+    let _inflight = state
+        .verification_cache
+        .lock(&format!("one-time:{}", tx_hash))
+        .await;
+    if !one_time_payment_state
+        .is_valid_for_redemption(tx_hash, get_current_time())
+        .await
+    {
+        return Err(AuthError::InvalidTransaction(
+            "Payment session expired or max redemptions reached".to_string(),
+        ));
+    }
+    let redemptions = one_time_payment_state
+        .increment_redemptions(tx_hash)
+        .await
+        .unwrap_or(MAX_REDEMPTIONS);
+    Ok(Some(MAX_REDEMPTIONS.saturating_sub(redemptions)))
+}
+
+// Usage reported by the handler that couldn't be charged, the response was served anyway
+#[cfg(not(target_arch = "wasm32"))]
+fn insert_shortfall(response: &mut Response, shortfall: U256) {
+    if shortfall.is_zero() {
+        return;
+    }
+    warn!(%shortfall, "Usage not covered by the payment");
+    response.headers_mut().insert(
+        "X-Payment-Shortfall",
+        shortfall.to_string().parse().unwrap(),
+    );
+}
+
+// Attach a session token for the verified payer to the response, if sessions are enabled
+#[cfg(not(target_arch = "wasm32"))]
+fn issue_session(
+    config: &MiddlewareConfig,
+    mut response: Response,
+    scheme: &Scheme,
+    payer: Address,
+    resource: &str,
+    access_end: Option<u64>,
+    payment: Option<FixedBytes<32>>, // one-time payment the token redeems
+) -> Response {
+    let Some(session) = &config.session else {
+        return response;
+    };
+
+    let payer = payer.to_string();
+    let token = match payment {
+        Some(tx_hash) => session.issue_for_payment(
+            &payer,
+            scheme.to_string(),
+            resource,
+            access_end,
+            &tx_hash.to_string(),
+        ),
+        None => session.issue(&payer, scheme.to_string(), resource, access_end),
+    };
+    match token {
+        Ok(token) => match token.parse() {
+            Ok(value) => {
+                response.headers_mut().insert(SESSION_HEADER, value);
+            }
+            Err(_) => warn!("Failed to set session token header"),
+        },
+        Err(e) => warn!(error = %e, "Failed to issue session token"),
     }
+    response
 }
@@ -0,0 +1,215 @@
+// Batched on-chain reads
+// Contract reads arriving within a short window are coalesced into a single Multicall3 `aggregate3` call,
+// and receipt lookups into a single JSON-RPC batch request
+
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alloy::{
+    primitives::{Address, Bytes, FixedBytes},
+    providers::Provider,
+    rpc::{client::BatchRequest, types::TransactionReceipt},
+    sol,
+    transports::TransportError,
+};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+use crate::middleware::rpc::RpcPool;
+
+pub const MULTICALL3_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11"; // same address on every chain it's deployed to
+
+sol! {
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    contract Multicall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+
+        struct Call3Result {
+            bool success;
+            bytes returnData;
+        }
+
+        function aggregate3(Call3[] calldata calls) external payable returns (Call3Result[] memory returnData);
+    }
+}
+
+type CallResult = Result<Bytes, String>;
+type ReceiptResult = Result<Option<TransactionReceipt>, String>;
+
+// Reads waiting for the current window to close
+#[derive(Default)]
+pub(crate) struct BatchQueues {
+    calls: Mutex<Vec<(Address, Bytes, oneshot::Sender<CallResult>)>>,
+    receipts: Mutex<Vec<(FixedBytes<32>, oneshot::Sender<ReceiptResult>)>>,
+}
+
+/// Queue a contract read, the first read of a window schedules the flush
+pub(crate) async fn queue_call(
+    pool: &RpcPool,
+    queues: &Arc<BatchQueues>,
+    window: Duration,
+    target: Address,
+    calldata: Bytes,
+) -> CallResult {
+    let (tx, rx) = oneshot::channel();
+
+    let first = {
+        let mut calls = queues.calls.lock().unwrap();
+        calls.push((target, calldata, tx));
+        calls.len() == 1
+    };
+
+    if first {
+        let pool = pool.clone();
+        let queues = queues.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let calls = std::mem::take(&mut *queues.calls.lock().unwrap());
+            flush_calls(&pool, calls).await;
+        });
+    }
+
+    rx.await.map_err(|_| "Batched call dropped".to_string())?
+}
+
+/// Queue a receipt lookup, the first lookup of a window schedules the flush
+pub(crate) async fn queue_receipt(
+    pool: &RpcPool,
+    queues: &Arc<BatchQueues>,
+    window: Duration,
+    tx_hash: FixedBytes<32>,
+) -> ReceiptResult {
+    let (tx, rx) = oneshot::channel();
+
+    let first = {
+        let mut receipts = queues.receipts.lock().unwrap();
+        receipts.push((tx_hash, tx));
+        receipts.len() == 1
+    };
+
+    if first {
+        let pool = pool.clone();
+        let queues = queues.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let receipts = std::mem::take(&mut *queues.receipts.lock().unwrap());
+            flush_receipts(&pool, receipts).await;
+        });
+    }
+
+    rx.await
+        .map_err(|_| "Batched receipt lookup dropped".to_string())?
+}
+
+async fn flush_calls(pool: &RpcPool, calls: Vec<(Address, Bytes, oneshot::Sender<CallResult>)>) {
+    // Nothing to coalesce
+    if calls.len() == 1 {
+        for (target, calldata, tx) in calls {
+            let _ = tx.send(pool.direct_call(target, calldata).await);
+        }
+        return;
+    }
+
+    let multicall = Address::from_str(MULTICALL3_ADDRESS).unwrap();
+    let call3s: Vec<Multicall3::Call3> = calls
+        .iter()
+        .map(|(target, calldata, _)| Multicall3::Call3 {
+            target: *target,
+            allowFailure: true,
+            callData: calldata.clone(),
+        })
+        .collect();
+
+    debug!(
+        calls = call3s.len(),
+        "Batching contract reads into Multicall3"
+    );
+
+    let results = pool
+        .call(|provider| {
+            let call3s = call3s.clone();
+            async move {
+                Multicall3::new(multicall, provider)
+                    .aggregate3(call3s)
+                    .call()
+                    .await
+            }
+        })
+        .await;
+
+    match results {
+        Ok(results) if results.returnData.len() == calls.len() => {
+            for ((_, _, tx), result) in calls.into_iter().zip(results.returnData) {
+                let _ = tx.send(if result.success {
+                    Ok(result.returnData)
+                } else {
+                    Err("execution reverted".to_string())
+                });
+            }
+        }
+        _ => {
+            // Multicall3 missing on this chain or the batch failed, read each one on its own
+            warn!("Multicall3 batch failed, falling back to individual calls");
+            for (target, calldata, tx) in calls {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let _ = tx.send(pool.direct_call(target, calldata).await);
+                });
+            }
+        }
+    }
+}
+
+async fn flush_receipts(
+    pool: &RpcPool,
+    receipts: Vec<(FixedBytes<32>, oneshot::Sender<ReceiptResult>)>,
+) {
+    let tx_hashes: Vec<FixedBytes<32>> = receipts.iter().map(|(tx_hash, _)| *tx_hash).collect();
+
+    let results = pool
+        .call(|provider| {
+            let tx_hashes = tx_hashes.clone();
+            async move {
+                let mut batch = BatchRequest::new(provider.client());
+                let waiters = tx_hashes
+                    .iter()
+                    .map(|tx_hash| {
+                        batch.add_call::<_, Option<TransactionReceipt>>(
+                            "eth_getTransactionReceipt",
+                            &(tx_hash,),
+                        )
+                    })
+                    .collect::<Result<Vec<_>, TransportError>>()?;
+
+                batch.send().await?;
+
+                let mut receipts = Vec::with_capacity(waiters.len());
+                for waiter in waiters {
+                    receipts.push(waiter.await?);
+                }
+                Ok::<_, TransportError>(receipts)
+            }
+        })
+        .await;
+
+    match results {
+        Ok(results) => {
+            for ((_, tx), receipt) in receipts.into_iter().zip(results) {
+                let _ = tx.send(Ok(receipt));
+            }
+        }
+        Err(e) => {
+            for (_, tx) in receipts {
+                let _ = tx.send(Err(e.clone()));
+            }
+        }
+    }
+}
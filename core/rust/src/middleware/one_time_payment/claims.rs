@@ -0,0 +1,52 @@
+// Transfers used as payments, shared by the schemes paid with a plain transfer
+// One-time, credits and subscription payments all present a tx hash, each transfer can only pay for one of them
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use alloy::primitives::FixedBytes;
+
+use crate::{error::AuthError, middleware::types::Scheme};
+
+#[derive(Clone, Debug, Default)]
+pub struct TxClaims {
+    claims: Arc<Mutex<HashMap<FixedBytes<32>, Scheme>>>, // tx hash to the scheme it paid for
+}
+
+impl TxClaims {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scheme the transfer was used for, if any
+    pub fn claimed_by(&self, tx_hash: FixedBytes<32>) -> Option<Scheme> {
+        self.claims.lock().unwrap().get(&tx_hash).cloned()
+    }
+
+    /// Fails if another scheme already used the transfer, checked before verifying it on-chain
+    pub fn check(&self, tx_hash: FixedBytes<32>, scheme: &Scheme) -> Result<(), AuthError> {
+        match self.claimed_by(tx_hash) {
+            Some(claimed) if claimed != *scheme => Err(already_used(&claimed)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a verified transfer as paying for `scheme`, fails if another scheme already used it
+    pub fn claim(&self, tx_hash: FixedBytes<32>, scheme: Scheme) -> Result<(), AuthError> {
+        let mut claims = self.claims.lock().unwrap();
+        match claims.get(&tx_hash) {
+            Some(claimed) if *claimed != scheme => Err(already_used(claimed)),
+            Some(_) => Ok(()),
+            None => {
+                claims.insert(tx_hash, scheme);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn already_used(scheme: &Scheme) -> AuthError {
+    AuthError::InvalidTransaction(format!("Payment already used for {}", scheme.to_string()))
+}
@@ -0,0 +1,79 @@
+// Prepaid credits built on one-time payments
+// A verified transfer tops up the balance of the payer, each request is then debited from it
+
+use std::{collections::HashMap, sync::Arc};
+
+use alloy::primitives::{Address, FixedBytes, U256};
+use tokio::sync::RwLock;
+
+use crate::error::AuthError;
+
+#[derive(Clone)]
+pub struct CreditState {
+    balances: Arc<RwLock<HashMap<Address, U256>>>, // Credit balance for each payer
+    deposits: Arc<RwLock<HashMap<FixedBytes<32>, Address>>>, // Deposit tx hash to payer, a deposit is only credited once
+}
+
+impl Default for CreditState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CreditState {
+    pub fn new() -> Self {
+        Self {
+            balances: Arc::new(RwLock::new(HashMap::new())),
+            deposits: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn balance(&self, payer: Address) -> U256 {
+        let balances = self.balances.read().await;
+        balances.get(&payer).cloned().unwrap_or_default()
+    }
+
+    /// Payer who made the deposit, if it was already credited
+    pub async fn get_depositor(&self, tx_hash: FixedBytes<32>) -> Option<Address> {
+        let deposits = self.deposits.read().await;
+        deposits.get(&tx_hash).cloned()
+    }
+
+    /// Credit a verified deposit to the payer, returns false if the deposit was already credited
+    pub async fn top_up(&self, tx_hash: FixedBytes<32>, payer: Address, amount: U256) -> bool {
+        let mut deposits = self.deposits.write().await;
+        if deposits.contains_key(&tx_hash) {
+            return false;
+        }
+        deposits.insert(tx_hash, payer);
+
+        let mut balances = self.balances.write().await;
+        let balance = balances.entry(payer).or_default();
+        *balance = balance.saturating_add(amount);
+        true
+    }
+
+    /// Debit `amount` from the payer, returns the remaining balance
+    pub async fn debit(&self, payer: Address, amount: U256) -> Result<U256, AuthError> {
+        let mut balances = self.balances.write().await;
+        let balance = balances.entry(payer).or_default();
+
+        if *balance < amount {
+            return Err(AuthError::InsufficientBalance);
+        }
+
+        *balance -= amount;
+        Ok(*balance)
+    }
+
+    /// Debit usage known only after the request was served, as much as the balance allows
+    /// Returns the amount actually debited and the remaining balance
+    pub async fn debit_usage(&self, payer: Address, amount: U256) -> (U256, U256) {
+        let mut balances = self.balances.write().await;
+        let balance = balances.entry(payer).or_default();
+
+        let debited = amount.min(*balance);
+        *balance -= debited;
+        (debited, *balance)
+    }
+}
@@ -1,3 +1,6 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod claims;
+pub mod credits;
 pub mod state;
 pub mod types;
 pub mod utils;
@@ -15,6 +18,7 @@ use std::{future::Future, pin::Pin};
 use tower::{Layer, Service};
 
 use state::OneTimePaymentState;
+use tracing::debug;
 use types::OneTimePaymentConfig;
 use utils::parse_tx_headers;
 use verify::verify_tx;
@@ -93,9 +97,6 @@ where
 
         // #[cfg(not(target_arch = "wasm32"))]
         Box::pin(async move {
-            println!("\n=== onetime_tx_auth_middleware ===");
-            println!("=== new request ===");
-
             let signed_payment_tx = match parse_tx_headers(&request.headers().clone()).await {
                 Ok(tx) => tx,
                 Err(e) => return Ok(e.into_response()),
@@ -105,8 +106,8 @@ where
             let current_time = get_current_time();
 
             // Check if payment exists in state
-            if let Some(_) = state.get(tx_hash).await {
-                println!("Found existing payment in state");
+            if state.get(tx_hash).await.is_some() {
+                debug!("Found existing payment in state");
 
                 // Use custom period_ttl_sec if set in config, otherwise fallback to hardcoded values
                 let custom_session_ttl = config.period_ttl_sec;
@@ -121,22 +122,21 @@ where
                     )
                     .await
                 {
-                    println!("Payment is valid for redemption");
+                    debug!("Payment is valid for redemption");
 
                     // Increment redemption count
-                    if let Some(_) = state.increment_redemptions(tx_hash).await {}
+                    state.increment_redemptions(tx_hash).await;
 
-                    println!("=== end middleware check ===");
                     inner.call(request).await
                 } else {
-                    println!("Payment is no longer valid for redemption");
+                    debug!("Payment is no longer valid for redemption");
                     Ok(AuthError::InvalidTransaction(
                         "Payment session expired or max redemptions reached".to_string(),
                     )
                     .into_response())
                 }
             } else {
-                println!("New payment - verifying transaction");
+                debug!("New payment - verifying transaction");
 
                 // First time seeing this payment, verify the transaction
                 let (new_payment, verify) = match verify_tx(signed_payment_tx.clone(), config).await
@@ -146,17 +146,16 @@ where
                 };
 
                 if verify {
-                    println!("Transaction verified successfully");
+                    debug!("Transaction verified successfully");
 
                     state.set(tx_hash, new_payment).await;
-                    println!("Added new payment to state");
+                    debug!("Added new payment to state");
 
                     state.increment_redemptions(tx_hash).await;
-                    println!("=== end middleware check ===");
 
                     inner.call(request).await
                 } else {
-                    println!("Transaction verification failed");
+                    debug!("Transaction verification failed");
                     Ok(
                         AuthError::InvalidTransaction("Authentication failed".to_string())
                             .into_response(),
@@ -179,9 +178,6 @@ pub async fn onetime_payment_auth_fn_middleware(
     request: Request<Body>,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    println!("\n=== onetime_tx_auth_middleware ===");
-    println!("=== new request ===");
-
     let signed_payment_tx = match parse_tx_headers(&request.headers().clone()).await {
         Ok(tx) => tx,
         Err(e) => return Ok(e.into_response()),
@@ -192,7 +188,7 @@ pub async fn onetime_payment_auth_fn_middleware(
 
     // Check if payment exists in state
     if let Some(existing_payment) = state.payment_state.get(tx_hash).await {
-        println!("Found existing payment in state");
+        debug!("Found existing payment in state");
 
         // Use custom period_ttl_sec if set in config, otherwise fallback to hardcoded values
         let custom_session_ttl = state.config.period_ttl_sec;
@@ -203,7 +199,7 @@ pub async fn onetime_payment_auth_fn_middleware(
             .is_valid_for_redemption_with_period(tx_hash, current_time, None, custom_session_ttl)
             .await
         {
-            println!("Payment is valid for redemption");
+            debug!("Payment is valid for redemption");
 
             // Set first redeemed timestamp if this is the first time
             if existing_payment.first_reedemed == 0 {
@@ -211,22 +207,21 @@ pub async fn onetime_payment_auth_fn_middleware(
                     .payment_state
                     .set_first_redeemed(tx_hash, current_time)
                     .await;
-                println!("Set first redemption timestamp");
+                debug!("Set first redemption timestamp");
             }
 
             // Increment redemption count
             if let Some(new_count) = state.payment_state.increment_redemptions(tx_hash).await {
-                println!("Incremented redemptions to: {}", new_count);
+                debug!("Incremented redemptions to: {}", new_count);
             }
 
-            println!("=== end middleware check ===");
             Ok(next.run(request).await)
         } else {
-            println!("Payment is no longer valid for redemption");
+            debug!("Payment is no longer valid for redemption");
             Err(StatusCode::UNAUTHORIZED)
         }
     } else {
-        println!("New payment - verifying transaction");
+        debug!("New payment - verifying transaction");
 
         // First time seeing this payment, verify the transaction
         let (new_payment, verify) = match verify_tx(signed_payment_tx.clone(), state.config).await {
@@ -235,17 +230,16 @@ pub async fn onetime_payment_auth_fn_middleware(
         };
 
         if verify {
-            println!("Transaction verified successfully");
+            debug!("Transaction verified successfully");
 
             state.payment_state.set(tx_hash, new_payment).await;
-            println!("Added new payment to state");
+            debug!("Added new payment to state");
 
             state.payment_state.increment_redemptions(tx_hash).await;
-            println!("=== end middleware check ===");
 
             Ok(next.run(request).await)
         } else {
-            println!("Transaction verification failed");
+            debug!("Transaction verification failed");
             Err(StatusCode::UNAUTHORIZED)
         }
     }
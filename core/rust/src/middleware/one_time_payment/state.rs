@@ -1,4 +1,4 @@
-use alloy::primitives::FixedBytes;
+use alloy::primitives::{Address, FixedBytes};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
@@ -8,12 +8,24 @@ use crate::middleware::one_time_payment::types::OneTimePayment;
 pub struct OneTimePaymentState {
     // Transaction hash to payment mapping (tx_hash is the identifier)
     payments: Arc<RwLock<HashMap<FixedBytes<32>, OneTimePayment>>>,
+    // Sender to subscription expiry, for payments buying time-based access
+    subscriptions: Arc<RwLock<HashMap<Address, u64>>>,
+    // Payments applied to subscriptions, kept apart so they can't be redeemed as one-time payments
+    subscription_payments: Arc<RwLock<HashMap<FixedBytes<32>, OneTimePayment>>>,
+}
+
+impl Default for OneTimePaymentState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OneTimePaymentState {
     pub fn new() -> Self {
         Self {
             payments: Arc::new(RwLock::new(HashMap::new())),
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            subscription_payments: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -62,14 +74,15 @@ impl OneTimePaymentState {
         tx_hash: FixedBytes<32>,
         current_time: u64,
     ) -> bool {
-        self.is_valid_for_redemption_with_period(tx_hash, current_time, None, None).await
+        self.is_valid_for_redemption_with_period(tx_hash, current_time, None, None)
+            .await
     }
 
     pub async fn is_valid_for_redemption_with_period(
         &self,
         tx_hash: FixedBytes<32>,
         current_time: u64,
-        custom_period: Option<u64>, // Custom absolute window period
+        custom_period: Option<u64>,      // Custom absolute window period
         custom_session_ttl: Option<u64>, // Custom session TTL
     ) -> bool {
         let payments = self.payments.read().await;
@@ -81,7 +94,7 @@ impl OneTimePaymentState {
 
             // Use custom period if provided, otherwise fallback to hardcoded value
             let abs_window = custom_period.unwrap_or(super::types::ABS_WINDOW_SEC);
-            
+
             // Check if payment is within the absolute window from payment timestamp
             if current_time > payment.payment_timestamp + abs_window {
                 return false;
@@ -94,11 +107,47 @@ impl OneTimePaymentState {
 
             // Use custom session TTL if provided, otherwise fallback to hardcoded value
             let session_ttl = custom_session_ttl.unwrap_or(super::types::SESSION_TTL_SEC);
-            
+
             // Check if we're within the session TTL from first redemption
             current_time <= payment.first_reedemed + session_ttl
         } else {
             false
         }
     }
+
+    /// Payment applied to a subscription, if any
+    pub async fn get_subscription_payment(
+        &self,
+        tx_hash: FixedBytes<32>,
+    ) -> Option<OneTimePayment> {
+        let subscription_payments = self.subscription_payments.read().await;
+        subscription_payments.get(&tx_hash).cloned()
+    }
+
+    /// Expiry of the sender's subscription, if any
+    pub async fn get_subscription(&self, sender: Address) -> Option<u64> {
+        let subscriptions = self.subscriptions.read().await;
+        subscriptions.get(&sender).cloned()
+    }
+
+    /// Extend the sender's subscription by `duration` with a verified payment, starting from the current expiry if
+    /// still running or from the payment otherwise. Returns the new expiry, or None if the payment was already applied
+    /// to a subscription or redeemed as a one-time payment
+    pub async fn extend_subscription(&self, payment: OneTimePayment, duration: u64) -> Option<u64> {
+        let payments = self.payments.read().await;
+        let mut subscription_payments = self.subscription_payments.write().await;
+        if payments.contains_key(&payment.tx_hash)
+            || subscription_payments.contains_key(&payment.tx_hash)
+        {
+            return None;
+        }
+
+        let mut subscriptions = self.subscriptions.write().await;
+        let expiry = subscriptions.entry(payment.sender).or_default();
+        *expiry = (*expiry).max(payment.payment_timestamp) + duration;
+        let new_expiry = *expiry;
+
+        subscription_payments.insert(payment.tx_hash, payment);
+        Some(new_expiry)
+    }
 }
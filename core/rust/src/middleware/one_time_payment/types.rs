@@ -23,8 +23,11 @@ pub struct OneTimePayment {
     pub payment_timestamp: u64, // timestamp of the payment
     pub first_reedemed: u64,    // timestamp of first redemption
     pub redemptions: u32,       // number of times this payment has been redeemed
+    #[serde(default)]
+    pub amount: U256, // amount transferred
 }
 
 pub const ABS_WINDOW_SEC: u64 = 172800; // 2 days i.e. payment must be within this window
 pub const SESSION_TTL_SEC: u64 = 3600; // 1 hour i.e. session is valid for this period once created
 pub const MAX_REDEMPTIONS: u32 = 3; // max 3 redemption attempts allowed per payment
+pub const SUBSCRIPTION_PERIOD_SEC: u64 = 2592000; // 30 days i.e. subscription bought by paying the configured amount
@@ -4,6 +4,7 @@ use alloy::{
     primitives::{keccak256, FixedBytes, PrimitiveSignature},
 };
 use http::HeaderMap;
+use tracing::debug;
 
 use crate::{error::AuthError, middleware::one_time_payment::types::SignedPaymentTx};
 
@@ -20,12 +21,12 @@ pub async fn parse_tx_headers(headers: &HeaderMap) -> Result<SignedPaymentTx, Au
 
     let signature = hex::decode(signature.trim_start_matches("0x"))
         .map_err(|_| {
-            println!("Failed: Signature decode");
+            debug!("Failed: Signature decode");
             AuthError::InvalidSignature
         })
         .and_then(|bytes| {
             PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                println!("Failed: Signature conversion");
+                debug!("Failed: Signature conversion");
                 AuthError::InvalidSignature
             })
         })?;
@@ -41,7 +42,7 @@ pub async fn parse_tx_headers(headers: &HeaderMap) -> Result<SignedPaymentTx, Au
         })?;
 
     let tx_hash = hex::decode(tx_hash).map_err(|_| {
-        println!("Failed: Message decode");
+        debug!("Failed: Message decode");
         AuthError::InvalidTransaction("Tx hash decode failed".to_string())
     })?;
 
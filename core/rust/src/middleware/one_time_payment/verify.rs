@@ -2,8 +2,8 @@ use alloy::{
     dyn_abi::DynSolType,
     hex::{self},
     primitives::Address,
-    providers::{Provider, ProviderBuilder},
 };
+use tracing::debug;
 
 use crate::{
     error::AuthError,
@@ -12,7 +12,8 @@ use crate::{
             types::{OneTimePayment, OneTimePaymentConfig, SignedPaymentTx, ABS_WINDOW_SEC},
             utils::create_tx_message,
         },
-        utils::get_current_time,
+        rpc::get_rpc_pool,
+        utils::{get_current_time, redact},
     },
 };
 
@@ -20,39 +21,58 @@ use crate::{
 pub async fn verify_tx(
     signed_tx: SignedPaymentTx,
     config: OneTimePaymentConfig,
+) -> Result<(OneTimePayment, bool), AuthError> {
+    verify_transfer(signed_tx, config, true).await
+}
+
+// For credit deposits, any transfer of at least the configured amount is accepted and the amount is recorded on the payment
+pub async fn verify_deposit(
+    signed_tx: SignedPaymentTx,
+    config: OneTimePaymentConfig,
+) -> Result<(OneTimePayment, bool), AuthError> {
+    verify_transfer(signed_tx, config, false).await
+}
+
+async fn verify_transfer(
+    signed_tx: SignedPaymentTx,
+    config: OneTimePaymentConfig,
+    exact_amount: bool,
 ) -> Result<(OneTimePayment, bool), AuthError> {
     // creating the message
     let reconstructed_message = create_tx_message(signed_tx.tx_hash);
-    println!("Message: 0x{}", hex::encode(&reconstructed_message));
 
     let signature = signed_tx.signature;
-    println!("Signature: 0x{}", hex::encode(&signature.as_bytes()));
+    debug!(
+        signature = %redact(&hex::encode(signature.as_bytes())),
+        "Verifying payment transaction"
+    );
 
     // recovering the address from the signature
     let recovered = match signature.recover_address_from_msg(reconstructed_message) {
         Ok(address) => address,
         Err(_) => return Err(AuthError::InvalidSignature),
     };
-    println!("Recovered address: {}", recovered);
+    debug!(%recovered, "Recovered payment signer");
 
-    let provider = ProviderBuilder::new().on_http(config.rpc_url.parse().unwrap());
+    let pool = get_rpc_pool(&config.rpc_url)?;
+    let tx_hash = signed_tx.tx_hash;
 
     // Fetching the info for transaction
-    let tx_receipt = match provider
-        .get_transaction_receipt(signed_tx.tx_hash)
+    let tx_receipt = match pool
+        .get_transaction_receipt(tx_hash)
         .await
-        .map_err(|e| AuthError::ContractError(e.to_string()))?
+        .map_err(AuthError::ContractError)?
     {
         Some(tx_receipt) => tx_receipt,
         None => {
-            println!("Failed: Transaction not found");
+            debug!("Failed: Transaction not found");
             return Err(AuthError::TransactionNotFound);
         }
     };
 
     // Verifying recovered address against the sender for the transaction
     if recovered != tx_receipt.from {
-        println!("Failed: Recovered address mismatch");
+        debug!("Failed: Recovered address mismatch");
         return Err(AuthError::InvalidSignature);
     }
 
@@ -66,7 +86,7 @@ pub async fn verify_tx(
             }
         }
         None => {
-            println!("Failed: To address not found");
+            debug!("Failed: To address not found");
             return Err(AuthError::InvalidTransaction(
                 "To address not found".to_string(),
             ));
@@ -108,13 +128,13 @@ pub async fn verify_tx(
             }
         }
 
-        match transfer_log.topics().get(2) {
+        let amount = match transfer_log.topics().get(2) {
             Some(t) => {
-                let to = Address::from_word(t.clone());
+                let to = Address::from_word(*t);
                 let data = &transfer_log.data().data;
                 let data_type = DynSolType::Uint(256);
                 let decoded = data_type
-                    .abi_decode(&data)
+                    .abi_decode(data)
                     .map_err(|e| AuthError::ContractError(e.to_string()))?;
 
                 let (amount, _) = match decoded.as_uint() {
@@ -126,31 +146,36 @@ pub async fn verify_tx(
                     }
                 };
 
-                if to != config.recipient || amount != config.amount {
+                let amount_valid = if exact_amount {
+                    amount == config.amount
+                } else {
+                    amount >= config.amount
+                };
+
+                if to != config.recipient || !amount_valid {
                     return Err(AuthError::InvalidTransaction(
                         "Invalid recipient or amount".to_string(),
                     ));
                 }
+
+                amount
             }
             None => return Err(AuthError::InvalidTransaction("Topic not found".to_string())),
-        }
+        };
 
         let payment = OneTimePayment {
             tx_hash: signed_tx.tx_hash,
             sender: tx_receipt.from,
-            payment_timestamp: if let Some(ts) = transfer_log.block_timestamp {
-                ts
-            } else {
-                0
-            },
+            payment_timestamp: transfer_log.block_timestamp.unwrap_or_default(),
             first_reedemed: current_time, // Set on first access
             redemptions: 0,
+            amount,
         };
 
-        return Ok((payment, true));
+        Ok((payment, true))
     } else {
-        return Err(AuthError::InvalidTransaction(
+        Err(AuthError::InvalidTransaction(
             "Invalid Token used for payment".to_string(),
-        ));
+        ))
     }
 }
@@ -1,7 +1,10 @@
 // Channel struct and implementation
 // It's the local channel state for the middleware on the server side on how to store the info and just work with it
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as SyncMutex},
+};
 
 use alloy::{
     contract::Error,
@@ -12,17 +15,21 @@ use alloy::{
     sol,
 };
 use alloy::{primitives::Bytes, transports::http::reqwest::Url};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+use tracing::debug;
 
 use crate::{
     error::AuthError,
-    middleware::payment_channel::types::{
-        PaymentChannel as PaymentChannelType, PaymentChannelConfig,
+    middleware::events::{PaymentEvent, PaymentEvents},
+    middleware::payment_channel::{
+        factory::verify_channel_factory,
+        types::{ChannelAccount, PaymentChannel as PaymentChannelType, PaymentChannelConfig},
     },
+    middleware::rpc::get_rpc_pool,
 };
 
 sol!(
-    #[allow(missing_docs)]
+    #[allow(missing_docs, clippy::too_many_arguments)]
     #[sol(rpc)]
     PaymentChannelABI,
     "src/abi/PaymentChannel.json"
@@ -30,23 +37,88 @@ sol!(
 
 #[derive(Clone)]
 pub struct ChannelState {
-    pub(crate) channels: Arc<RwLock<HashMap<U256, PaymentChannelType>>>, // All the channels the current server has with other user
+    pub(crate) channels: Arc<RwLock<HashMap<U256, PaymentChannelType>>>, // All the channels the current server has with other user, as last signed by the sender
+    pub(crate) accounts: Arc<RwLock<HashMap<U256, ChannelAccount>>>, // Deposited vs spent amount for each channel
     pub(crate) latest_signatures: Arc<RwLock<HashMap<U256, PrimitiveSignature>>>, // Latest signatures for each channel
+    pub(crate) signed_bodies: Arc<RwLock<HashMap<U256, Bytes>>>, // Body signed alongside the latest signature, needed to close the channel
+    pub(crate) closed_channels: Arc<RwLock<HashSet<U256>>>, // Channels closed or timed out on-chain
+    pub(crate) channel_locks: Arc<SyncMutex<HashMap<U256, Arc<Mutex<()>>>>>, // Serializes requests on the same channel, only while in use
+    events: PaymentEvents, // Notified when a channel is settled
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ChannelState {
     pub fn new() -> Self {
         Self {
             channels: Arc::new(RwLock::new(HashMap::new())),
+            accounts: Arc::new(RwLock::new(HashMap::new())),
             latest_signatures: Arc::new(RwLock::new(HashMap::new())),
+            signed_bodies: Arc::new(RwLock::new(HashMap::new())),
+            closed_channels: Arc::new(RwLock::new(HashSet::new())),
+            channel_locks: Arc::new(SyncMutex::new(HashMap::new())),
+            events: PaymentEvents::default(),
         }
     }
 
+    /// Report settled channels to `events`
+    pub fn with_events(mut self, events: PaymentEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Lock a channel, held while a request on it is verified and stored so only one request per nonce is accepted
+    /// and the stored signature always matches the stored channel
+    /// Also held by the event appliers, the lock is dropped once nobody waits on it as ids come from requests
+    pub async fn lock_channel(&self, channel_id: U256) -> ChannelGuard {
+        let lock = {
+            let mut locks = self.channel_locks.lock().unwrap();
+            locks.entry(channel_id).or_default().clone()
+        };
+
+        ChannelGuard {
+            guard: Some(lock.lock_owned().await),
+            channel_id,
+            channel_locks: self.channel_locks.clone(),
+        }
+    }
+
+    /// Get the channel as last signed by the sender together with its signature and signed body, read under the channel lock
+    pub async fn get_signed_channel(
+        &self,
+        channel_id: U256,
+    ) -> Option<(PaymentChannelType, PrimitiveSignature, Bytes)> {
+        let _guard = self.lock_channel(channel_id).await;
+        let channel = self.get_channel(channel_id).await?;
+        let signature = self.get_latest_signature(channel_id).await?;
+        let body = self.get_signed_body(channel_id).await.unwrap_or_default();
+        Some((channel, signature, body))
+    }
+
     pub async fn get_channel(&self, channel_id: U256) -> Option<PaymentChannelType> {
         let channels = self.channels.read().await;
         channels.get(&channel_id).cloned()
     }
 
+    /// Get the channel with the balance still available to the sender, i.e. what the next request has to be signed against
+    pub async fn get_channel_with_remaining(&self, channel_id: U256) -> Option<PaymentChannelType> {
+        let mut channel = self.get_channel(channel_id).await?;
+        if let Some(account) = self.get_account(channel_id).await {
+            channel.balance = account.remaining();
+        }
+        Some(channel)
+    }
+
+    /// Get the deposited vs spent accounting for a channel
+    pub async fn get_account(&self, channel_id: U256) -> Option<ChannelAccount> {
+        let accounts = self.accounts.read().await;
+        accounts.get(&channel_id).cloned()
+    }
+
     /// Get the latest signature for a channel
     pub async fn get_latest_signature(&self, channel_id: U256) -> Option<PrimitiveSignature> {
         let signatures = self.latest_signatures.read().await;
@@ -59,6 +131,87 @@ impl ChannelState {
         signatures.insert(channel_id, signature);
     }
 
+    /// Get the body signed with the latest signature for a channel
+    pub async fn get_signed_body(&self, channel_id: U256) -> Option<Bytes> {
+        let bodies = self.signed_bodies.read().await;
+        bodies.get(&channel_id).cloned()
+    }
+
+    /// Update the body signed with the latest signature for a channel
+    pub async fn update_signed_body(&self, channel_id: U256, body: Bytes) {
+        let mut bodies = self.signed_bodies.write().await;
+        bodies.insert(channel_id, body);
+    }
+
+    /// Record a post-paid charge, known only after the request was served
+    /// It's owed until the next voucher covers it, the remaining balance returned to the sender already accounts for it
+    /// The service was already rendered, so the charge is capped at what's left of the deposit, the amount charged is returned
+    pub async fn record_charge(
+        &self,
+        channel_id: U256,
+        amount: U256,
+    ) -> Result<(ChannelAccount, U256), AuthError> {
+        let _guard = self.lock_channel(channel_id).await;
+        let mut accounts = self.accounts.write().await;
+        let account = accounts
+            .get_mut(&channel_id)
+            .ok_or(AuthError::ChannelNotFound)?;
+
+        let charged = amount.min(account.remaining());
+        account.owed += charged;
+        Ok((account.clone(), charged))
+    }
+
+    /// Whether the channel has been closed or timed out on-chain
+    pub async fn is_closed(&self, channel_id: U256) -> bool {
+        self.closed_channels.read().await.contains(&channel_id)
+    }
+
+    /// Apply a `DepositMade` event, raising the deposited total of a cached channel to the new on-chain balance
+    /// Taking the max keeps this idempotent if the same event is delivered twice
+    pub async fn apply_deposit(&self, channel_id: U256, new_balance: U256) -> bool {
+        let _guard = self.lock_channel(channel_id).await;
+        let mut accounts = self.accounts.write().await;
+        match accounts.get_mut(&channel_id) {
+            Some(account) => {
+                account.deposited = account.deposited.max(new_balance);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply an `ExpirationExtended` event to a cached channel
+    pub async fn apply_expiration(&self, channel_id: U256, new_expiration: U256) -> bool {
+        let _guard = self.lock_channel(channel_id).await;
+        let mut channels = self.channels.write().await;
+        match channels.get_mut(&channel_id) {
+            Some(channel) => {
+                channel.expiration = new_expiration;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply a `ChannelClosed` or `TimeoutClaimed` event, any further request on the channel is rejected
+    pub async fn mark_closed(&self, channel_id: U256) {
+        let _guard = self.lock_channel(channel_id).await;
+        self.closed_channels.write().await.insert(channel_id);
+        let channel = self.channels.write().await.remove(&channel_id);
+        let account = self.accounts.write().await.remove(&channel_id);
+        self.latest_signatures.write().await.remove(&channel_id);
+        self.signed_bodies.write().await.remove(&channel_id);
+
+        if let Some(channel) = channel {
+            self.events.emit(PaymentEvent::ChannelSettled {
+                channel_id,
+                sender: channel.sender,
+                spent: account.map(|a| a.spent).unwrap_or_default(),
+            });
+        }
+    }
+
     // verification method
     pub async fn verify_signature(
         &self,
@@ -70,7 +223,7 @@ impl ChannelState {
 
         // Network logic to verify the signature, could be a simple ECDSA verification
         let recovered = signature.recover_address_from_msg(message);
-        println!("Recovered address: {:?}", recovered);
+        debug!(recovered = ?recovered.as_ref().ok(), "Recovered channel signer");
 
         // Match the recovered address with the one in the channel state
         match recovered {
@@ -83,34 +236,42 @@ impl ChannelState {
     }
 
     // Validating all the information of the channel from the onchain contract for the first time, before the channel is used
+    // Returns the balance locked in the contract, i.e. the total deposited by the sender
     pub async fn validate_channel(
         &self,
         config: &PaymentChannelConfig,
         payment_channel: &PaymentChannelType,
-    ) -> Result<(), AuthError> {
+    ) -> Result<U256, AuthError> {
         // self.network.validate_channel(channel_id, balance).await
-        let provider = ProviderBuilder::new().on_http(config.rpc_url.parse().unwrap());
+        if let Some(factory_address) = config.factory_address {
+            verify_channel_factory(&config.rpc_url, factory_address, payment_channel).await?;
+        }
 
-        let payment_channel_contract = PaymentChannelABI::new(payment_channel.address, provider);
+        let pool = get_rpc_pool(&config.rpc_url)?;
+        let channel_address = payment_channel.address;
 
-        let channel_info = payment_channel_contract
-            .getChannelInfo()
-            .call()
+        let channel_info = pool
+            .read(channel_address, PaymentChannelABI::getChannelInfoCall {})
             .await
             .map_err(|e| {
-                AuthError::ContractError(format!("Failed to fetch channel info: {}", e.to_string()))
+                AuthError::ContractError(format!("Failed to fetch channel info: {}", e))
             })?;
 
+        // Only open channels can be used, `ChannelState.Open` is 1 in the contract enum
+        if channel_info.state != 1 {
+            return Err(AuthError::ChannelClosed);
+        }
+
         let balance = channel_info.balance;
-        println!("Balance: {}", balance);
+        debug!(%balance, "Channel balance on-chain");
 
-        // If the balance is less than the balance in the local state, return an error
-        if payment_channel.balance < balance {
+        // The sender can't sign over a remaining balance larger than what is locked in the contract
+        if payment_channel.balance > balance {
             return Err(AuthError::InsufficientBalance);
         }
 
         let expiration = channel_info.exp;
-        println!("Expiration: {}", expiration);
+        debug!(%expiration, "Channel expiration on-chain");
 
         if payment_channel.expiration != expiration {
             return Err(AuthError::Expired);
@@ -118,7 +279,7 @@ impl ChannelState {
 
         // Verify the channelID from the contract
         let channel_id = channel_info.id;
-        println!("Channel ID: {}", channel_id);
+        debug!(%channel_id, "Channel id on-chain");
 
         if payment_channel.channel_id != channel_id {
             return Err(AuthError::InvalidChannel(format!(
@@ -148,20 +309,13 @@ impl ChannelState {
             )));
         }
 
-        let price_value = channel_info.pricePerRequest;
-
-        if price_value != config.amount {
-            return Err(AuthError::InvalidChannel(format!(
-                "Price mismatch - expected: {}, contract price: {}",
-                config.amount, price_value
-            )));
-        }
+        // `pricePerRequest` registered on-chain isn't enforced, the server computes the price of each request
+        // and the signed cumulative amount only has to cover it
 
-        let token_value = payment_channel_contract
-            .token()
-            .call()
+        let token_value = pool
+            .read(channel_address, PaymentChannelABI::tokenCall {})
             .await
-            .map_err(|e| AuthError::ContractError(e.to_string()))?
+            .map_err(AuthError::ContractError)?
             ._0;
 
         if token_value != config.token_address {
@@ -171,26 +325,44 @@ impl ChannelState {
             )));
         }
 
-        Ok(())
+        Ok(balance)
+    }
+}
+
+/// Held while a channel is used, the lock is dropped from the state once nobody waits on it
+pub struct ChannelGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    channel_id: U256,
+    channel_locks: Arc<SyncMutex<HashMap<U256, Arc<Mutex<()>>>>>,
+}
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+
+        let mut locks = self.channel_locks.lock().unwrap();
+        if let Some(lock) = locks.get(&self.channel_id) {
+            if Arc::strong_count(lock) == 1 {
+                locks.remove(&self.channel_id);
+            }
+        }
     }
 }
 
-// Close the channel using the latest signature stored in state
+// Close the channel using the latest signature and the body it was signed over, as stored in state
 pub async fn close_channel_from_state(
     state: &ChannelState,
     rpc_url: Url,
     private_key: &str,
     channel_id: U256,
-    raw_body: Bytes,
 ) -> Result<FixedBytes<32>, AuthError> {
     // Get the channel and latest signature from state
-    let payment_channel = state
-        .get_channel(channel_id)
-        .await
-        .ok_or_else(|| AuthError::ChannelNotFound)?;
+    if state.get_channel(channel_id).await.is_none() {
+        return Err(AuthError::ChannelNotFound);
+    }
 
-    let signature = state
-        .get_latest_signature(channel_id)
+    let (payment_channel, signature, raw_body) = state
+        .get_signed_channel(channel_id)
         .await
         .ok_or_else(|| AuthError::InvalidChannel("No signature found for channel".to_string()))?;
 
@@ -4,6 +4,7 @@ use alloy::{
 };
 use axum::{async_trait, extract::FromRequestParts};
 use http::{request::Parts, StatusCode};
+use tracing::debug;
 
 use crate::middleware::payment_channel::types::PaymentChannel;
 
@@ -54,25 +55,25 @@ where
         // Parse signature
         let signature = hex::decode(signature.trim_start_matches("0x"))
             .map_err(|_| {
-                println!("Failed: Signature decode");
+                debug!("Failed: Signature decode");
                 (StatusCode::BAD_REQUEST, "Signature couldn't be decoded")
             })
             .and_then(|bytes| {
                 PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                    println!("Failed: Signature conversion");
+                    debug!("Failed: Signature conversion");
                     (StatusCode::BAD_REQUEST, "Signature couldn't be converted")
                 })
             })?;
 
         // Parse message
         let message = hex::decode(message).map_err(|_| {
-            println!("Failed: Message decode");
+            debug!("Failed: Message decode");
             (StatusCode::BAD_REQUEST, "Message couldn't be decoded")
         })?;
 
         // Parse payment channel data
         let payment_channel: PaymentChannel = serde_json::from_str(payment_data).map_err(|e| {
-            println!("Failed: Payment data decode - Error {}", e);
+            debug!(error = %e, "Failed: Payment data decode");
             (StatusCode::BAD_REQUEST, "Payment couldn't be decoded")
         })?;
 
@@ -116,19 +117,19 @@ where
         // Parse signature
         let signature = hex::decode(signature.trim_start_matches("0x"))
             .map_err(|_| {
-                println!("Failed: Signature decode");
+                debug!("Failed: Signature decode");
                 (StatusCode::BAD_REQUEST, "Signature couldn't be decoded")
             })
             .and_then(|bytes| {
                 PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                    println!("Failed: Signature conversion");
+                    debug!("Failed: Signature conversion");
                     (StatusCode::BAD_REQUEST, "Signature couldn't be converted")
                 })
             })?;
 
         // Parse message
         let tx_hash = hex::decode(tx_hash).map_err(|_| {
-            println!("Failed: Message decode");
+            debug!("Failed: Message decode");
             (StatusCode::BAD_REQUEST, "Message couldn't be decoded")
         })?;
 
@@ -0,0 +1,191 @@
+// ChannelFactory bindings and helpers
+// Used by the API provider to register its price, discover the channels opened to it and to make sure a channel was deployed by a trusted factory
+
+use std::str::FromStr;
+
+use alloy::{
+    network::EthereumWallet,
+    primitives::{Address, FixedBytes, U256},
+    providers::ProviderBuilder,
+    signers::local::PrivateKeySigner,
+    sol,
+    transports::http::reqwest::Url,
+};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        payment_channel::{
+            channel::PaymentChannelABI,
+            types::{PaymentChannel as PaymentChannelType, CHANNEL_FACTORY_ADDRESS},
+        },
+        rpc::get_rpc_pool,
+    },
+};
+
+sol!(
+    #[allow(missing_docs, clippy::too_many_arguments)]
+    #[sol(rpc)]
+    ChannelFactoryABI,
+    "src/abi/ChannelFactory.json"
+);
+
+/// Known ChannelFactory deployment for a given `chain_id`, if any
+pub fn get_channel_factory_from_chain_id(chain_id: &u64) -> Option<Address> {
+    match chain_id {
+        84532 => Address::from_str(CHANNEL_FACTORY_ADDRESS).ok(), // Base Sepolia
+        _ => None,
+    }
+}
+
+// Register as an API provider or update the price per request
+pub async fn register_provider(
+    rpc_url: Url,
+    private_key: &str,
+    factory_address: Address,
+    price: U256,
+) -> Result<FixedBytes<32>, AuthError> {
+    let signer: PrivateKeySigner = private_key
+        .parse()
+        .map_err(|_| AuthError::InvalidRequest("Invalid private key".to_string()))?;
+    let wallet = EthereumWallet::from(signer);
+
+    let provider = ProviderBuilder::new().wallet(wallet).on_http(rpc_url);
+
+    let factory_contract = ChannelFactoryABI::new(factory_address, provider);
+
+    let tx_hash = factory_contract
+        .register(price)
+        .send()
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to register provider: {}", e)))?
+        .watch()
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to register provider: {}", e)))?;
+
+    Ok(tx_hash)
+}
+
+// Price per request registered by the provider, zero if not registered
+pub async fn get_provider_price(
+    rpc_url: &str,
+    factory_address: Address,
+    recipient: Address,
+) -> Result<U256, AuthError> {
+    let pool = get_rpc_pool(rpc_url)?;
+
+    let price = pool
+        .call(|provider| async move {
+            ChannelFactoryABI::new(factory_address, provider)
+                .pricing(recipient)
+                .call()
+                .await
+        })
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to fetch pricing: {}", e)))?
+        ._0;
+
+    Ok(price)
+}
+
+// Enumerate all the channels opened to the recipient, as seen on-chain
+pub async fn get_recipient_channels(
+    rpc_url: &str,
+    factory_address: Address,
+    recipient: Address,
+) -> Result<Vec<PaymentChannelType>, AuthError> {
+    let pool = get_rpc_pool(rpc_url)?;
+
+    let channel_ids = pool
+        .call(|provider| async move {
+            ChannelFactoryABI::new(factory_address, provider)
+                .getRecipientChannels(recipient)
+                .call()
+                .await
+        })
+        .await
+        .map_err(|e| {
+            AuthError::ContractError(format!("Failed to fetch recipient channels: {}", e))
+        })?
+        ._0;
+
+    let mut channels = Vec::with_capacity(channel_ids.len());
+    for channel_id in channel_ids {
+        let info = pool
+            .call(|provider| async move {
+                ChannelFactoryABI::new(factory_address, provider)
+                    .getChannelInfo(channel_id)
+                    .call()
+                    .await
+            })
+            .await
+            .map_err(|e| {
+                AuthError::ContractError(format!("Failed to fetch channel info: {}", e))
+            })?;
+
+        if !info.exists {
+            continue;
+        }
+
+        channels.push(PaymentChannelType {
+            address: info.channelAddress,
+            sender: info.sender,
+            recipient: info.recipient,
+            balance: info.balance,
+            nonce: info.lastNonce,
+            expiration: info.expiration,
+            channel_id: info.id,
+        });
+    }
+
+    Ok(channels)
+}
+
+// Verify the presented channel contract was deployed by the trusted factory under the same channel id
+pub async fn verify_channel_factory(
+    rpc_url: &str,
+    factory_address: Address,
+    payment_channel: &PaymentChannelType,
+) -> Result<(), AuthError> {
+    let pool = get_rpc_pool(rpc_url)?;
+    let channel_id = payment_channel.channel_id;
+    let channel_address = payment_channel.address;
+
+    let registered_address = pool
+        .call(|provider| async move {
+            ChannelFactoryABI::new(factory_address, provider)
+                .channels(channel_id)
+                .call()
+                .await
+        })
+        .await
+        .map_err(|e| AuthError::ContractError(format!("Failed to fetch channel: {}", e)))?
+        ._0;
+
+    if registered_address != payment_channel.address {
+        return Err(AuthError::InvalidChannel(format!(
+            "Channel not deployed by factory - expected: {}, received: {}",
+            registered_address, payment_channel.address
+        )));
+    }
+
+    let channel_factory = pool
+        .call(|provider| async move {
+            PaymentChannelABI::new(channel_address, provider)
+                .factory()
+                .call()
+                .await
+        })
+        .await
+        .map_err(AuthError::ContractError)?
+        ._0;
+
+    if channel_factory != factory_address {
+        return Err(AuthError::InvalidChannel(format!(
+            "Factory mismatch - expected: {}, channel factory: {}",
+            factory_address, channel_factory
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address, B256},
+    providers::{Provider, ProviderBuilder, WsConnect},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+};
+
+use super::{
+    channel::{ChannelState, PaymentChannelABI},
+    types::ChannelListenerConfig,
+};
+use tracing::{debug, error, info, warn};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct ChannelListener {
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl ChannelListener {
+    pub async fn new(state: ChannelState, listener_config: ChannelListenerConfig) -> Self {
+        let handle = tokio::spawn(async move {
+            debug!("Spawning channel event listener");
+
+            if let Some(wss_url) = listener_config.wss_url.clone() {
+                match Self::start(state.clone(), wss_url).await {
+                    Ok(()) => warn!("Channel event subscription ended, falling back to polling"),
+                    Err(e) => error!(error = %e, "Channel event listener error"),
+                }
+            }
+
+            if let Err(e) = Self::poll(state, listener_config).await {
+                error!(error = %e, "Channel event polling error");
+            }
+        });
+
+        Self { handle }
+    }
+
+    /// Events emitted by the `PaymentChannel` contract that affect the cached channel state
+    fn event_signatures() -> Vec<B256> {
+        vec![
+            PaymentChannelABI::DepositMade::SIGNATURE_HASH,
+            PaymentChannelABI::ExpirationExtended::SIGNATURE_HASH,
+            PaymentChannelABI::ChannelClosed::SIGNATURE_HASH,
+            PaymentChannelABI::TimeoutClaimed::SIGNATURE_HASH,
+        ]
+    }
+
+    /// Subscribe to channel events over websocket
+    pub async fn start(state: ChannelState, wss_url: String) -> Result<(), String> {
+        info!("Starting channel event listener");
+
+        let ws = WsConnect::new(wss_url);
+
+        let provider = ProviderBuilder::new().on_ws(ws).await.map_err(|e| {
+            warn!(error = ?e, "Error connecting to websocket");
+            "Error connecting to websocket".to_string()
+        })?;
+
+        // Channels are separate proxy contracts, so filter by event signature and match the address on receipt
+        let filter = Filter::new()
+            .event_signature(Self::event_signatures())
+            .from_block(BlockNumberOrTag::Latest);
+
+        let mut sub = provider.subscribe_logs(&filter).await.map_err(|e| {
+            warn!(error = ?e, "Error subscribing to channel events");
+            "Error subscribing to channel events".to_string()
+        })?;
+
+        debug!("Subscribed to channel events");
+
+        while let Ok(log) = sub.recv().await {
+            Self::apply_log(&state, &log).await;
+        }
+
+        Ok(())
+    }
+
+    /// Poll channel events over HTTP, used when no websocket endpoint is available
+    pub async fn poll(
+        state: ChannelState,
+        listener_config: ChannelListenerConfig,
+    ) -> Result<(), String> {
+        info!("Starting channel event polling");
+
+        let rpc_url = listener_config
+            .rpc_url
+            .parse()
+            .map_err(|_| "Invalid RPC url".to_string())?;
+        let provider = ProviderBuilder::new().on_http(rpc_url);
+        let interval = Duration::from_secs(listener_config.poll_interval_sec.max(1));
+
+        let mut last_block = provider
+            .get_block_number()
+            .await
+            .map_err(|e| format!("Error fetching block number: {}", e))?;
+
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let latest_block = match provider.get_block_number().await {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!(error = ?e, "Error fetching block number");
+                    continue;
+                }
+            };
+
+            if latest_block <= last_block {
+                continue;
+            }
+
+            let addresses: Vec<Address> = state
+                .channels
+                .read()
+                .await
+                .values()
+                .map(|channel| channel.address)
+                .collect();
+
+            if !addresses.is_empty() {
+                let filter = Filter::new()
+                    .address(addresses)
+                    .event_signature(Self::event_signatures())
+                    .from_block(last_block + 1)
+                    .to_block(latest_block);
+
+                match provider.get_logs(&filter).await {
+                    Ok(logs) => {
+                        for log in logs {
+                            Self::apply_log(&state, &log).await;
+                        }
+                    }
+                    Err(e) => {
+                        // Retry the same block range on the next tick
+                        warn!(error = ?e, "Error fetching channel events");
+                        continue;
+                    }
+                }
+            }
+
+            last_block = latest_block;
+        }
+    }
+
+    /// Apply a single channel event to the cached channel, ignoring channels we don't track
+    pub async fn apply_log(state: &ChannelState, log: &Log) {
+        let Some(channel_topic) = log.topics().get(1) else {
+            return;
+        };
+        let channel_id = (*channel_topic).into();
+
+        // Only trust events emitted by the contract we have cached for this channel id
+        match state.get_channel(channel_id).await {
+            Some(channel) if channel.address == log.address() => {}
+            _ => return,
+        }
+
+        match log.topic0() {
+            Some(&PaymentChannelABI::DepositMade::SIGNATURE_HASH) => {
+                if let Ok(event) = log.log_decode::<PaymentChannelABI::DepositMade>() {
+                    info!(%channel_id, amount = %event.inner.amount, "Deposit made on channel");
+                    state
+                        .apply_deposit(channel_id, event.inner.newBalance)
+                        .await;
+                }
+            }
+            Some(&PaymentChannelABI::ExpirationExtended::SIGNATURE_HASH) => {
+                if let Ok(event) = log.log_decode::<PaymentChannelABI::ExpirationExtended>() {
+                    info!(
+                        %channel_id,
+                        expiration = %event.inner.newExpiration,
+                        "Expiration extended on channel"
+                    );
+                    state
+                        .apply_expiration(channel_id, event.inner.newExpiration)
+                        .await;
+                }
+            }
+            Some(&PaymentChannelABI::ChannelClosed::SIGNATURE_HASH)
+            | Some(&PaymentChannelABI::TimeoutClaimed::SIGNATURE_HASH) => {
+                info!(%channel_id, "Channel closed on-chain");
+                state.mark_closed(channel_id).await;
+            }
+            _ => {}
+        }
+    }
+}
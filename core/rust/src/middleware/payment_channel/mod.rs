@@ -1,4 +1,7 @@
 pub mod channel;
+pub mod factory;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod listener;
 pub mod types;
 pub mod utils;
 pub mod verify;
@@ -17,6 +20,9 @@ use axum::{
 use tower::{Layer, Service};
 
 use channel::ChannelState;
+#[cfg(not(target_arch = "wasm32"))]
+pub use listener::ChannelListener;
+use tracing::debug;
 use types::PaymentChannelConfig;
 use utils::{modify_headers_axum, parse_headers};
 use verify::verify_and_update_channel;
@@ -95,15 +101,12 @@ where
 
         // #[cfg(not(target_arch = "wasm32"))]
         Box::pin(async move {
-            println!("\n=== auth_middleware ===");
-            println!(" === new request ===");
-
             // Get request body
             let (parts, body) = request.into_parts();
             let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
                 Ok(bytes) => bytes,
                 Err(_) => {
-                    println!("Failed: Body decode");
+                    debug!("Failed: Body decode");
                     return Ok(
                         AuthError::InvalidRequest("Failed to decode body".to_string())
                             .into_response(),
@@ -115,7 +118,7 @@ where
                 match parse_headers(&parts.headers, body_bytes.to_vec(), payment_amount).await {
                     Ok(signed_request) => signed_request,
                     Err(e) => {
-                        println!("Failed: Parse headers");
+                        debug!("Failed: Parse headers");
                         return Ok(e.into_response());
                     }
                 };
@@ -126,20 +129,13 @@ where
                 match verify_and_update_channel(&state, &config, signed_request).await {
                     Ok((updated_channel, verify)) => (updated_channel, verify),
                     Err(e) => {
-                        println!("Failed: Verify and update channel");
+                        debug!("Failed: Verify and update channel");
                         return Ok(e.into_response());
                     }
                 };
 
-            state
-                .channels
-                .write()
-                .await
-                .insert(updated_channel.channel_id, updated_channel.clone());
-
             if verify {
-                println!("Verified");
-                println!("=== end middleware check ===");
+                debug!("Verified");
 
                 let request = Request::from_parts(parts, Body::from(body_bytes));
                 let response = inner.call(request).await?;
@@ -163,15 +159,12 @@ pub async fn payment_channel_auth_fn_middleware(
     request: Request<Body>,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    println!("\n=== auth_middleware ===");
-    println!(" === new request ===");
-
     // Get request body
     let (parts, body) = request.into_parts();
     let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
         Ok(bytes) => bytes,
         Err(_) => {
-            println!("Failed: Body decode");
+            debug!("Failed: Body decode");
             return Ok(
                 AuthError::InvalidRequest("Failed to decode body".to_string()).into_response(),
             );
@@ -182,7 +175,7 @@ pub async fn payment_channel_auth_fn_middleware(
         match parse_headers(&parts.headers, body_bytes.to_vec(), state.config.amount).await {
             Ok(signed_request) => signed_request,
             Err(e) => {
-                println!("Failed: Parse headers");
+                debug!("Failed: Parse headers");
                 return Ok(e.into_response());
             }
         };
@@ -193,23 +186,15 @@ pub async fn payment_channel_auth_fn_middleware(
         match verify_and_update_channel(&state.state, &state.config, signed_request).await {
             Ok((updated_channel, verify)) => (updated_channel, verify),
             Err(e) => {
-                println!("Failed: Verify and update channel");
+                debug!("Failed: Verify and update channel");
                 return Ok(e.into_response());
             }
         };
 
-    state
-        .state
-        .channels
-        .write()
-        .await
-        .insert(updated_channel.channel_id, updated_channel.clone());
-
     let request = Request::from_parts(parts, Body::from(body_bytes));
 
     if verify {
-        println!("Verified");
-        println!("=== end middleware check ===");
+        debug!("Verified");
 
         let response = next.run(request).await;
         let response = modify_headers_axum(response, &updated_channel);
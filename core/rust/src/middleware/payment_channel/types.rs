@@ -22,6 +22,31 @@ pub struct PaymentChannel {
     pub channel_id: U256,
 }
 
+// Server side accounting of a channel
+// `deposited` is the total locked in the channel contract and `spent` the cumulative amount the sender has signed over so far
+#[serde_as]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChannelAccount {
+    #[serde_as(as = "DisplayFromStr")]
+    pub deposited: U256,
+
+    #[serde_as(as = "DisplayFromStr")]
+    pub spent: U256,
+
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(default)]
+    pub owed: U256, // post-paid charges the next voucher has to cover
+}
+
+impl ChannelAccount {
+    /// Balance still available to the sender
+    pub fn remaining(&self) -> U256 {
+        self.deposited
+            .saturating_sub(self.spent)
+            .saturating_sub(self.owed)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignedRequest {
     pub message: Vec<u8>,
@@ -38,4 +63,16 @@ pub struct PaymentChannelConfig {
     pub token_address: Address,
     pub amount: U256, // amount for the one-time payment
     pub rpc_url: String,
+    #[serde(default)]
+    pub factory_address: Option<Address>, // if set, only channels deployed by this factory are accepted
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelListenerConfig {
+    pub wss_url: Option<String>, // preferred, falls back to polling `rpc_url` if missing or failing
+    pub rpc_url: String,
+    pub poll_interval_sec: u64,
+}
+
+pub const TIMESTAMP_WINDOW_SEC: u64 = 300; // 5 minutes, signed requests outside this window are rejected
+pub const CHANNEL_FACTORY_ADDRESS: &str = "0x5acfbe1f9B0183Ef7F2F8d8993d76f24B862092d";
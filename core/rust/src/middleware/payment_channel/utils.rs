@@ -6,10 +6,14 @@ use alloy::{
 use axum::{body::Body, http::Response};
 use http::HeaderMap;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, trace};
 
 use crate::{
     error::AuthError,
-    middleware::payment_channel::types::{PaymentChannel, SignedRequest},
+    middleware::{
+        payment_channel::types::{PaymentChannel, SignedRequest},
+        utils::redact,
+    },
 };
 
 pub async fn parse_headers(
@@ -24,65 +28,77 @@ pub async fn parse_headers(
         .and_then(|t| t.parse::<u64>().ok())
         .ok_or(AuthError::MissingHeaders)?;
 
-    println!("Timestamp: {}", timestamp);
-
     // Get and validate all required headers
     let signature = headers
         .get("X-Signature")
         .ok_or(AuthError::MissingHeaders)?
         .to_str()
-        .map_err(|_| AuthError::InvalidHeaders("X-Signature header contains invalid UTF-8 characters".to_string()))?;
+        .map_err(|_| {
+            AuthError::InvalidHeaders(
+                "X-Signature header contains invalid UTF-8 characters".to_string(),
+            )
+        })?;
 
     let message = headers
         .get("X-Message")
         .ok_or(AuthError::MissingHeaders)?
         .to_str()
-        .map_err(|_| AuthError::InvalidHeaders("X-Message header contains invalid UTF-8 characters".to_string()))?;
+        .map_err(|_| {
+            AuthError::InvalidHeaders(
+                "X-Message header contains invalid UTF-8 characters".to_string(),
+            )
+        })?;
 
     let payment_data = headers
         .get("X-Payment")
         .ok_or(AuthError::MissingHeaders)?
         .to_str()
-        .map_err(|_| AuthError::InvalidHeaders("X-Payment header contains invalid UTF-8 characters".to_string()))?;
+        .map_err(|_| {
+            AuthError::InvalidHeaders(
+                "X-Payment header contains invalid UTF-8 characters".to_string(),
+            )
+        })?;
 
-    // Print all the headers
-    println!("Signature: {}", signature);
-    println!("Message: {}", message);
-    println!("Payment Data: {}", payment_data);
+    debug!(
+        signature = %redact(signature),
+        message_len = message.len(),
+        timestamp,
+        "Parsing payment channel headers"
+    );
 
     // Parse signature
     let signature = hex::decode(signature.trim_start_matches("0x"))
         .map_err(|_| {
-            println!("Failed: Signature decode");
+            debug!("Failed: Signature decode");
             AuthError::InvalidSignature
         })
         .and_then(|bytes| {
             PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                println!("Failed: Signature conversion");
+                debug!("Failed: Signature conversion");
                 AuthError::InvalidSignature
             })
         })?;
 
     // Parse message
     let message = hex::decode(message).map_err(|_| {
-        println!("Failed: Message decode");
+        debug!("Failed: Message decode");
         AuthError::InvalidMessage
     })?;
 
     // Parse payment channel data
     let payment_channel: PaymentChannel = serde_json::from_str(payment_data).map_err(|e| {
-        println!("Failed: Payment data decode - Error {}", e);
+        debug!(error = %e, "Failed: Payment data decode");
         AuthError::InvalidChannel(format!("Failed to parse payment channel data: {}", e))
     })?;
 
-    println!("Body: {}", String::from_utf8_lossy(&body_bytes));
+    trace!(body_len = body_bytes.len(), "Request body read");
 
     let signed_request = SignedRequest {
         message,
         signature,
         payment_channel,
         payment_amount,
-        body_bytes: body_bytes,
+        body_bytes,
         timestamp,
     };
 
@@ -156,3 +172,15 @@ pub fn create_channel_message(
 
     hashed_message.to_vec()
 }
+
+/// Hash binding a voucher to the request it pays for, signed as the voucher body instead of the raw request body
+/// `keccak256(abi.encode(method, path, keccak256(body)))`, the strings are length-prefixed so the path and body can't be split differently
+pub fn create_request_hash(method: &str, path: &str, body: &[u8]) -> Vec<u8> {
+    let request = DynSolValue::Tuple(vec![
+        DynSolValue::String(method.to_uppercase()),
+        DynSolValue::String(path.to_string()),
+        DynSolValue::FixedBytes(keccak256(body), 32),
+    ]);
+
+    keccak256(request.abi_encode_params()).to_vec()
+}
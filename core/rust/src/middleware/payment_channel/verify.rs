@@ -2,155 +2,121 @@ use alloy::{
     hex::{self},
     primitives::U256,
 };
+use tracing::debug;
 
 use crate::{
     error::AuthError,
     middleware::{
         payment_channel::{
             channel::ChannelState,
-            types::{PaymentChannel, PaymentChannelConfig, SignedRequest},
+            types::{
+                ChannelAccount, PaymentChannel, PaymentChannelConfig, SignedRequest,
+                TIMESTAMP_WINDOW_SEC,
+            },
             utils::create_channel_message,
         },
-        utils::get_current_time,
+        utils::{get_current_time, redact},
     },
 };
 
 pub async fn verify_and_update_channel(
     state: &ChannelState,
     config: &PaymentChannelConfig,
-    mut request: SignedRequest,
+    request: SignedRequest,
 ) -> Result<(PaymentChannel, bool), AuthError> {
-    println!("\n=== verify_and_update_channel ===");
-    println!("Payment amount: {}", request.payment_amount);
-    println!(
-        "Payment channel: {}",
-        serde_json::to_string(&request.payment_channel).map_err(|_| AuthError::InternalError)?
-    );
-    println!("Channel balance: {}", request.payment_channel.balance);
-
-    println!("Message length: {}", request.message.len());
-    println!("Original message: 0x{}", hex::encode(&request.message));
+    let channel_id = request.payment_channel.channel_id;
 
-    let now = get_current_time();
+    // Requests on the same channel are verified and stored one at a time
+    let _guard = state.lock_channel(channel_id).await;
 
-    if now - request.timestamp > 300 {
-        return Err(AuthError::TimestampError);
+    // Reject channels already closed or timed out on-chain
+    if state.is_closed(channel_id).await {
+        return Err(AuthError::ChannelClosed);
     }
 
-    // Verify that the message matches what we expect
-    let reconstructed_message = create_channel_message(
-        request.payment_channel.channel_id,
-        request.payment_channel.balance,
-        request.payment_channel.nonce,
-        &request.body_bytes,
-    );
+    let existing = match (
+        state.get_channel(channel_id).await,
+        state.get_account(channel_id).await,
+    ) {
+        (Some(channel), Some(account)) => Some((channel, account)),
+        _ => None,
+    };
 
-    if request.message != reconstructed_message {
-        println!("Failed: Message mismatch");
-        return Err(AuthError::InvalidMessage);
-    } else {
-        println!("Message match");
-    }
+    let (signed_channel, account) = verify_request(state, config, &request, existing).await?;
 
-    // Verify signature
+    // Store the channel as signed by the sender, this is what gets submitted on close
+    // Still under the channel lock so the channel, account and signature are updated together
     state
-        .verify_signature(
-            &request.payment_channel,
-            &request.signature,
-            &request.message,
-        )
-        .await?;
-
-    let mut channels = state.channels.write().await;
-
-    // Check if the channel is not expired with the current timestamp
-    let now = get_current_time();
-
-    if request.payment_channel.expiration < U256::from(now) {
-        return Err(AuthError::Expired);
-    }
-
-    // Check if channel exists
-    // NOTE: Nonce validation can be skipped as the balance will be acting as nonce here, the sender will always send the tx with the highest balance, we'll check for that here within our local record
-    if let Some(existing_channel) = channels.get(&request.payment_channel.channel_id) {
-        println!("Existing channel found");
-        // Ensure new nonce is greater than existing nonce
-        if request.payment_channel.nonce <= existing_channel.nonce {
-            println!(
-                "Failed: Invalid nonce - current: {}, received: {}",
-                existing_channel.nonce, request.payment_channel.nonce
-            );
-            return Err(AuthError::InvalidNonce);
-        } else {
-            println!("Nonce match");
-        }
-
-        if request.payment_channel.balance != existing_channel.balance {
-            println!(
-                "Failed: Invalid balance - current: {}, received: {}",
-                existing_channel.balance, request.payment_channel.balance
-            );
-            return Err(AuthError::InvalidChannel(format!(
-                "Balance mismatch in verify_and_update_channel - expected: {}, received: {}",
-                existing_channel.balance, request.payment_channel.balance
-            )));
-        } else {
-            println!("Balance match");
-        }
-    } else {
-        println!("New channel found");
-
-        // Verify that the channel contract data is correct
-        // 1. Verify the balance is available in the contract as the channel balance
-        // 2. Verify the expiration is in the future
-        // 3. Verify the channel ID is correct
-        state
-            .validate_channel(config, &request.payment_channel)
-            .await?;
-
-        // Ensure the nonce is 0
-        if request.payment_channel.nonce != U256::from(0) {
-            return Err(AuthError::InvalidNonce);
-        }
-    }
-
-    // NOTE: Update Balance for updating the local state, deducting the balance from the channel
-    println!("Updating channel state");
-    request.payment_channel.balance -= request.payment_amount;
+        .channels
+        .write()
+        .await
+        .insert(channel_id, signed_channel.clone());
 
-    // Update or insert the channel
-    channels.insert(
-        request.payment_channel.channel_id,
-        request.payment_channel.clone(),
-    );
+    state
+        .accounts
+        .write()
+        .await
+        .insert(channel_id, account.clone());
 
-    drop(channels);
+    state
+        .update_latest_signature(channel_id, request.signature)
+        .await;
 
     state
-        .update_latest_signature(request.payment_channel.channel_id, request.signature)
+        .update_signed_body(channel_id, request.body_bytes.into())
         .await;
 
-    println!("API request authorized");
-    Ok((request.payment_channel.clone(), true))
+    debug!("API request authorized");
+    Ok((channel_with_remaining(signed_channel, &account), true))
 }
 
 // Verify the channel and return the updated channel
-// No need of a state object here as it's just a verification
+// No need of a state object here as it's just a verification, `current_channel` is the channel last returned by the server
 pub async fn verify_channel(
     config: PaymentChannelConfig,
-    mut request: SignedRequest,
+    request: SignedRequest,
     current_channel: Option<PaymentChannel>,
 ) -> Result<(PaymentChannel, bool), AuthError> {
-    println!("\n=== verify_channel ===");
-    println!("Payment amount: {}", request.payment_amount);
-    println!(
-        "Payment channel: {}",
-        serde_json::to_string(&request.payment_channel).map_err(|_| AuthError::InternalError)?
+    // Create a temporary state object, only used for the on-chain validation
+    let state = ChannelState::new();
+
+    // Without a local record, the remaining balance of the current channel is all the sender can still spend
+    let existing = current_channel.map(|channel| {
+        let account = ChannelAccount {
+            deposited: channel.balance,
+            ..Default::default()
+        };
+        (channel, account)
+    });
+
+    let (signed_channel, account) = verify_request(&state, &config, &request, existing).await?;
+
+    debug!("API request authorized");
+    Ok((channel_with_remaining(signed_channel, &account), true))
+}
+
+// Shared verification for a signed channel request against the last known record of the channel, if any
+// Returns the channel as signed by the sender and the updated accounting
+async fn verify_request(
+    state: &ChannelState,
+    config: &PaymentChannelConfig,
+    request: &SignedRequest,
+    existing: Option<(PaymentChannel, ChannelAccount)>,
+) -> Result<(PaymentChannel, ChannelAccount), AuthError> {
+    debug!(
+        payment_amount = %request.payment_amount,
+        sender = %request.payment_channel.sender,
+        balance = %request.payment_channel.balance,
+        nonce = %request.payment_channel.nonce,
+        signature = %redact(&hex::encode(request.signature.as_bytes())),
+        "Verifying channel request"
     );
-    println!("Channel balance: {}", request.payment_channel.balance);
 
-    println!("Message length: {}", request.message.len());
-    println!("Original message: 0x{}", hex::encode(&request.message));
+    // Both stale and future timestamps are rejected
+    let now = get_current_time();
+    if now.abs_diff(request.timestamp) > TIMESTAMP_WINDOW_SEC {
+        return Err(AuthError::TimestampError);
+    }
 
     // Verify that the message matches what we expect
     let reconstructed_message = create_channel_message(
@@ -161,25 +127,11 @@ pub async fn verify_channel(
     );
 
     if request.message != reconstructed_message {
-        println!("Failed: Message mismatch");
+        debug!("Failed: Message mismatch");
         return Err(AuthError::InvalidMessage);
-    } else {
-        println!("Message match");
-    }
-
-    // Create a temporary state object
-    let state = ChannelState::new();
-
-    // If currentChannel is present add it to the state
-    if let Some(channel) = current_channel {
-        state
-            .channels
-            .write()
-            .await
-            .insert(channel.channel_id, channel);
     }
 
-    // Verify signature using network-specific logic
+    // Verify signature
     state
         .verify_signature(
             &request.payment_channel,
@@ -188,62 +140,110 @@ pub async fn verify_channel(
         )
         .await?;
 
-    let channels = state.channels.write().await;
+    let mut signed_channel = request.payment_channel.clone();
 
-    // Check if the channel is not expired with the current timestamp
-    let now = get_current_time();
-    if request.payment_channel.expiration < U256::from(now) {
-        return Err(AuthError::Expired);
-    }
+    let account = if let Some((existing_channel, account)) = existing {
+        debug!("Existing channel found");
+
+        // The channel presented must be the one we know, we trust our record over the request for everything else
+        if signed_channel.address != existing_channel.address
+            || signed_channel.sender != existing_channel.sender
+            || signed_channel.recipient != existing_channel.recipient
+        {
+            return Err(AuthError::InvalidChannel(format!(
+                "Channel mismatch for channel id {}",
+                signed_channel.channel_id
+            )));
+        }
 
-    // Check if channel exists
-    // NOTE: Nonce validation can be skipped as the balance will be acting as nonce here, the sender will always send the tx with the highest balance, we'll check for that here within our local record
-    if let Some(existing_channel) = channels.get(&request.payment_channel.channel_id) {
-        println!("Existing channel found");
         // Ensure new nonce is greater than existing nonce
-        if request.payment_channel.nonce <= existing_channel.nonce {
-            println!(
-                "Failed: Invalid nonce - current: {}, received: {}",
-                existing_channel.nonce, request.payment_channel.nonce
+        if signed_channel.nonce <= existing_channel.nonce {
+            debug!(
+                current = %existing_channel.nonce,
+                received = %signed_channel.nonce,
+                "Failed: Invalid nonce"
             );
             return Err(AuthError::InvalidNonce);
-        } else {
-            println!("Nonce match");
         }
 
-        if request.payment_channel.balance != existing_channel.balance {
-            println!(
-                "Failed: Invalid balance - current: {}, received: {}",
-                existing_channel.balance, request.payment_channel.balance
-            );
-            return Err(AuthError::InvalidChannel(format!(
-                "Balance mismatch in verify_channel - expected: {}, received: {}",
-                existing_channel.balance, request.payment_channel.balance
-            )));
-        } else {
-            println!("Balance match");
-        }
+        // Expiration can only change through on-chain events
+        signed_channel.expiration = existing_channel.expiration;
+
+        account
     } else {
-        println!("New channel found");
+        debug!("New channel found");
 
         // Verify that the channel contract data is correct
-        // 1. Verify the balance is available in the contract as the channel balance
-        // 2. Verify the expiration is in the future
+        // 1. Verify the signed balance is available in the contract as the channel balance
+        // 2. Verify the expiration matches the contract
         // 3. Verify the channel ID is correct
-        state
-            .validate_channel(&config, &request.payment_channel)
-            .await?;
+        let deposited = state.validate_channel(config, &signed_channel).await?;
 
         // Ensure the nonce is 0
-        if request.payment_channel.nonce != U256::from(0) {
+        if signed_channel.nonce != U256::ZERO {
             return Err(AuthError::InvalidNonce);
         }
+
+        ChannelAccount {
+            deposited,
+            ..Default::default()
+        }
+    };
+
+    // Check if the channel is not expired with the current timestamp
+    if signed_channel.expiration < U256::from(now) {
+        return Err(AuthError::Expired);
+    }
+
+    let account = charge_channel(&account, signed_channel.balance, request.payment_amount)?;
+
+    Ok((signed_channel, account))
+}
+
+/// Charge `payment_amount` against the channel using the remaining balance signed by the sender.
+///
+/// The signed balance is what the sender agrees to leave in the channel, so `deposited - signed_balance`
+/// is the cumulative amount authorised so far. It must cover everything spent before, any post-paid charge
+/// still owed, plus this request. Anything signed above that is kept as spent.
+pub fn charge_channel(
+    account: &ChannelAccount,
+    signed_balance: U256,
+    payment_amount: U256,
+) -> Result<ChannelAccount, AuthError> {
+    if account.remaining() < payment_amount {
+        return Err(AuthError::InsufficientBalance);
     }
 
-    // NOTE: Update Balance for updating the local state, deducting the balance from the channel
-    println!("Updating channel state");
-    request.payment_channel.balance -= request.payment_amount;
+    let cumulative = account
+        .deposited
+        .checked_sub(signed_balance)
+        .ok_or_else(|| {
+            AuthError::InvalidChannel(format!(
+                "Signed balance exceeds deposit - deposited: {}, signed: {}",
+                account.deposited, signed_balance
+            ))
+        })?;
+
+    // Can't overflow as spent + owed + payment_amount <= deposited, checked above
+    let required = account.spent + account.owed + payment_amount;
+
+    if cumulative < required {
+        return Err(AuthError::InvalidChannel(format!(
+            "Signed amount doesn't cover the request - required balance at most: {}, signed: {}",
+            account.deposited - required,
+            signed_balance
+        )));
+    }
+
+    Ok(ChannelAccount {
+        deposited: account.deposited,
+        spent: cumulative,
+        owed: U256::ZERO,
+    })
+}
 
-    println!("API request authorized");
-    Ok((request.payment_channel.clone(), true))
+// The channel returned to the sender carries the balance left to sign against for the next request
+fn channel_with_remaining(mut channel: PaymentChannel, account: &ChannelAccount) -> PaymentChannel {
+    channel.balance = account.remaining();
+    channel
 }
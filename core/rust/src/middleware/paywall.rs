@@ -0,0 +1,175 @@
+// HTML paywall served instead of the JSON 402 to browsers
+// The requirements are embedded as JSON for a wallet script to pay with and reload the page
+
+use crate::middleware::types::PaymentRequiredResponse;
+
+pub const PAYWALL_REQUIREMENTS_ID: &str = "x402-payment-requirements"; // id of the script element holding the requirements
+
+// Placeholders: {{title}}, {{error}}, {{resource}}, {{options}}, {{requirements}} and {{script}}
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<style>
+body { font-family: system-ui, sans-serif; max-width: 36rem; margin: 4rem auto; padding: 0 1rem; color: #222; }
+li { margin: 0.5rem 0; }
+.resource { word-break: break-all; color: #555; }
+</style>
+</head>
+<body>
+<h1>{{title}}</h1>
+<p class="resource">{{resource}}</p>
+<p>Pay with one of:</p>
+<ul>{{options}}</ul>
+<p><small>{{error}}</small></p>
+<script id="x402-payment-requirements" type="application/json">{{requirements}}</script>
+{{script}}
+</body>
+</html>
+"#;
+
+/// Page rendered for 402s when the client prefers HTML, see `MiddlewareConfig::with_paywall`
+#[derive(Clone, Debug)]
+pub struct PaywallConfig {
+    pub title: String,
+    pub template: String, // HTML with the placeholders of the default template
+    pub script_url: Option<String>, // wallet script reading the requirements and paying
+}
+
+impl Default for PaywallConfig {
+    fn default() -> Self {
+        Self {
+            title: "Payment Required".to_string(),
+            template: DEFAULT_TEMPLATE.to_string(),
+            script_url: None,
+        }
+    }
+}
+
+impl PaywallConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// Replace the default page, `{{requirements}}` is the 402 JSON to embed in a `<script type="application/json">`
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    pub fn with_script(mut self, script_url: &str) -> Self {
+        self.script_url = Some(script_url.to_string());
+        self
+    }
+
+    pub fn render(&self, payment_required: &PaymentRequiredResponse) -> String {
+        let resource = payment_required
+            .accepts
+            .first()
+            .map(|accept| accept.resource.as_str())
+            .unwrap_or_default();
+        let options: String = payment_required
+            .accepts
+            .iter()
+            .map(|accept| {
+                format!(
+                    "<li>{} {} on {} <small>({})</small></li>",
+                    escape_html(&accept.amount),
+                    escape_html(&accept.asset),
+                    escape_html(&accept.network),
+                    escape_html(&accept.scheme),
+                )
+            })
+            .collect();
+        let script = self
+            .script_url
+            .as_ref()
+            .map(|url| format!(r#"<script src="{}" defer></script>"#, escape_html(url)))
+            .unwrap_or_default();
+
+        let title = escape_html(&self.title);
+        let error = escape_html(&payment_required.error);
+        let resource = escape_html(resource);
+        let requirements = script_json(payment_required);
+        let value = |name: &str| match name {
+            "title" => Some(title.as_str()),
+            "error" => Some(error.as_str()),
+            "resource" => Some(resource.as_str()),
+            "options" => Some(options.as_str()),
+            "requirements" => Some(requirements.as_str()),
+            "script" => Some(script.as_str()),
+            _ => None,
+        };
+
+        // In a single pass over the template, placeholders in the substituted values are left as they are
+        let mut page = String::with_capacity(self.template.len() + requirements.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("{{") {
+            page.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after
+                .find("}}")
+                .and_then(|end| value(&after[..end]).map(|value| (end, value)))
+            {
+                Some((end, value)) => {
+                    page.push_str(value);
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    page.push_str("{{");
+                    rest = after;
+                }
+            }
+        }
+        page.push_str(rest);
+        page
+    }
+}
+
+/// Whether `Accept` prefers an HTML page over JSON, as browsers navigating to a page do
+/// `*/*` alone is answered with JSON, API clients keep the x402 response
+pub fn prefers_html(accept: &str) -> bool {
+    let mut html = 0.0;
+    let mut json = 0.0;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_type = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" => html = f32::max(html, quality),
+            "application/json" | "*/*" => json = f32::max(json, quality),
+            _ => {}
+        }
+    }
+    html > 0.0 && html > json
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// JSON safe inside a script element, `<` can't close it once escaped
+fn script_json(payment_required: &PaymentRequiredResponse) -> String {
+    serde_json::to_string(payment_required)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+}
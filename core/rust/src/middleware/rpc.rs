@@ -0,0 +1,289 @@
+// RPC provider pool
+// Providers are built once per endpoint and shared by the verifiers, each call is retried with backoff and
+// fails over to the next endpoint on transport errors, rate limits or timeouts
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use alloy::{
+    contract,
+    primitives::{Address, Bytes, FixedBytes},
+    providers::{Provider, ProviderBuilder, RootProvider},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    sol_types::SolCall,
+    transports::{
+        http::{Client, Http},
+        RpcError, TransportError,
+    },
+};
+use tracing::warn;
+
+use crate::error::AuthError;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::middleware::multicall::{queue_call, queue_receipt, BatchQueues};
+
+pub type HttpProvider = RootProvider<Http<Client>>;
+
+#[derive(Clone, Debug)]
+pub struct RpcPoolConfig {
+    pub timeout: Duration,              // per attempt, not enforced on wasm
+    pub max_retries: u32,               // rounds over all the endpoints after the first one
+    pub initial_backoff: Duration,      // doubled after each round
+    pub batch_window: Option<Duration>, // if set, reads within this window are batched, not on wasm
+}
+
+impl Default for RpcPoolConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(200),
+            batch_window: None,
+        }
+    }
+}
+
+/// Error of an RPC call, only retried when another attempt could succeed
+pub trait RpcCallError: Display {
+    fn is_retryable(&self) -> bool;
+}
+
+impl RpcCallError for TransportError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            // Unreachable, failing or garbled endpoints, another one may answer
+            RpcError::Transport(_) | RpcError::NullResp | RpcError::DeserError { .. } => true,
+            // Rate limits, reverts and execution errors would fail the same way again
+            RpcError::ErrorResp(payload) => payload.is_retry_err(),
+            _ => false,
+        }
+    }
+}
+
+impl RpcCallError for contract::Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            contract::Error::TransportError(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcPool {
+    endpoints: Arc<Vec<(String, HttpProvider)>>,
+    preferred: Arc<AtomicUsize>, // last endpoint that answered, tried first
+    chain_id: Arc<OnceLock<u64>>, // known once fetched, labels the RPC metrics
+    config: RpcPoolConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    batch: Option<Arc<BatchQueues>>,
+}
+
+impl RpcPool {
+    /// Build a pool over `urls`, in order of preference. Malformed URLs are an error, not a panic
+    pub fn new(urls: &[String], config: RpcPoolConfig) -> Result<Self, AuthError> {
+        if urls.is_empty() {
+            return Err(AuthError::NetworkError("No RPC url provided".to_string()));
+        }
+
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let parsed = url
+                    .parse()
+                    .map_err(|_| AuthError::NetworkError(format!("Invalid RPC url: {}", url)))?;
+                Ok((url.clone(), ProviderBuilder::new().on_http(parsed)))
+            })
+            .collect::<Result<Vec<_>, AuthError>>()?;
+
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            preferred: Arc::new(AtomicUsize::new(0)),
+            chain_id: Arc::new(OnceLock::new()),
+            #[cfg(not(target_arch = "wasm32"))]
+            batch: config.batch_window.map(|_| Arc::default()),
+            config,
+        })
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        self.endpoints.iter().map(|(url, _)| url.clone()).collect()
+    }
+
+    /// Chain served by the pool, once it has been fetched with `get_chain_id`
+    pub fn chain_id(&self) -> Option<u64> {
+        self.chain_id.get().copied()
+    }
+
+    pub(crate) fn set_chain_id(&self, chain_id: u64) {
+        let _ = self.chain_id.set(chain_id);
+    }
+
+    /// Run `f` against the pool until one endpoint succeeds, returning the last error otherwise
+    /// Errors that aren't retryable, like reverts, are returned right away
+    pub async fn call<T, E, F, Fut>(&self, f: F) -> Result<T, String>
+    where
+        F: Fn(HttpProvider) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: RpcCallError,
+    {
+        let count = self.endpoints.len();
+        let mut backoff = self.config.initial_backoff;
+        let mut last_error = String::new();
+
+        for round in 0..=self.config.max_retries {
+            if round > 0 {
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let start = self.preferred.load(Ordering::Relaxed);
+            for offset in 0..count {
+                let index = (start + offset) % count;
+                let (url, provider) = &self.endpoints[index];
+
+                #[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+                let started = std::time::Instant::now();
+
+                let result = self.attempt(f(provider.clone())).await;
+
+                #[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+                crate::middleware::metrics::observe_rpc(
+                    self.chain_id(),
+                    started.elapsed(),
+                    matches!(result, Ok(Ok(_))),
+                );
+
+                match result {
+                    Ok(Ok(value)) => {
+                        self.preferred.store(index, Ordering::Relaxed);
+                        return Ok(value);
+                    }
+                    Ok(Err(e)) if !e.is_retryable() => return Err(e.to_string()),
+                    Ok(Err(e)) => {
+                        warn!(%url, error = %e, "RPC call failed");
+                        last_error = e.to_string();
+                    }
+                    Err(timeout) => {
+                        warn!(%url, error = %timeout, "RPC call failed");
+                        last_error = timeout;
+                    }
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Read a contract, batched with concurrent reads if enabled
+    pub async fn read<C: SolCall>(&self, target: Address, call: C) -> Result<C::Return, String> {
+        let data = self.eth_call(target, call.abi_encode().into()).await?;
+        C::abi_decode_returns(&data, true).map_err(|e| e.to_string())
+    }
+
+    /// `eth_call` with raw calldata, batched with concurrent calls if enabled
+    pub async fn eth_call(&self, target: Address, calldata: Bytes) -> Result<Bytes, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(queues), Some(window)) = (&self.batch, self.config.batch_window) {
+            return queue_call(self, queues, window, target, calldata).await;
+        }
+
+        self.direct_call(target, calldata).await
+    }
+
+    /// Transaction receipt lookup, batched with concurrent lookups if enabled
+    pub async fn get_transaction_receipt(
+        &self,
+        tx_hash: FixedBytes<32>,
+    ) -> Result<Option<TransactionReceipt>, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(queues), Some(window)) = (&self.batch, self.config.batch_window) {
+            return queue_receipt(self, queues, window, tx_hash).await;
+        }
+
+        self.call(|provider| async move { provider.get_transaction_receipt(tx_hash).await })
+            .await
+    }
+
+    pub(crate) async fn direct_call(
+        &self,
+        target: Address,
+        calldata: Bytes,
+    ) -> Result<Bytes, String> {
+        let tx = TransactionRequest::default()
+            .to(target)
+            .input(calldata.into());
+
+        self.call(|provider| {
+            let tx = tx.clone();
+            async move { provider.call(&tx).await }
+        })
+        .await
+    }
+
+    // The result of the call, or the timeout
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn attempt<T, E>(
+        &self,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<Result<T, E>, String> {
+        tokio::time::timeout(self.config.timeout, fut)
+            .await
+            .map_err(|_| format!("RPC request timed out after {:?}", self.config.timeout))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn attempt<T, E>(
+        &self,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<Result<T, E>, String> {
+        Ok(fut.await)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+// No timer on wasm, retries go out right away
+#[cfg(target_arch = "wasm32")]
+async fn sleep(_duration: Duration) {}
+
+// Pools shared across the verifiers, keyed by each of their endpoint urls
+fn pools() -> &'static Mutex<HashMap<String, RpcPool>> {
+    static POOLS: OnceLock<Mutex<HashMap<String, RpcPool>>> = OnceLock::new();
+    POOLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Register the RPC endpoints of a chain, the first one being the primary
+/// Any config using one of these urls then goes through the same pool
+pub fn register_rpc_pool(urls: Vec<String>, config: RpcPoolConfig) -> Result<RpcPool, AuthError> {
+    let pool = RpcPool::new(&urls, config)?;
+    let mut pools = pools().lock().unwrap();
+    for url in urls {
+        pools.insert(url, pool.clone());
+    }
+    Ok(pool)
+}
+
+/// Pool serving `rpc_url`, a single endpoint pool with the default config is created if none was registered
+pub fn get_rpc_pool(rpc_url: &str) -> Result<RpcPool, AuthError> {
+    let mut pools = pools().lock().unwrap();
+    if let Some(pool) = pools.get(rpc_url) {
+        return Ok(pool.clone());
+    }
+
+    let pool = RpcPool::new(&[rpc_url.to_string()], RpcPoolConfig::default())?;
+    pools.insert(rpc_url.to_string(), pool.clone());
+    Ok(pool)
+}
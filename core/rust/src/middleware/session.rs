@@ -0,0 +1,153 @@
+// Session tokens issued after a payment is verified
+// Short-lived HS256 JWTs signed with a server key, accepted instead of the payment for the same scheme and resource
+
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{error::AuthError, middleware::utils::get_current_time};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SESSION_HEADER: &str = "X-Payment-Session";
+pub const SESSION_TOKEN_TTL_SEC: u64 = 300; // 5 minutes, default validity of a session token
+
+#[derive(Clone)]
+pub struct SessionConfig {
+    pub secret: Vec<u8>, // server key the tokens are signed with
+    pub ttl_sec: u64,
+}
+
+// Never print the server key
+impl fmt::Debug for SessionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionConfig")
+            .field("secret", &"<redacted>")
+            .field("ttl_sec", &self.ttl_sec)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SessionClaims {
+    pub sub: String,    // payer address
+    pub scheme: String, // scheme the payment was verified with
+    pub scope: String,  // resource the token grants access to
+    pub iat: u64,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment: Option<String>, // one-time payment tx hash, each use of the token is one of its redemptions
+}
+
+impl SessionConfig {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl_sec: SESSION_TOKEN_TTL_SEC,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl_sec: u64) -> Self {
+        self.ttl_sec = ttl_sec;
+        self
+    }
+
+    /// Issue a token for the payer, valid for the configured TTL or until `expires_at` if earlier
+    pub fn issue(
+        &self,
+        payer: &str,
+        scheme: &str,
+        scope: &str,
+        expires_at: Option<u64>,
+    ) -> Result<String, AuthError> {
+        self.issue_claims(payer, scheme, scope, expires_at, None)
+    }
+
+    /// Same as `issue`, each request with the token is counted as a redemption of the one-time `payment`
+    pub fn issue_for_payment(
+        &self,
+        payer: &str,
+        scheme: &str,
+        scope: &str,
+        expires_at: Option<u64>,
+        payment: &str,
+    ) -> Result<String, AuthError> {
+        self.issue_claims(payer, scheme, scope, expires_at, Some(payment.to_string()))
+    }
+
+    fn issue_claims(
+        &self,
+        payer: &str,
+        scheme: &str,
+        scope: &str,
+        expires_at: Option<u64>,
+        payment: Option<String>,
+    ) -> Result<String, AuthError> {
+        let now = get_current_time();
+        let exp = match expires_at {
+            Some(expires_at) => expires_at.min(now + self.ttl_sec),
+            None => now + self.ttl_sec,
+        };
+
+        let claims = SessionClaims {
+            sub: payer.to_string(),
+            scheme: scheme.to_string(),
+            scope: scope.to_string(),
+            iat: now,
+            exp,
+            payment,
+        };
+
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims).map_err(|_| AuthError::InternalError)?);
+        let signing_input = format!("{}.{}", header, payload);
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(signing_input.as_bytes())?);
+
+        Ok(format!("{}.{}", signing_input, signature))
+    }
+
+    /// Verify the token signature and expiry, the caller checks the scheme and scope
+    pub fn verify(&self, token: &str) -> Result<SessionClaims, AuthError> {
+        let invalid = || AuthError::InvalidHeaders("Invalid session token".to_string());
+
+        let (signing_input, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let (header, payload) = signing_input.split_once('.').ok_or_else(invalid)?;
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).map_err(|_| invalid())?)
+                .map_err(|_| invalid())?;
+        if header["alg"] != "HS256" {
+            return Err(invalid());
+        }
+
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).map_err(|_| AuthError::InternalError)?;
+        mac.update(signing_input.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        let claims: SessionClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?)
+                .map_err(|_| invalid())?;
+
+        if claims.exp <= get_current_time() {
+            return Err(AuthError::InvalidHeaders(
+                "Session token expired".to_string(),
+            ));
+        }
+
+        Ok(claims)
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, AuthError> {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).map_err(|_| AuthError::InternalError)?;
+        mac.update(message);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
@@ -1,6 +1,8 @@
 use crate::middleware::{
-    one_time_payment::state::OneTimePaymentState,
-    payment_channel::channel::ChannelState,
+    coalesce::VerificationCache,
+    events::{PaymentEventSink, PaymentEvents},
+    one_time_payment::{claims::TxClaims, credits::CreditState, state::OneTimePaymentState},
+    payment_channel::{channel::ChannelState, types::ChannelListenerConfig, ChannelListener},
     stream_payment::{
         state::StreamState,
         types::{StreamListenerConfig, StreamsConfig},
@@ -10,12 +12,23 @@ use crate::middleware::{
 };
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tracing::warn;
 
 #[derive(Clone)]
 pub struct MiddlewareState {
     pub stream_state: Arc<RwLock<Option<StreamState>>>,
     pub channel_state: Arc<RwLock<Option<ChannelState>>>,
     pub one_time_payment_state: Arc<RwLock<Option<OneTimePaymentState>>>,
+    pub credit_state: Arc<RwLock<Option<CreditState>>>,
+    pub verification_cache: VerificationCache, // in-flight verifications and recent failures
+    pub tx_claims: TxClaims, // transfers used by one-time, credits and subscription payments
+    pub events: PaymentEvents, // sinks notified of payments and channel/stream changes
+}
+
+impl Default for MiddlewareState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MiddlewareState {
@@ -24,12 +37,28 @@ impl MiddlewareState {
             stream_state: Arc::new(RwLock::new(None)),
             channel_state: Arc::new(RwLock::new(None)),
             one_time_payment_state: Arc::new(RwLock::new(None)),
+            credit_state: Arc::new(RwLock::new(None)),
+            verification_cache: VerificationCache::default(),
+            tx_claims: TxClaims::new(),
+            events: PaymentEvents::default(),
         }
     }
 
+    /// Notify `sink` of payment events, can be called several times to add more sinks
+    pub fn with_event_sink(self, sink: impl PaymentEventSink + 'static) -> Self {
+        self.events.add(sink);
+        self
+    }
+
+    /// Remember definitive verification failures for `ttl_sec` instead of the default, 0 disables it
+    pub fn with_negative_cache_ttl(mut self, ttl_sec: u64) -> Self {
+        self.verification_cache = VerificationCache::new(ttl_sec);
+        self
+    }
+
     pub async fn with_stream_state(self) -> Self {
         let mut stream_state = self.stream_state.write().await;
-        *stream_state = Some(StreamState::new());
+        *stream_state = Some(StreamState::new().with_events(self.events.clone()));
         drop(stream_state);
         self
     }
@@ -39,7 +68,7 @@ impl MiddlewareState {
         let cfa = match get_cfa_from_chain_id(&chain_id).await {
             Ok(addr) => addr,
             Err(e) => {
-                println!("Error fetching CFA address: {:?}", e);
+                warn!(error = %e, "Error fetching CFA address");
                 return;
             }
         };
@@ -48,7 +77,7 @@ impl MiddlewareState {
         let wss_url = match get_chain_wss_url(&chain_id).await {
             Ok(url) => url,
             Err(e) => {
-                println!("Error fetching WSS URL: {:?}", e);
+                warn!(error = %e, "Error fetching WSS URL");
                 return;
             }
         };
@@ -68,15 +97,45 @@ impl MiddlewareState {
 
     pub async fn with_channel_state(self) -> Self {
         let mut channel_state = self.channel_state.write().await;
-        *channel_state = Some(ChannelState::new());
+        *channel_state = Some(ChannelState::new().with_events(self.events.clone()));
         drop(channel_state);
         self
     }
 
+    pub async fn start_channel_listener(&self, chain_id: u64, rpc_url: &str) {
+        // websocket is preferred, the listener falls back to polling the http rpc without it
+        let wss_url = match get_chain_wss_url(&chain_id).await {
+            Ok(url) => Some(url),
+            Err(e) => {
+                warn!(error = %e, "Error fetching WSS URL, polling instead");
+                None
+            }
+        };
+
+        let channel_listener_config = ChannelListenerConfig {
+            wss_url,
+            rpc_url: rpc_url.to_string(),
+            poll_interval_sec: 15,
+        };
+
+        let channel_state_guard = self.channel_state.read().await;
+        if let Some(channel_state) = channel_state_guard.as_ref() {
+            let _listener =
+                ChannelListener::new(channel_state.clone(), channel_listener_config).await;
+        }
+    }
+
     pub async fn with_one_time_payment_state(self) -> Self {
         let mut one_time_payment_state = self.one_time_payment_state.write().await;
         *one_time_payment_state = Some(OneTimePaymentState::new());
         drop(one_time_payment_state);
         self
     }
+
+    pub async fn with_credit_state(self) -> Self {
+        let mut credit_state = self.credit_state.write().await;
+        *credit_state = Some(CreditState::new());
+        drop(credit_state);
+        self
+    }
 }
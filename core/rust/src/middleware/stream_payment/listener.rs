@@ -8,6 +8,7 @@ use alloy::{
     rpc::types::Filter,
 };
 
+use tracing::{debug, error, info, trace, warn};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen_futures::spawn_local;
 
@@ -28,17 +29,17 @@ impl StreamListner {
     ) -> Self {
         #[cfg(not(target_arch = "wasm32"))]
         let handle = tokio::spawn(async move {
-            println!("Spawning event listener");
+            debug!("Spawning event listener");
             if let Err(e) = Self::start(state, config, listener_config).await {
-                eprintln!("Event listener error: {:?}", e);
+                error!(error = %e, "Event listener error");
             }
         });
 
         #[cfg(target_arch = "wasm32")]
         spawn_local(async move {
-            println!("Starting event listener");
+            info!("Starting event listener");
             if let Err(e) = Self::start(state, config, listener_config).await {
-                eprintln!("Event listener error: {:?}", e);
+                error!(error = %e, "Event listener error");
             }
         });
 
@@ -54,13 +55,13 @@ impl StreamListner {
         config: StreamsConfig,
         listener_config: StreamListenerConfig,
     ) -> Result<(), String> {
-        println!("Starting event listener");
+        info!("Starting event listener");
 
         let wss_url = listener_config.wss_url;
         let ws = WsConnect::new(wss_url);
 
         let provider = ProviderBuilder::new().on_ws(ws).await.map_err(|e| {
-            println!("Error receiving event: {:?}", e);
+            warn!(error = ?e, "Error receiving event");
             "Error receiving event".to_string()
         })?;
 
@@ -77,29 +78,28 @@ impl StreamListner {
             .event_signature(event_signature)
             .from_block(BlockNumberOrTag::Latest)
             .topic1(FixedBytes::<32>::left_padding_from(
-                &config.token_address.to_vec(),
+                config.token_address.as_ref(),
             ))
             .topic3(FixedBytes::<32>::left_padding_from(
-                &config.recipient.to_vec(),
+                config.recipient.as_ref(),
             ));
 
         let mut sub = provider.subscribe_logs(&filter).await.map_err(|e| {
-            println!("Error receiving event: {:?}", e);
+            warn!(error = ?e, "Error receiving event");
             "Error receiving event".to_string()
         })?;
 
-        print!("Subscribed to logs");
+        debug!("Subscribed to logs");
 
         while let Ok(log) = sub.recv().await {
-            println!("Received event: ");
+            trace!("Received event");
 
             if let Some(sender_topic) = log.topics().get(2) {
                 let sender = Address::from_slice(&sender_topic.0[12..]);
                 // check if the sender is in the stream state
 
                 if let Some(_stream) = state.get(sender).await {
-                    println!("Event sender in cache");
-                    println!("Sender: {:?}", sender);
+                    debug!(%sender, "Event sender in cache");
                     // check the stream flow rate and if it has changed other than the config amount, invalidate state
 
                     let data = &log.data().data[0..32];
@@ -108,25 +108,23 @@ impl StreamListner {
                     let decoded = match data_type.abi_decode(data) {
                         Ok(decoded) => decoded,
                         Err(e) => {
-                            println!("Error decoding data: {:?}", e);
+                            warn!(error = ?e, "Error decoding data");
                             continue; // Skipping this event instead of breaking
                         }
                     };
 
-                    // println!("Decoded data: {:?}", decoded);
-
                     let (flow_rate, _) = match decoded.as_int() {
                         Some(flow_rate) => flow_rate,
                         None => {
-                            println!("Error parsing flow rate");
+                            warn!("Error parsing flow rate");
                             continue;
                         }
                     };
 
-                    println!("Updated Flow rate for the sender: {:?}", flow_rate);
+                    debug!(%sender, %flow_rate, "Updated flow rate for the sender");
 
                     if flow_rate.as_i64() != config.amount.as_i64() {
-                        println!("Invalidating stream as stream modified or cancelled");
+                        info!(%sender, "Invalidating stream as stream modified or cancelled");
                         state.invalidate(sender).await;
                     }
                 }
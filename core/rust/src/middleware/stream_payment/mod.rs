@@ -17,6 +17,7 @@ use tower::{Layer, Service};
 
 pub use listener::StreamListner;
 use state::StreamState;
+use tracing::debug;
 pub use types::{Stream, StreamsConfig};
 use utils::parse_stream_headers;
 use verify::verify_stream;
@@ -94,9 +95,6 @@ where
 
         // #[cfg(not(target_arch = "wasm32"))]
         Box::pin(async move {
-            println!("\n=== superfluid_streams_auth_middleware ===");
-            println!("=== new request ===");
-
             let signed_stream = match parse_stream_headers(&request.headers().clone()).await {
                 Ok(tx) => tx,
                 Err(e) => return Ok(e.into_response()),
@@ -111,8 +109,7 @@ where
                         .as_secs();
 
                     if timestamp - stream.last_verified < config.cache_time {
-                        println!("Stream already verified, in Cache!");
-                        println!("=== end middleware check ===");
+                        debug!("Stream already verified, in Cache!");
 
                         return inner.call(request).await;
                     }
@@ -125,8 +122,7 @@ where
             };
 
             if verify {
-                println!("Verified");
-                println!("=== end middleware check ===");
+                debug!("Verified");
 
                 let timestamp = SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
@@ -168,9 +164,6 @@ pub async fn superfluid_streams_auth_fn_middleware(
     request: Request<Body>,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    println!("\n=== superfluid_streams_auth_middleware ===");
-    println!("=== new request ===");
-
     let signed_stream = match parse_stream_headers(&request.headers().clone()).await {
         Ok(tx) => tx,
         Err(e) => return Ok(e.into_response()),
@@ -182,8 +175,7 @@ pub async fn superfluid_streams_auth_fn_middleware(
     };
 
     if verify {
-        println!("Verified");
-        println!("=== end middleware check ===");
+        debug!("Verified");
         Ok(next.run(request).await)
     } else {
         Err(StatusCode::UNAUTHORIZED)
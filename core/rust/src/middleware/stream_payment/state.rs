@@ -2,21 +2,38 @@ use alloy::primitives::Address;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
-use crate::middleware::stream_payment::Stream;
+use crate::middleware::{
+    events::{PaymentEvent, PaymentEvents},
+    stream_payment::Stream,
+};
 
 #[derive(Clone)]
 pub struct StreamState {
     // Sender address to stream ( sender is the identifier )
     streams: Arc<RwLock<HashMap<Address, Stream>>>,
+    events: PaymentEvents, // notified when a stream is invalidated
+}
+
+impl Default for StreamState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StreamState {
     pub fn new() -> Self {
         Self {
             streams: Arc::new(RwLock::new(HashMap::new())),
+            events: PaymentEvents::default(),
         }
     }
 
+    /// Report invalidated streams to `events`
+    pub fn with_events(mut self, events: PaymentEvents) -> Self {
+        self.events = events;
+        self
+    }
+
     pub async fn get(&self, stream_id: Address) -> Option<Stream> {
         let streams = self.streams.read().await;
         streams.get(&stream_id).cloned()
@@ -29,6 +46,9 @@ impl StreamState {
 
     pub async fn invalidate(&self, stream_id: Address) {
         let mut streams = self.streams.write().await;
-        streams.remove(&stream_id);
+        if streams.remove(&stream_id).is_some() {
+            self.events
+                .emit(PaymentEvent::StreamInvalidated { sender: stream_id });
+        }
     }
 }
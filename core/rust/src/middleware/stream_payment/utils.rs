@@ -1,6 +1,6 @@
 use alloy::{
     dyn_abi::DynSolValue,
-    primitives::{keccak256, Address},
+    primitives::{aliases::I96, keccak256, Address},
 };
 use alloy::{
     hex::{self},
@@ -8,6 +8,7 @@ use alloy::{
 };
 use http::HeaderMap;
 use std::str::FromStr;
+use tracing::debug;
 
 use crate::{error::AuthError, middleware::stream_payment::types::SignedStream};
 
@@ -21,21 +22,37 @@ pub fn create_stream_message(sender: Address) -> Vec<u8> {
     hashed_message.to_vec()
 }
 
+/// Per second flow rate streaming `monthly_amount` tokens a month, in base units of a token with `decimals`
+pub fn monthly_flow_rate(monthly_amount: &str, decimals: u8) -> Result<I96, AuthError> {
+    let monthly_amount = monthly_amount
+        .parse::<f64>()
+        .map_err(|_| AuthError::InternalError)?;
+
+    let amount_with_decimals = monthly_amount * (10_f64.powi(decimals as i32));
+    let flow_rate_per_second = amount_with_decimals / ((365.0 / 12.0) * 24.0 * 60.0 * 60.0);
+
+    Ok(I96::try_from(flow_rate_per_second as i128).unwrap_or(I96::ZERO))
+}
+
 pub async fn parse_stream_headers(headers: &HeaderMap) -> Result<SignedStream, AuthError> {
     let signature = headers
         .get("X-Signature")
         .ok_or(AuthError::MissingHeaders)?
         .to_str()
-        .map_err(|_| AuthError::InvalidHeaders("X-Signature header contains invalid UTF-8 characters".to_string()))?;
+        .map_err(|_| {
+            AuthError::InvalidHeaders(
+                "X-Signature header contains invalid UTF-8 characters".to_string(),
+            )
+        })?;
 
     let signature = hex::decode(signature.trim_start_matches("0x"))
         .map_err(|_| {
-            println!("Failed: Signature decode");
+            debug!("Failed: Signature decode");
             AuthError::InvalidSignature
         })
         .and_then(|bytes| {
             PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                println!("Failed: Signature conversion");
+                debug!("Failed: Signature conversion");
                 AuthError::InvalidSignature
             })
         })?;
@@ -44,17 +61,18 @@ pub async fn parse_stream_headers(headers: &HeaderMap) -> Result<SignedStream, A
         .get("X-Sender")
         .ok_or(AuthError::MissingHeaders)?
         .to_str()
-        .map_err(|_| AuthError::InvalidHeaders("X-Sender header contains invalid UTF-8 characters".to_string()))?;
+        .map_err(|_| {
+            AuthError::InvalidHeaders(
+                "X-Sender header contains invalid UTF-8 characters".to_string(),
+            )
+        })?;
 
     let sender = Address::from_str(sender).map_err(|_| {
-        println!("Failed: Sender conversion");
+        debug!("Failed: Sender conversion");
         AuthError::InvalidSender
     })?;
 
-    let signed_tx = SignedStream {
-        signature,
-        sender: sender,
-    };
+    let signed_tx = SignedStream { signature, sender };
 
     Ok(signed_tx)
 }
@@ -1,17 +1,21 @@
 use alloy::{
     hex::{self},
     primitives::Signed,
-    providers::ProviderBuilder,
     sol,
 };
 use reqwest::Client;
 use serde_json::json;
+use tracing::{debug, warn};
 
 use crate::{
     error::AuthError,
-    middleware::stream_payment::{
-        types::{SignedStream, StreamsConfig},
-        utils::create_stream_message,
+    middleware::{
+        rpc::get_rpc_pool,
+        stream_payment::{
+            types::{SignedStream, StreamsConfig},
+            utils::create_stream_message,
+        },
+        utils::redact,
     },
 };
 
@@ -29,21 +33,23 @@ pub(crate) async fn verify_stream_via_indexer(
 ) -> Result<bool, AuthError> {
     // Creating the message
     let reconstructed_message = create_stream_message(stream.sender);
-    println!("Message: 0x{}", hex::encode(&reconstructed_message));
 
     let signature = stream.signature;
-    println!("Signature: 0x{}", hex::encode(&signature.as_bytes()));
+    debug!(
+        signature = %redact(&hex::encode(signature.as_bytes())),
+        "Verifying stream"
+    );
 
     // Recovering the address from the signature
     let recovered = match signature.recover_address_from_msg(reconstructed_message) {
         Ok(address) => address,
         Err(_) => return Err(AuthError::InvalidSignature),
     };
-    println!("Recovered address: {}", recovered);
+    debug!(%recovered, "Recovered stream signer");
 
     // Verify the recovered address against the sender for the stream
     if recovered != stream.sender {
-        println!("Failed: Recovered address mismatch");
+        debug!("Failed: Recovered address mismatch");
         return Err(AuthError::InvalidSignature);
     }
 
@@ -76,27 +82,27 @@ pub(crate) async fn verify_stream_via_indexer(
         .send()
         .await
         .map_err(|e| {
-            println!("Failed: Network error: {}", e);
+            warn!(error = %e, "Failed: Network error");
             AuthError::NetworkError("Failed to fetch stream data from indexer".to_string())
         })?
         .json::<serde_json::Value>()
         .await
         .map_err(|e| {
-            println!("Failed: JSON parse error: {}", e);
+            warn!(error = %e, "Failed: JSON parse error");
             AuthError::NetworkError("Failed to parse stream data from indexer".to_string())
         })?;
 
     if let Some(inflows) = response["data"]["account"]["inflows"].as_array() {
         if !inflows.is_empty() {
-            println!("✅ Stream is active! Inflow record found.");
+            debug!("Stream is active, inflow record found");
         } else {
-            println!("❌ No active inflow detected.");
+            debug!("Failed: No active inflow detected");
             return Err(AuthError::InvalidStream(
                 "No active inflow detected".to_string(),
             ));
         }
     } else {
-        println!("❌ No inflow data found.");
+        debug!("Failed: No inflow data found");
         return Err(AuthError::InvalidStream("No inflow data found".to_string()));
     }
 
@@ -106,45 +112,50 @@ pub(crate) async fn verify_stream_via_indexer(
 pub async fn verify_stream(stream: SignedStream, config: StreamsConfig) -> Result<bool, AuthError> {
     // Creating the message
     let reconstructed_message = create_stream_message(stream.sender);
-    println!("Message: 0x{}", hex::encode(&reconstructed_message));
 
     let signature = stream.signature;
-    println!("Signature: 0x{}", hex::encode(&signature.as_bytes()));
+    debug!(
+        signature = %redact(&hex::encode(signature.as_bytes())),
+        "Verifying stream"
+    );
 
     // Recovering the address from the signature
     let recovered = match signature.recover_address_from_msg(reconstructed_message) {
         Ok(address) => address,
         Err(_) => return Err(AuthError::InvalidSignature),
     };
-    println!("Recovered address: {}", recovered);
+    debug!(%recovered, "Recovered stream signer");
 
     // Verify the recovered address against the sender for the stream
     if recovered != stream.sender {
-        println!("Failed: Recovered address mismatch");
+        debug!("Failed: Recovered address mismatch");
         return Err(AuthError::InvalidSignature);
     }
 
-    let provider = ProviderBuilder::new().on_http(config.rpc_url.parse().unwrap());
-
-    let cfav1_forwarder = CFAv1Forwarder::new(config.cfa_forwarder, provider);
+    let pool = get_rpc_pool(&config.rpc_url)?;
 
     // Fetch the stream flow from sender to recipient, if it exists, using CFAv1Forwarder
-    let flow_info = cfav1_forwarder
-        .getFlowInfo(config.token_address, stream.sender, config.recipient)
-        .call()
+    let flow_info = pool
+        .read(
+            config.cfa_forwarder,
+            CFAv1Forwarder::getFlowInfoCall {
+                token: config.token_address,
+                sender: stream.sender,
+                receiver: config.recipient,
+            },
+        )
         .await
-        .map_err(|e| AuthError::ContractError(e.to_string()))?;
+        .map_err(AuthError::ContractError)?;
 
     // Check if the flow exists
     if flow_info.flowrate == Signed::ZERO {
-        println!("Failed: No stream flow found");
+        debug!("Failed: No stream flow found");
         return Err(AuthError::InvalidStream("No stream flow found".to_string()));
     } else {
-        println!("Stream flow found");
-        println!("Flow rate: {}", flow_info.flowrate);
+        debug!(flow_rate = %flow_info.flowrate, "Stream flow found");
         // check the flowRate matches with what recipient expects
         if flow_info.flowrate != config.amount {
-            println!("Failed: Invalid stream flow rate");
+            debug!("Failed: Invalid stream flow rate");
             return Err(AuthError::InvalidStream(
                 "Invalid stream flow rate".to_string(),
             ));
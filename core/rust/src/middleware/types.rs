@@ -1,9 +1,25 @@
-use alloy::primitives::Address;
+use std::sync::{Arc, Mutex};
+
+use alloy::primitives::{aliases::I96, Address, FixedBytes, U256};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{request::Parts, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::{Deserialize, Deserializer, Serialize};
+use tracing::warn;
 
-use crate::middleware::{
-    payment_channel::types::PaymentChannel,
-    utils::{get_chain_id, get_chain_name, get_super_token_from_token, get_token_decimals},
+use crate::{
+    error::AuthError,
+    middleware::{
+        exact_payment::utils::get_token_domain,
+        payment_channel::types::PaymentChannel,
+        paywall::PaywallConfig,
+        session::SessionConfig,
+        utils::{get_chain_id, get_chain_name, get_super_token_from_token, get_token_decimals},
+    },
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -13,7 +29,13 @@ pub enum Scheme {
     #[serde(rename = "channel")]
     PaymentChannels, // 'channel' payments
     #[serde(rename = "one-time")]
-    OneTimePayments, // 'exact' payments
+    OneTimePayments, // transfer made beforehand, redeemed a few times
+    #[serde(rename = "credits")]
+    Credits, // prepaid credits topped up with one-time payments
+    #[serde(rename = "subscription")]
+    Subscription, // time-based access bought with one-time payments
+    #[serde(rename = "exact")]
+    Exact, // x402 'exact' payments, an EIP-3009 transfer authorization per request
 }
 
 impl Scheme {
@@ -22,6 +44,9 @@ impl Scheme {
             "one-time" => Some(Self::OneTimePayments),
             "stream" => Some(Self::SuperfluidStreams),
             "channel" => Some(Self::PaymentChannels),
+            "credits" => Some(Self::Credits),
+            "subscription" => Some(Self::Subscription),
+            "exact" => Some(Self::Exact),
             _ => None,
         }
     }
@@ -31,6 +56,9 @@ impl Scheme {
             Self::OneTimePayments => "one-time",
             Self::SuperfluidStreams => "stream",
             Self::PaymentChannels => "channel",
+            Self::Credits => "credits",
+            Self::Subscription => "subscription",
+            Self::Exact => "exact",
         }
     }
 }
@@ -41,6 +69,7 @@ pub enum PaymentPayload {
     OneTime(OneTimePayload),
     Stream(StreamPayload),
     Channel(ChannelPayload),
+    Exact(ExactPayload),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -55,6 +84,25 @@ pub struct StreamPayload {
     pub sender: String,
 }
 
+/// Payload of the x402 `exact` scheme, the authorization as signed by the payer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExactPayload {
+    pub signature: String,
+    pub authorization: ExactAuthorization,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExactAuthorization {
+    pub from: String,
+    pub to: String,
+    pub value: String, // in token base units
+    #[serde(rename = "validAfter")]
+    pub valid_after: String, // unix seconds
+    #[serde(rename = "validBefore")]
+    pub valid_before: String, // unix seconds
+    pub nonce: String, // 32 bytes, hex encoded
+}
+
 // Custom deserializer to handle both string and object formats for paymentChannel
 fn deserialize_payment_channel<'de, D>(deserializer: D) -> Result<PaymentChannel, D::Error>
 where
@@ -87,14 +135,132 @@ pub struct ChannelPayload {
     pub timestamp: u64,
 }
 
+/// Price of a payment channel request in token base units, e.g. computed from the model and max tokens requested
+/// Without one, every request costs the configured scheme amount
+pub type ChannelPricer = Arc<dyn Fn(&Request<Body>) -> U256 + Send + Sync>;
+
+/// Usage only known once the handler ran (rows scanned, tokens generated), in token base units
+/// Inserted in the request extensions of every paid request, the middleware charges it after the response
+#[derive(Clone, Debug, Default)]
+pub struct UsageReport(Arc<Mutex<Option<U256>>>);
+
+impl UsageReport {
+    /// Add `amount` to the usage charged for the request
+    pub fn charge(&self, amount: U256) {
+        let mut usage = self.0.lock().unwrap();
+        *usage = Some(usage.unwrap_or_default() + amount);
+    }
+
+    pub fn amount(&self) -> Option<U256> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Payment a request was accepted with, inserted in the request extensions before the handler runs
+/// Extract it with `VerifiedPayment` in handlers behind the middleware, or `Option<VerifiedPayment>` elsewhere
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedPayment {
+    pub scheme: Scheme,
+    pub network: String, // chain name of the scheme config
+    pub chain_id: u64,
+    pub payer: Address,
+    pub amount: Option<U256>, // charged for this request, or transferred for one-time payments and subscriptions
+    pub tx_hash: Option<FixedBytes<32>>, // one-time, credits and subscription payments
+    pub channel_id: Option<U256>,
+    pub flow_rate: Option<I96>, // per second flow rate required of the stream
+    pub remaining_redemptions: Option<u32>, // one-time payments
+    pub remaining_balance: Option<U256>, // channels and credits, before any usage reported by the handler
+    pub expires_at: Option<u64>, // end of the access paid for, one-time payments and subscriptions
+    pub session: bool, // accepted on a session token, only the scheme, payer and expiry are known
+}
+
+impl VerifiedPayment {
+    pub(crate) fn new(config: &SchemeConfig, payer: Address) -> Self {
+        Self {
+            scheme: config.scheme.clone(),
+            network: config.chain_name.clone(),
+            chain_id: config.chain_id,
+            payer,
+            amount: None,
+            tx_hash: None,
+            channel_id: None,
+            flow_rate: None,
+            remaining_redemptions: None,
+            remaining_balance: None,
+            expires_at: None,
+            session: false,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for VerifiedPayment
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Self>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "No verified payment, is the route behind the payments middleware?",
+        ))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MiddlewareConfig {
     pub accepts: Vec<SchemeConfig>,
+    #[serde(default, rename = "requestBinding")]
+    pub request_binding: Option<RequestBinding>, // opt-in, binds channel vouchers to the request they pay for
+    #[serde(skip)]
+    pub session: Option<SessionConfig>, // opt-in, issues session tokens once a payment is verified
+    #[serde(skip)]
+    pub paywall: Option<PaywallConfig>, // opt-in, HTML 402 for clients preferring it
+    #[serde(default, rename = "publicUrl")]
+    pub public_url: Option<String>, // base URL of the resources in the 402, read from the request headers if unset
+}
+
+/// Channel vouchers are signed over a hash of the request method, path and body instead of an empty body
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestBinding {
+    #[serde(rename = "maxBodyBytes")]
+    pub max_body_bytes: usize, // bodies are buffered to be hashed, larger ones are rejected
 }
 
 impl MiddlewareConfig {
     pub fn new(accepts: Vec<SchemeConfig>) -> Self {
-        Self { accepts }
+        Self {
+            accepts,
+            request_binding: None,
+            session: None,
+            paywall: None,
+            public_url: None,
+        }
+    }
+
+    /// Issue session tokens for one-time, stream and subscription payments, accepted instead of the payment on the same resource
+    pub fn with_sessions(mut self, session: SessionConfig) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Answer browsers with an HTML paywall instead of the JSON 402, per the `Accept` header of the request
+    pub fn with_paywall(mut self, paywall: PaywallConfig) -> Self {
+        self.paywall = Some(paywall);
+        self
+    }
+
+    /// Base URL the server is reached at, e.g. `https://api.example.com`, the 402 resource is this URL and the path
+    /// Without it, the resource is built from the `Host` and `X-Forwarded-Proto` headers sent by the client
+    pub fn with_public_url(mut self, public_url: &str) -> Self {
+        self.public_url = Some(public_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn with_request_binding(mut self, max_body_bytes: usize) -> Self {
+        self.request_binding = Some(RequestBinding { max_body_bytes });
+        self
     }
 
     pub fn add_scheme(&mut self, req: SchemeConfig) {
@@ -146,7 +312,12 @@ impl SchemeConfig {
                 decimals: Some(decimals),
             };
         } else if scheme == Scheme::PaymentChannels {
-            println!("Payment Channels currently aren't safe for production, Use with caution");
+            warn!("Payment Channels currently aren't safe for production, Use with caution");
+        } else if scheme == Scheme::Exact {
+            // Fetched once so 402s advertise the domain clients sign in
+            if let Err(e) = get_token_domain(&network_rpc_url, chain_id, &token_address).await {
+                warn!(error = %e, token = %token_address, "Unknown EIP-712 domain, exact payments will fail");
+            }
         }
 
         let decimals = get_token_decimals(&network_rpc_url, &token_address)
@@ -170,13 +341,18 @@ impl SchemeConfig {
 pub struct PaymentRequiredAccept {
     pub scheme: String,
     pub network: String,
-    pub amount: String,
+    #[serde(default)]
+    pub amount: String, // in token units, absent from stock x402 servers
+    #[serde(rename = "maxAmountRequired", skip_serializing_if = "Option::is_none")]
+    pub max_amount_required: Option<String>, // x402 name, in token base units
     #[serde(rename = "payTo")]
     pub pay_to: String,
     pub asset: String,
     pub resource: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default, rename = "mimeType")]
+    pub mime_type: String, // of the resource, empty when unknown
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "maxTimeoutSeconds")]
     pub max_timeout_seconds: Option<u64>,
@@ -207,17 +383,43 @@ impl PaymentHeader {
         serde_json::from_str(json_str)
     }
 
+    /// Parse an `X-Payment` header, raw JSON as pipegate clients send it or base64 encoded JSON as x402 SDKs do
+    pub fn decode(header: &str) -> Result<Self, AuthError> {
+        let header = header.trim();
+        let json = if header.starts_with('{') {
+            header.as_bytes().to_vec()
+        } else {
+            STANDARD.decode(header).map_err(|_| {
+                AuthError::InvalidHeaders(
+                    "X-Payment is neither JSON nor base64 encoded JSON".to_string(),
+                )
+            })?
+        };
+
+        serde_json::from_slice(&json).map_err(|e| {
+            AuthError::InvalidHeaders(format!("Failed to parse X-Payment JSON: {}", e))
+        })
+    }
+
+    /// Base64 encoded JSON, the x402 encoding of the header
+    pub fn encode(&self) -> Result<String, serde_json::Error> {
+        Ok(STANDARD.encode(serde_json::to_vec(self)?))
+    }
+
     pub fn get_scheme_enum(&self) -> Option<Scheme> {
         Scheme::from_string(&self.scheme)
     }
 
     pub fn validate_payload_for_scheme(&self) -> bool {
-        match (self.scheme.as_str(), &self.payload) {
-            ("one-time", PaymentPayload::OneTime(_)) => true,
-            ("stream", PaymentPayload::Stream(_)) => true,
-            ("channel", PaymentPayload::Channel(_)) => true,
-            _ => false,
-        }
+        matches!(
+            (self.scheme.as_str(), &self.payload),
+            ("one-time", PaymentPayload::OneTime(_))
+                | ("stream", PaymentPayload::Stream(_))
+                | ("channel", PaymentPayload::Channel(_))
+                | ("credits", PaymentPayload::OneTime(_))
+                | ("subscription", PaymentPayload::OneTime(_))
+                | ("exact", PaymentPayload::Exact(_))
+        )
     }
 
     pub fn to_json_string(&self) -> Result<String, serde_json::Error> {
@@ -226,3 +428,4 @@ impl PaymentHeader {
 }
 
 pub const CHAINLIST_API: &str = "https://chainlist.org/rpcs.json";
+pub const PAYMENT_RESPONSE_HEADER: &str = "X-Payment-Response"; // x402 settlement of the payment, base64 encoded JSON
@@ -1,22 +1,29 @@
 use alloy::{
     hex,
     primitives::{Address, FixedBytes, PrimitiveSignature},
-    providers::{Provider, ProviderBuilder},
+    providers::Provider,
     sol,
 };
 use std::str::FromStr;
+use tracing::{debug, warn};
 
 use crate::{
     error::AuthError,
     middleware::{
         one_time_payment::types::SignedPaymentTx,
         payment_channel::types::PaymentChannel,
+        rpc::get_rpc_pool,
         stream_payment::types::{SignedStream, SUPERFLUID_NETWORKS_LIST, SUPERFLUID_TOKEN_LIST},
     },
 };
 
 #[cfg(not(target_arch = "wasm32"))]
-use crate::middleware::types::{ChannelPayload, OneTimePayload, StreamPayload, CHAINLIST_API};
+use crate::middleware::{
+    exact_payment::types::{SignedAuthorization, TransferWithAuthorization},
+    types::{ChannelPayload, ExactPayload, OneTimePayload, StreamPayload, CHAINLIST_API},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use alloy::primitives::U256;
 
 sol! {
    // The `rpc` attribute enables contract interaction via the provider.
@@ -26,12 +33,11 @@ sol! {
    }
 }
 
-pub async fn get_token_decimals(rpc_url: &String, token_address: &Address) -> Result<u8, String> {
-    let provider = ProviderBuilder::new().on_http(rpc_url.parse().unwrap());
+pub async fn get_token_decimals(rpc_url: &str, token_address: &Address) -> Result<u8, String> {
+    let pool = get_rpc_pool(rpc_url).map_err(|e| e.to_string())?;
+    let token_address = *token_address;
 
-    let erc20 = ERC20::new(*token_address, provider);
-
-    let balance = erc20.decimals().call().await;
+    let balance = pool.read(token_address, ERC20::decimalsCall {}).await;
 
     match balance {
         Ok(decimals) => Ok(decimals._0),
@@ -39,29 +45,37 @@ pub async fn get_token_decimals(rpc_url: &String, token_address: &Address) -> Re
     }
 }
 
-pub async fn convert_signature(signature: &String) -> Result<PrimitiveSignature, AuthError> {
+/// Shorten a signature or other payment secret for logs, e.g. `0x1234…cdef`
+pub(crate) fn redact(value: &str) -> String {
+    match (value.get(..6), value.get(value.len().saturating_sub(4)..)) {
+        (Some(start), Some(end)) if value.len() > 12 => format!("{}…{}", start, end),
+        _ => "<redacted>".to_string(),
+    }
+}
+
+pub async fn convert_signature(signature: &str) -> Result<PrimitiveSignature, AuthError> {
     let signature = hex::decode(signature.trim_start_matches("0x"))
         .map_err(|_| {
-            println!("Failed: Signature decode");
+            debug!("Failed: Signature decode");
             AuthError::InvalidSignature
         })
         .and_then(|bytes| {
             PrimitiveSignature::try_from(bytes.as_slice()).map_err(|_| {
-                println!("Failed: Signature conversion");
+                debug!("Failed: Signature conversion");
                 AuthError::InvalidSignature
             })
         });
 
-    return signature;
+    signature
 }
 
 pub async fn convert_tx_hash(tx_hash: &String) -> Result<FixedBytes<32>, AuthError> {
     let tx_hash = hex::decode(tx_hash).map_err(|_| {
-        println!("Failed: Message decode");
+        debug!("Failed: Message decode");
         AuthError::InvalidTransaction("Tx hash decode failed".to_string())
     });
 
-    return tx_hash.map(|h| FixedBytes::<32>::from_slice(&h));
+    tx_hash.map(|h| FixedBytes::<32>::from_slice(&h))
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -84,8 +98,11 @@ pub async fn parse_stream_payload(payload: &StreamPayload) -> Result<SignedStrea
 pub async fn parse_channel_payload(
     payload: &ChannelPayload,
 ) -> Result<(PrimitiveSignature, Vec<u8>, PaymentChannel), AuthError> {
-    println!("Signature : {}", payload.signature);
-    println!("Message : {}", payload.message);
+    debug!(
+        signature = %redact(&payload.signature),
+        message_len = payload.message.len(),
+        "Parsing channel payload"
+    );
     let signature = convert_signature(&payload.signature).await?;
     let message = hex::decode(&payload.message).map_err(|_| AuthError::InvalidMessage)?;
 
@@ -93,14 +110,43 @@ pub async fn parse_channel_payload(
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub async fn get_chain_id(rpc_url: &String) -> Result<u64, AuthError> {
-    let provider = ProviderBuilder::new().on_http(rpc_url.parse().unwrap());
+pub async fn parse_exact_payload(payload: &ExactPayload) -> Result<SignedAuthorization, AuthError> {
+    let signature = convert_signature(&payload.signature).await?;
+    let authorization = &payload.authorization;
+    let invalid =
+        |field: &str| AuthError::InvalidHeaders(format!("Invalid authorization {}", field));
+
+    Ok(SignedAuthorization {
+        authorization: TransferWithAuthorization {
+            from: Address::from_str(&authorization.from).map_err(|_| invalid("from"))?,
+            to: Address::from_str(&authorization.to).map_err(|_| invalid("to"))?,
+            value: U256::from_str(&authorization.value).map_err(|_| invalid("value"))?,
+            validAfter: U256::from_str(&authorization.valid_after)
+                .map_err(|_| invalid("validAfter"))?,
+            validBefore: U256::from_str(&authorization.valid_before)
+                .map_err(|_| invalid("validBefore"))?,
+            nonce: FixedBytes::from_str(&authorization.nonce).map_err(|_| invalid("nonce"))?,
+        },
+        signature,
+    })
+}
 
-    let chain_id = provider.get_chain_id().await.map_err(|e| {
-        println!("Error fetching chain ID: {}", e);
-        AuthError::ContractError("Failed to fetch chain ID".to_string())
-    })?;
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn get_chain_id(rpc_url: &str) -> Result<u64, AuthError> {
+    let pool = get_rpc_pool(rpc_url)?;
+    if let Some(chain_id) = pool.chain_id() {
+        return Ok(chain_id);
+    }
+
+    let chain_id = pool
+        .call(|provider| async move { provider.get_chain_id().await })
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "Error fetching chain ID");
+            AuthError::ContractError("Failed to fetch chain ID".to_string())
+        })?;
 
+    pool.set_chain_id(chain_id);
     Ok(chain_id)
 }
 
@@ -197,6 +243,8 @@ pub async fn get_chain_name(chain_id: &u64) -> Result<String, AuthError> {
         56 => return Ok("bsc".to_string()),
         8453 => return Ok("base".to_string()),
         43114 => return Ok("avalanche".to_string()),
+        1329 => return Ok("sei".to_string()),
+        4689 => return Ok("iotex".to_string()),
         42220 => return Ok("celo".to_string()),
         324 => return Ok("zksync-era".to_string()),
 
@@ -207,6 +255,8 @@ pub async fn get_chain_name(chain_id: &u64) -> Result<String, AuthError> {
         11155420 => return Ok("optimism-sepolia".to_string()),
         43113 => return Ok("avalanche-fuji".to_string()),
         44787 => return Ok("celo-alfajores".to_string()),
+        80002 => return Ok("polygon-amoy".to_string()),
+        1328 => return Ok("sei-testnet".to_string()),
 
         _ => {}
     }
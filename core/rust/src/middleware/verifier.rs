@@ -0,0 +1,138 @@
+// Verification of payments on behalf of the middleware, in process or by a remote x402 facilitator
+// Delegating keeps the resource server free of RPC endpoints and payment state, native only like the rest of the middleware
+
+use std::time::Duration;
+
+use axum::async_trait;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+use crate::{
+    error::AuthError,
+    facilitator::{
+        types::{FacilitatorRequest, SettleResponse, SupportedResponse, VerifyResponse},
+        Facilitator,
+    },
+};
+
+pub const FACILITATOR_TIMEOUT_SEC: u64 = 10; // default time a facilitator call may take, the paid request waits on it
+
+/// Verifies and settles payments as a facilitator does, `Err` only when the verifier can't be reached
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    /// Check the payment without recording it
+    async fn verify(&self, request: &FacilitatorRequest) -> Result<VerifyResponse, AuthError>;
+
+    /// Verify and record the payment, the middleware settles every payment it accepts
+    async fn settle(&self, request: &FacilitatorRequest) -> Result<SettleResponse, AuthError>;
+}
+
+/// Local implementation, the facilitator runs in the same process
+#[async_trait]
+impl Verifier for Facilitator {
+    async fn verify(&self, request: &FacilitatorRequest) -> Result<VerifyResponse, AuthError> {
+        Ok(Facilitator::verify(self, request).await)
+    }
+
+    async fn settle(&self, request: &FacilitatorRequest) -> Result<SettleResponse, AuthError> {
+        Ok(Facilitator::settle(self, request).await)
+    }
+}
+
+/// Remote implementation, calling `/verify` and `/settle` of a facilitator over HTTP
+#[derive(Clone, Debug)]
+pub struct RemoteVerifier {
+    url: Url, // base URL, the routes are appended to its path
+    client: Client,
+}
+
+impl RemoteVerifier {
+    pub fn new(url: &str) -> Result<Self, AuthError> {
+        let url = Url::parse(url.trim_end_matches('/'))
+            .map_err(|e| AuthError::InvalidRequest(format!("Invalid facilitator URL: {}", e)))?;
+        Ok(Self {
+            url,
+            client: Self::client(Duration::from_secs(FACILITATOR_TIMEOUT_SEC)),
+        })
+    }
+
+    /// Fail calls taking longer than `timeout` instead of `FACILITATOR_TIMEOUT_SEC`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Self::client(timeout);
+        self
+    }
+
+    /// Send requests with `client`, e.g. to set authentication headers, its own timeout applies
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Schemes and networks served by the facilitator
+    pub async fn supported(&self) -> Result<SupportedResponse, AuthError> {
+        let response = self
+            .client
+            .get(self.route("supported"))
+            .send()
+            .await
+            .map_err(|e| AuthError::NetworkError(format!("Facilitator unreachable: {}", e)))?;
+        Self::parse(response).await
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        route: &str,
+        body: &impl Serialize,
+    ) -> Result<T, AuthError> {
+        debug!(facilitator = %self.url, route, "Calling facilitator");
+        let response = self
+            .client
+            .post(self.route(route))
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| AuthError::NetworkError(format!("Facilitator unreachable: {}", e)))?;
+        Self::parse(response).await
+    }
+
+    fn client(timeout: Duration) -> Client {
+        Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default()
+    }
+
+    fn route(&self, route: &str) -> String {
+        format!("{}/{}", self.url.as_str().trim_end_matches('/'), route)
+    }
+
+    async fn parse<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, AuthError> {
+        let status = response.status();
+        if !status.is_success() {
+            return Err(AuthError::NetworkError(format!(
+                "Facilitator answered {}",
+                status
+            )));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| AuthError::NetworkError(format!("Invalid facilitator response: {}", e)))
+    }
+}
+
+#[async_trait]
+impl Verifier for RemoteVerifier {
+    async fn verify(&self, request: &FacilitatorRequest) -> Result<VerifyResponse, AuthError> {
+        self.post("verify", request).await
+    }
+
+    async fn settle(&self, request: &FacilitatorRequest) -> Result<SettleResponse, AuthError> {
+        self.post("settle", request).await
+    }
+}
@@ -0,0 +1,296 @@
+// Paywall gateway, runs the payments middleware in front of an upstream server written in any language
+// Paid requests are forwarded with the payer in `X-Pipegate-*` headers, bodies are streamed both ways
+
+pub mod types;
+
+use std::{fs, str::FromStr};
+
+use alloy::signers::local::PrivateKeySigner;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        header::{self, HeaderName},
+        uri::{PathAndQuery, Uri},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use hyper_tls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    error::AuthError,
+    middleware::{
+        session::{SessionConfig, SESSION_HEADER},
+        MiddlewareConfig, MiddlewareState, PaymentsLayer, Scheme, SchemeConfig, VerifiedPayment,
+    },
+};
+
+pub use types::{ProxyConfig, RouteConfig, RoutePrice};
+
+pub const PAYER_HEADER: &str = "X-Pipegate-Payer";
+pub const SCHEME_HEADER: &str = "X-Pipegate-Scheme";
+pub const NETWORK_HEADER: &str = "X-Pipegate-Network";
+pub const AMOUNT_HEADER: &str = "X-Pipegate-Amount"; // in the smallest unit of the token
+pub const TX_HASH_HEADER: &str = "X-Pipegate-Tx-Hash";
+pub const CHANNEL_ID_HEADER: &str = "X-Pipegate-Channel-Id";
+
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+type UpstreamClient = Client<HttpsConnector<HttpConnector>, Body>;
+
+#[derive(Clone)]
+struct Upstream {
+    uri: Uri,
+    client: UpstreamClient,
+}
+
+/// Reverse proxy charging for routes of an upstream, unmatched routes are answered with 404
+#[derive(Clone)]
+pub struct Proxy {
+    upstream: Upstream,
+    state: MiddlewareState,
+    routes: Vec<(String, Option<MiddlewareConfig>)>, // path and payments config, none for free routes
+    settlement_signer: Option<PrivateKeySigner>,     // executes the transfers of `exact` payments
+}
+
+impl Proxy {
+    pub fn new(upstream: &str) -> Result<Self, AuthError> {
+        let uri = Uri::from_str(upstream)
+            .ok()
+            .filter(|uri| uri.scheme().is_some() && uri.authority().is_some())
+            .ok_or_else(|| AuthError::InvalidRequest(format!("Invalid upstream {}", upstream)))?;
+        let client = Client::builder(TokioExecutor::new()).build(HttpsConnector::new());
+
+        Ok(Self {
+            upstream: Upstream { uri, client },
+            state: MiddlewareState::new(),
+            routes: Vec::new(),
+            settlement_signer: None,
+        })
+    }
+
+    /// Build the proxy of a config file, fetching the chain of each price
+    /// Routes accepting `exact` payments need `settlementKeyFile`, they could never be settled otherwise
+    pub async fn from_config(config: &ProxyConfig) -> Result<Self, AuthError> {
+        let session = config.session_secret.as_deref().map(SessionConfig::new);
+
+        let mut proxy = Self::new(&config.upstream)?;
+        match &config.settlement_key_file {
+            Some(path) => {
+                let key = fs::read_to_string(path).map_err(|e| {
+                    AuthError::InvalidRequest(format!(
+                        "Failed to read settlement key {}: {}",
+                        path, e
+                    ))
+                })?;
+                let signer = key.trim().parse::<PrivateKeySigner>().map_err(|_| {
+                    AuthError::InvalidRequest(format!("Invalid settlement key in {}", path))
+                })?;
+                proxy = proxy.with_settlement_signer(signer);
+            }
+            None => {
+                if let Some(route) = config.routes.iter().find(|route| {
+                    route
+                        .accepts
+                        .iter()
+                        .any(|price| price.scheme == Scheme::Exact)
+                }) {
+                    return Err(AuthError::InvalidRequest(format!(
+                        "Route {} accepts exact payments without a settlementKeyFile",
+                        route.path
+                    )));
+                }
+            }
+        }
+        for route in &config.routes {
+            if route.accepts.is_empty() {
+                proxy = proxy.free_route(&route.path);
+                continue;
+            }
+
+            let mut accepts = Vec::with_capacity(route.accepts.len());
+            for price in &route.accepts {
+                accepts.push(
+                    SchemeConfig::new(
+                        price.scheme.clone(),
+                        price.rpc_url.clone(),
+                        price.token,
+                        price.recipient,
+                        price.amount.clone(),
+                    )
+                    .await,
+                );
+            }
+
+            let mut middleware_config = MiddlewareConfig::new(accepts);
+            middleware_config.request_binding = route.request_binding.clone();
+            if let Some(session) = &session {
+                middleware_config = middleware_config.with_sessions(session.clone());
+            }
+            if let Some(public_url) = &config.public_url {
+                middleware_config = middleware_config.with_public_url(public_url);
+            }
+            proxy = proxy.paid_route(&route.path, middleware_config);
+        }
+
+        Ok(proxy)
+    }
+
+    /// Share middleware state with other services, e.g. to sink payment events
+    pub fn with_state(mut self, state: MiddlewareState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Account executing the transfers authorized by `exact` payments on every paid route
+    pub fn with_settlement_signer(mut self, signer: PrivateKeySigner) -> Self {
+        self.settlement_signer = Some(signer);
+        self
+    }
+
+    /// Forward requests to `path` once paid as configured
+    pub fn paid_route(mut self, path: &str, config: MiddlewareConfig) -> Self {
+        self.routes.push((path.to_string(), Some(config)));
+        self
+    }
+
+    /// Forward requests to `path` without payment
+    pub fn free_route(mut self, path: &str) -> Self {
+        self.routes.push((path.to_string(), None));
+        self
+    }
+
+    pub fn router(&self) -> Router {
+        let mut router = Router::new();
+        for (path, config) in &self.routes {
+            let route = match config {
+                Some(config) => {
+                    let mut layer = PaymentsLayer::new(self.state.clone(), config.clone());
+                    if let Some(signer) = &self.settlement_signer {
+                        layer = layer.with_settlement_signer(signer.clone());
+                    }
+                    any(forward).layer(layer)
+                }
+                None => any(forward),
+            };
+            router = router.route(path, route);
+        }
+        router.with_state(self.upstream.clone())
+    }
+}
+
+async fn forward(State(upstream): State<Upstream>, request: Request) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let uri = match upstream_uri(&upstream.uri, &parts.uri) {
+        Ok(uri) => uri,
+        Err(e) => return e.into_response(),
+    };
+
+    let host = parts.headers.get(header::HOST).cloned();
+    strip_hop_by_hop(&mut parts.headers);
+    parts.headers.remove(header::HOST);
+    parts.headers.remove("X-Payment");
+    parts.headers.remove(SESSION_HEADER);
+
+    // Only the proxy vouches for the payer
+    let spoofed: Vec<HeaderName> = parts
+        .headers
+        .keys()
+        .filter(|name| name.as_str().starts_with("x-pipegate-"))
+        .cloned()
+        .collect();
+    for name in spoofed {
+        parts.headers.remove(name);
+    }
+
+    if let Some(host) = host {
+        parts.headers.insert("X-Forwarded-Host", host);
+    }
+    if let Some(payment) = parts.extensions.get::<VerifiedPayment>() {
+        insert_payment_headers(&mut parts.headers, payment);
+    }
+
+    debug!(method = %parts.method, %uri, "Forwarding request");
+    parts.uri = uri;
+    let request = Request::from_parts(parts, body);
+
+    match upstream.client.request(request).await {
+        Ok(response) => {
+            let mut response = response.map(Body::new);
+            strip_hop_by_hop(response.headers_mut());
+            response
+        }
+        Err(e) => {
+            warn!(error = %e, "Upstream request failed");
+            (StatusCode::BAD_GATEWAY, "Upstream unavailable").into_response()
+        }
+    }
+}
+
+// Upstream base path, without its trailing slash, followed by the path and query of the request
+fn upstream_uri(base: &Uri, uri: &Uri) -> Result<Uri, AuthError> {
+    let prefix = base.path().trim_end_matches('/');
+    let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+
+    Uri::builder()
+        .scheme(base.scheme().cloned().expect("Checked in Proxy::new"))
+        .authority(base.authority().cloned().expect("Checked in Proxy::new"))
+        .path_and_query(format!("{}{}", prefix, path_and_query))
+        .build()
+        .map_err(|e| AuthError::InvalidRequest(format!("Invalid request path: {}", e)))
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+}
+
+fn insert_payment_headers(headers: &mut HeaderMap, payment: &VerifiedPayment) {
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    };
+
+    insert(PAYER_HEADER, payment.payer.to_string());
+    insert(SCHEME_HEADER, payment.scheme.to_string().to_string());
+    insert(NETWORK_HEADER, payment.network.clone());
+    if let Some(amount) = payment.amount {
+        insert(AMOUNT_HEADER, amount.to_string());
+    }
+    if let Some(tx_hash) = payment.tx_hash {
+        insert(TX_HASH_HEADER, tx_hash.to_string());
+    }
+    if let Some(channel_id) = payment.channel_id {
+        insert(CHANNEL_ID_HEADER, channel_id.to_string());
+    }
+}
@@ -0,0 +1,39 @@
+use alloy::primitives::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::middleware::{types::RequestBinding, Scheme};
+
+/// Configuration of the proxy binary, read from a JSON file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub listen: String,   // e.g. `0.0.0.0:8080`
+    pub upstream: String, // base URL requests are forwarded to, its path is prefixed to the request path
+    #[serde(default, rename = "sessionSecret")]
+    pub session_secret: Option<String>, // opt-in, issues session tokens on every paid route
+    #[serde(default, rename = "publicUrl")]
+    pub public_url: Option<String>, // URL the proxy is reached at, advertised in the 402 instead of the client's `Host`
+    #[serde(default, rename = "settlementKeyFile")]
+    pub settlement_key_file: Option<String>, // file holding the key settling `exact` payments, needed by routes accepting them
+    pub routes: Vec<RouteConfig>,
+}
+
+/// Route of the proxy, free if nothing is accepted
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteConfig {
+    pub path: String, // axum route syntax, e.g. `/api/*rest`
+    #[serde(default)]
+    pub accepts: Vec<RoutePrice>,
+    #[serde(default, rename = "requestBinding")]
+    pub request_binding: Option<RequestBinding>,
+}
+
+/// Price of a route in a scheme, chain id and decimals are fetched from `rpc_url` at startup
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoutePrice {
+    pub scheme: Scheme,
+    #[serde(rename = "rpcUrl")]
+    pub rpc_url: String,
+    pub token: Address,
+    pub recipient: Address,
+    pub amount: String, // in token units, monthly for streams
+}
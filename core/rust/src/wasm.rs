@@ -6,6 +6,7 @@ use alloy::{
 };
 use console_error_panic_hook;
 use js_sys::Date;
+use tracing::debug;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::future_to_promise;
 
@@ -97,12 +98,6 @@ impl PaymentChannelVerifier {
                     JsValue::from_str(&format!("Verification failed: {}", e.to_string()))
                 })?;
 
-            state
-                .channels
-                .write()
-                .await
-                .insert(result.0.channel_id, result.0.clone());
-
             // Rate limiting is not implemented in the wasm version
             Ok(JsValue::from_str(&serde_json::to_string(&result).unwrap()))
         })
@@ -278,8 +273,7 @@ impl StreamVerifier {
                     let timestamp = (Date::now() as u64) / 1000;
 
                     if timestamp - stream.last_verified < config.cache_time {
-                        println!("Stream already verified, in Cache!");
-                        println!("=== end middleware check ===");
+                        debug!("Stream already verified, in Cache!");
 
                         return Ok(JsValue::from_bool(true));
                     }
@@ -291,8 +285,7 @@ impl StreamVerifier {
                 .map_err(|e| JsValue::from_str(&format!("Verification failed: {}", e)))?;
 
             if result {
-                println!("Verified");
-                println!("=== end middleware check ===");
+                debug!("Verified");
 
                 let timestamp = (Date::now() as u64) / 1000;
 
@@ -32,6 +32,7 @@ import { ChannelFactoryAddress } from "./constants/address.js";
 
 export class ClientInterceptor {
   private channelStates: Map<string, PaymentChannelResponse> = new Map();
+  private channelPrices: Map<string, bigint> = new Map();
 
   private account!: Account;
 
@@ -161,6 +162,7 @@ export class ClientInterceptor {
       expiration: (channelState.timestamp + channelState.duration).toString(),
       channel_id: channelId,
     });
+    this.channelPrices.set(channelId, BigInt(channelState.price));
   }
 
   updateChannel(channelId: string, channelState: PaymentChannelResponse) {
@@ -181,6 +183,36 @@ export class ClientInterceptor {
     return channelState?.nonce || "0";
   }
 
+  /**
+   * gets the price per request registered for a payment channel, in token base units
+   * @param channelId
+   * @returns
+   */
+  getChannelPrice(channelId: string): bigint {
+    return this.channelPrices.get(channelId) ?? 0n;
+  }
+
+  /**
+   * creates the voucher paying for the next request
+   * the channel state holds the balance left before the request, the voucher signs the balance left after paying `price`
+   * @param paymentChannel PaymentChannelResponse
+   * @param price Price of the request in token base units
+   * @returns PaymentChannelResponse to sign and send
+   */
+  chargeChannel(
+    paymentChannel: PaymentChannelResponse,
+    price: bigint
+  ): PaymentChannelResponse {
+    const balance = BigInt(paymentChannel.balance);
+    if (balance < price) {
+      throw new Error(
+        `Insufficient channel balance: ${balance} left, ${price} required`
+      );
+    }
+
+    return { ...paymentChannel, balance: (balance - price).toString() };
+  }
+
   /**
    * signs a request with channel details
    * @param paymentChannel PaymentChannelResponse
@@ -313,8 +345,9 @@ export class ClientInterceptor {
   /**
    * creates an interceptor for HTTP clients (axios, fetch)
    * @param channelId
+   * @param price Price per request in token base units, the price registered for the channel by default
    */
-  createPaymentChannelRequestInterceptor(channelId: string) {
+  createPaymentChannelRequestInterceptor(channelId: string, price?: bigint) {
     return {
       request: async (config: InternalAxiosRequestConfig) => {
         try {
@@ -323,8 +356,12 @@ export class ClientInterceptor {
             throw new Error(`No payment channel found for ID: ${channelId}`);
           }
 
-          const signedRequest = await this.signPaymentChannelRequest(
+          const voucher = this.chargeChannel(
             channelState,
+            price ?? this.getChannelPrice(channelId)
+          );
+          const signedRequest = await this.signPaymentChannelRequest(
+            voucher,
             config.data
           );
 
@@ -334,7 +371,7 @@ export class ClientInterceptor {
             "x-Message": signedRequest.message,
             "x-Signature": signedRequest.signature,
             "x-Timestamp": signedRequest.timestamp,
-            "x-Payment": JSON.stringify(channelState),
+            "x-Payment": JSON.stringify(voucher),
           });
 
           return config;
@@ -605,8 +642,15 @@ export function withPaymentInterceptor(
             );
           }
 
+          // The voucher signs the balance left once this request is paid
+          const price =
+            paymentRequirement.maxAmountRequired !== undefined
+              ? BigInt(paymentRequirement.maxAmountRequired)
+              : client.getChannelPrice(config.channel.channelId.toString());
+          const voucher = client.chargeChannel(channelState, price);
+
           const signedRequest = await client.signPaymentChannelRequest(
-            channelState,
+            voucher,
             undefined
           );
 
@@ -614,13 +658,13 @@ export function withPaymentInterceptor(
             signature: signedRequest.signature,
             message: signedRequest.message,
             paymentChannel: {
-              address: channelState.address,
-              sender: channelState.sender,
-              recipient: channelState.recipient,
-              balance: channelState.balance.toString(),
-              nonce: channelState.nonce.toString(),
-              expiration: channelState.expiration.toString(),
-              channel_id: channelState.channel_id.toString(),
+              address: voucher.address,
+              sender: voucher.sender,
+              recipient: voucher.recipient,
+              balance: voucher.balance.toString(),
+              nonce: voucher.nonce.toString(),
+              expiration: voucher.expiration.toString(),
+              channel_id: voucher.channel_id.toString(),
             },
             timestamp: Number(signedRequest.timestamp),
           };
@@ -48,6 +48,7 @@ export interface PaymentRequirements {
   scheme: PaymentScheme;
   network: string;
   amount: string;
+  maxAmountRequired?: string; // price in token base units
   payTo: `0x${string}`;
   asset: `0x${string}`;
   resource: string;